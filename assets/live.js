@@ -0,0 +1,66 @@
+// Keeps the balances and the activity of the overview up to date. The server
+// sends a change event after every change to the ledger, and the page then
+// fetches itself again and swaps in the new tables. Without this script, or
+// without EventSource, the overview works as before and needs a reload.
+(function () {
+    "use strict";
+
+    if (!window.EventSource || !window.fetch || !window.DOMParser) {
+        return;
+    }
+
+    var ids = ["balances", "recent"];
+    var loading = false;
+    var again = false;
+
+    function refresh() {
+        if (loading) {
+            // Another change came in while loading, load once more afterwards
+            again = true;
+            return;
+        }
+        loading = true;
+
+        fetch(window.location.href, { credentials: "same-origin" })
+            .then(function (res) {
+                if (!res.ok) {
+                    throw new Error(res.status + " " + res.statusText);
+                }
+                return res.text();
+            })
+            .then(function (html) {
+                var page = new DOMParser().parseFromString(html, "text/html");
+                ids.forEach(function (id) {
+                    var current = document.getElementById(id);
+                    var updated = page.getElementById(id);
+                    if (current && updated) {
+                        current.innerHTML = updated.innerHTML;
+                    }
+                });
+            })
+            .catch(function (err) {
+                console.error("Could not update the overview:", err);
+            })
+            .then(function () {
+                loading = false;
+                if (again) {
+                    again = false;
+                    refresh();
+                }
+            });
+    }
+
+    var events = new EventSource("events");
+    var lost = false;
+    events.addEventListener("change", refresh);
+    events.addEventListener("error", function () {
+        lost = true;
+    });
+    events.addEventListener("open", function () {
+        // Changes may have been missed while the connection was lost
+        if (lost) {
+            lost = false;
+            refresh();
+        }
+    });
+})();
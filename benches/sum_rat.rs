@@ -0,0 +1,67 @@
+//! Throughput of `sum_rat` over stored values in the legacy and the current
+//! form, in SQLite. Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::Binary;
+use sharebill::rational::Rational;
+
+const ROWS: u32 = 100_000;
+const RUNS: u32 = 10;
+
+#[derive(QueryableByName)]
+struct Sum {
+    #[diesel(sql_type = Binary)]
+    value: Rational,
+}
+
+/// The form values were stored in before `sharebill::rational::FORMAT_V1`
+fn legacy_bytes(value: &Rational) -> Vec<u8> {
+    let value = value.clone().into_inner();
+    let numer = value.numer().to_bytes_le();
+    let mut bytes = (numer.len() as u32).to_le_bytes().to_vec();
+    bytes.extend_from_slice(&numer);
+    bytes.extend_from_slice(&value.denom().to_bytes_le());
+    bytes
+}
+
+fn bench(conn: &mut SqliteConnection, name: &str, encode: impl Fn(&Rational) -> Vec<u8>) {
+    conn.batch_execute("DROP TABLE IF EXISTS bench; CREATE TABLE bench (x BLOB NOT NULL)")
+        .unwrap();
+    conn.transaction(|conn| {
+        for i in 0..ROWS {
+            // Amounts like 12.34 or 250/3, as entered in practice
+            let value = Rational::new(i % 10_000 + 1, [1u32, 3, 100][i as usize % 3]);
+            sql_query("INSERT INTO bench (x) VALUES (?)")
+                .bind::<Binary, _>(encode(&value))
+                .execute(conn)?;
+        }
+        Ok::<_, diesel::result::Error>(())
+    })
+    .unwrap();
+
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let sum = sql_query("SELECT sum_rat(x) AS value FROM bench")
+            .get_result::<Sum>(conn)
+            .unwrap();
+        best = best.min(start.elapsed());
+        assert!(!sum.value.is_zero());
+    }
+
+    println!(
+        "sum_rat, {name}: {:.0} rows/s",
+        f64::from(ROWS) / best.as_secs_f64()
+    );
+}
+
+fn main() {
+    let mut conn = sharebill::connect::<SqliteConnection>(":memory:");
+
+    bench(&mut conn, "legacy form", legacy_bytes);
+    bench(&mut conn, "current form", Rational::to_bytes);
+}
@@ -0,0 +1,366 @@
+-- The schema of the SQLite migrations up to 2026-10-18-210000, for
+-- PostgreSQL. Later changes to the schema need a migration in both
+-- directories.
+
+-- Rationals are stored as in SQLite: the length of the numerator as four
+-- little-endian bytes, followed by the numerator and the denominator, both
+-- little-endian. These functions do what the application registers on each
+-- SQLite connection.
+
+-- Reads little-endian bytes as an unsigned integer
+CREATE FUNCTION rat_le_to_numeric(bytes BYTEA) RETURNS NUMERIC
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+    result NUMERIC := 0;
+BEGIN
+    FOR i IN REVERSE length(bytes) - 1 .. 0 LOOP
+        result := result * 256 + get_byte(bytes, i);
+    END LOOP;
+    RETURN result;
+END
+$$;
+
+-- Writes an unsigned integer as little-endian bytes, with at least one byte
+CREATE FUNCTION rat_numeric_to_le(n NUMERIC) RETURNS BYTEA
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+    result BYTEA := '';
+BEGIN
+    LOOP
+        result := result || set_byte('\x00'::BYTEA, 0, mod(n, 256)::INTEGER);
+        n := div(n, 256);
+        EXIT WHEN n = 0;
+    END LOOP;
+    RETURN result;
+END
+$$;
+
+CREATE FUNCTION rat_numer(value BYTEA) RETURNS NUMERIC
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT rat_le_to_numeric(substring(
+        value FROM 5 FOR rat_le_to_numeric(substring(value FROM 1 FOR 4))::INTEGER
+    ))
+$$;
+
+CREATE FUNCTION rat_denom(value BYTEA) RETURNS NUMERIC
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT rat_le_to_numeric(substring(
+        value FROM 5 + rat_le_to_numeric(substring(value FROM 1 FOR 4))::INTEGER
+    ))
+$$;
+
+-- The stored form of numer/denom, reduced like the application does
+CREATE FUNCTION rat_from_parts(numer NUMERIC, denom NUMERIC) RETURNS BYTEA
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+    divisor NUMERIC := gcd(numer, denom);
+    numer_bytes BYTEA := rat_numeric_to_le(div(numer, divisor));
+BEGIN
+    RETURN substring(rat_numeric_to_le(length(numer_bytes)) || '\x000000'::BYTEA FROM 1 FOR 4)
+        || numer_bytes
+        || rat_numeric_to_le(div(denom, divisor));
+END
+$$;
+
+CREATE FUNCTION rat_add(x BYTEA, y BYTEA) RETURNS BYTEA
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT rat_from_parts(
+        rat_numer(x) * rat_denom(y) + rat_numer(y) * rat_denom(x),
+        rat_denom(x) * rat_denom(y)
+    )
+$$;
+
+CREATE FUNCTION rat_eq(x BYTEA, y BYTEA) RETURNS BOOLEAN
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT rat_numer(x) * rat_denom(y) = rat_numer(y) * rat_denom(x)
+$$;
+
+-- Like in SQLite, the sum of no rows is zero
+CREATE AGGREGATE sum_rat(BYTEA) (
+    SFUNC = rat_add,
+    STYPE = BYTEA,
+    INITCOND = '\x010000000001'
+);
+
+CREATE TABLE ledgers (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    decimals INTEGER NOT NULL DEFAULT 2 CHECK (decimals BETWEEN 0 AND 6),
+    decimal_separator TEXT NOT NULL DEFAULT '.',
+    thousands_separator TEXT NOT NULL DEFAULT '',
+    currency_symbol TEXT NOT NULL DEFAULT 'kr',
+    currency_before BOOLEAN NOT NULL DEFAULT FALSE,
+    base_currency TEXT NOT NULL DEFAULT 'NOK'
+);
+
+INSERT INTO ledgers (slug, name) VALUES ('default', 'Sharebill');
+
+CREATE TABLE users (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL
+);
+
+CREATE TABLE sessions (
+    token_hash BYTEA PRIMARY KEY,
+    user_id INTEGER REFERENCES users (id) NOT NULL,
+    created_time TIMESTAMP NOT NULL,
+    expiry_time TIMESTAMP NOT NULL
+);
+
+CREATE TABLE ledger_members (
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    user_id INTEGER REFERENCES users (id) NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('viewer', 'member', 'admin')),
+    PRIMARY KEY (ledger_id, user_id)
+);
+
+CREATE TABLE api_tokens (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id INTEGER REFERENCES users (id) NOT NULL,
+    name TEXT NOT NULL,
+    token_hash BYTEA NOT NULL UNIQUE,
+    created_time TIMESTAMP NOT NULL,
+    last_used_time TIMESTAMP
+);
+
+CREATE TABLE api_token_ledgers (
+    token_id INTEGER REFERENCES api_tokens (id) ON DELETE CASCADE NOT NULL,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    access TEXT NOT NULL CHECK (access IN ('read', 'read-write')),
+    PRIMARY KEY (token_id, ledger_id)
+);
+
+-- How many units of the ledger's base currency one unit of `currency` was
+-- worth on `date`
+CREATE TABLE exchange_rates (
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    currency TEXT NOT NULL,
+    date DATE NOT NULL,
+    rate BYTEA NOT NULL,
+    PRIMARY KEY (ledger_id, currency, date)
+);
+
+-- Templates that are turned into ordinary transactions on a schedule
+CREATE TABLE recurring_txs (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    description TEXT NOT NULL,
+    schedule TEXT NOT NULL,
+    start_date DATE NOT NULL,
+    -- Inclusive, NULL repeats forever
+    end_date DATE,
+    -- The latest occurrence that has been turned into a transaction
+    last_date DATE
+);
+
+CREATE TABLE recurring_credits (
+    recurring_tx_id INTEGER REFERENCES recurring_txs (id) NOT NULL,
+    account TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    value BYTEA NOT NULL,
+    PRIMARY KEY (recurring_tx_id, account, currency)
+);
+
+CREATE TABLE recurring_debits (
+    recurring_tx_id INTEGER REFERENCES recurring_txs (id) NOT NULL,
+    account TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    value BYTEA NOT NULL,
+    PRIMARY KEY (recurring_tx_id, account, currency)
+);
+
+-- Closed periods, where posts before the date can no longer be changed
+CREATE TABLE closings (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    closed_before DATE NOT NULL,
+    created_time TIMESTAMP NOT NULL,
+    user_id INTEGER REFERENCES users (id)
+);
+
+CREATE INDEX closings_ledger_id ON closings (ledger_id, closed_before);
+
+CREATE TABLE txs (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    tx_time TIMESTAMP NOT NULL,
+    rev_time TIMESTAMP NOT NULL,
+    description TEXT NOT NULL,
+    rev_user_id INTEGER REFERENCES users (id),
+    recurring_tx_id INTEGER REFERENCES recurring_txs (id),
+    -- Set on the closing and opening posts that carry the balances over a
+    -- closing
+    closing_id INTEGER REFERENCES closings (id)
+);
+
+CREATE INDEX txs_ledger_tx_time ON txs (ledger_id, tx_time);
+
+CREATE TABLE credits (
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    account TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    value BYTEA NOT NULL,
+    PRIMARY KEY (tx_id, account, currency)
+);
+
+CREATE TABLE debits (
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    account TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    value BYTEA NOT NULL,
+    PRIMARY KEY (tx_id, account, currency)
+);
+
+-- Categories of transactions, such as "groceries". A transaction may have any
+-- number of them.
+CREATE TABLE tx_tags (
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (tx_id, tag)
+);
+
+CREATE INDEX tx_tags_tag ON tx_tags (tag);
+
+-- Receipts and other files attached to transactions
+CREATE TABLE attachments (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    filename TEXT NOT NULL,
+    -- Detected from the contents, not taken from the upload
+    content_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    data BYTEA NOT NULL,
+    created_time TIMESTAMP NOT NULL,
+    user_id INTEGER REFERENCES users (id)
+);
+
+CREATE INDEX attachments_tx_id ON attachments (tx_id);
+
+-- The line items of itemized receipts, kept so the post can be edited again.
+-- The payer is the only credit account of the transaction.
+CREATE TABLE receipt_items (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    position INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    value BYTEA NOT NULL,
+    -- Tax, tip and the like, split in proportion to what each person had
+    shared BOOLEAN NOT NULL
+);
+
+CREATE INDEX receipt_items_tx_id ON receipt_items (tx_id);
+
+CREATE TABLE receipt_item_consumers (
+    item_id INTEGER REFERENCES receipt_items (id) NOT NULL,
+    account TEXT NOT NULL,
+    PRIMARY KEY (item_id, account)
+);
+
+-- Soft limits on what is spent on an account or tag per week, month or year
+CREATE TABLE budgets (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    target TEXT NOT NULL CHECK (target IN ('account', 'tag')),
+    -- The account or tag
+    name TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    amount BYTEA NOT NULL,
+    period TEXT NOT NULL CHECK (period IN ('week', 'month', 'year')),
+    UNIQUE (ledger_id, target, name, currency)
+);
+
+-- Append-only log of every change, written in the same database transaction
+-- as the change itself
+CREATE TABLE audit_log (
+    id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    time TIMESTAMP NOT NULL,
+    -- NULL for changes outside of ledgers, such as to users and API tokens
+    ledger_id INTEGER REFERENCES ledgers (id),
+    -- NULL for changes from the command line and the scheduler
+    user_id INTEGER REFERENCES users (id),
+    source TEXT NOT NULL CHECK (source IN ('web', 'cli', 'api', 'importer', 'scheduler')),
+    action TEXT NOT NULL CHECK (action IN ('create', 'edit', 'delete', 'import', 'close')),
+    -- What was changed, such as post or budget
+    object TEXT NOT NULL,
+    -- JSON describing the change
+    payload TEXT NOT NULL
+);
+
+CREATE INDEX audit_log_ledger_id ON audit_log (ledger_id, time);
+
+CREATE FUNCTION audit_log_append_only() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+    RAISE EXCEPTION 'the audit log is append-only';
+END
+$$;
+
+CREATE TRIGGER audit_log_append_only BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+-- Every transaction must have postings that balance in each currency. The
+-- checks are deferred to the commit, so the postings can be written one by
+-- one.
+CREATE FUNCTION check_tx_balanced(checked_id INTEGER) RETURNS VOID
+LANGUAGE plpgsql AS $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM txs WHERE id = checked_id) THEN
+        RETURN;
+    END IF;
+    IF NOT EXISTS (
+        SELECT 1 FROM credits WHERE tx_id = checked_id
+        UNION ALL
+        SELECT 1 FROM debits WHERE tx_id = checked_id
+    ) THEN
+        RAISE EXCEPTION 'transaction % has no credits or debits', checked_id
+            USING ERRCODE = 'check_violation';
+    END IF;
+    IF EXISTS (
+        SELECT 1 FROM (
+            SELECT currency FROM credits WHERE tx_id = checked_id
+            UNION
+            SELECT currency FROM debits WHERE tx_id = checked_id
+        ) AS c
+        WHERE NOT rat_eq(
+            (SELECT sum_rat(value) FROM credits
+             WHERE tx_id = checked_id AND currency = c.currency),
+            (SELECT sum_rat(value) FROM debits
+             WHERE tx_id = checked_id AND currency = c.currency)
+        )
+    ) THEN
+        RAISE EXCEPTION 'transaction % does not balance', checked_id
+            USING ERRCODE = 'check_violation';
+    END IF;
+END
+$$;
+
+CREATE FUNCTION check_tx_balance() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_TABLE_NAME = 'txs' THEN
+        PERFORM check_tx_balanced(NEW.id);
+        RETURN NULL;
+    END IF;
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        PERFORM check_tx_balanced(OLD.tx_id);
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        PERFORM check_tx_balanced(NEW.tx_id);
+    END IF;
+    RETURN NULL;
+END
+$$;
+
+CREATE CONSTRAINT TRIGGER txs_balanced AFTER INSERT ON txs
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW EXECUTE FUNCTION check_tx_balance();
+
+CREATE CONSTRAINT TRIGGER credits_balanced AFTER INSERT OR UPDATE OR DELETE ON credits
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW EXECUTE FUNCTION check_tx_balance();
+
+CREATE CONSTRAINT TRIGGER debits_balanced AFTER INSERT OR UPDATE OR DELETE ON debits
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW EXECUTE FUNCTION check_tx_balance();
@@ -0,0 +1,100 @@
+-- Rationals are now stored as the byte F1, followed by the numerator and the
+-- denominator as unsigned LEB128, in lowest terms. Values in the old form are
+-- still read, and are rewritten here.
+
+-- Reads an unsigned LEB128 number from the bytes, starting at the zero-based
+-- offset, along with the offset after it
+CREATE FUNCTION rat_read_leb128(bytes BYTEA, start INTEGER, OUT n NUMERIC, OUT next INTEGER)
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+    digit INTEGER;
+    scale NUMERIC := 1;
+BEGIN
+    n := 0;
+    next := start;
+    LOOP
+        digit := get_byte(bytes, next);
+        n := n + (digit & 127) * scale;
+        scale := scale * 128;
+        next := next + 1;
+        EXIT WHEN digit < 128;
+    END LOOP;
+END
+$$;
+
+-- Writes an unsigned integer as LEB128
+CREATE FUNCTION rat_numeric_to_leb128(n NUMERIC) RETURNS BYTEA
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+    result BYTEA := '';
+    digit INTEGER;
+BEGIN
+    LOOP
+        digit := mod(n, 128)::INTEGER;
+        n := div(n, 128);
+        IF n > 0 THEN
+            digit := digit | 128;
+        END IF;
+        result := result || set_byte('\x00'::BYTEA, 0, digit);
+        EXIT WHEN n = 0;
+    END LOOP;
+    RETURN result;
+END
+$$;
+
+CREATE OR REPLACE FUNCTION rat_numer(value BYTEA) RETURNS NUMERIC
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+BEGIN
+    IF get_byte(value, 0) = 241 THEN
+        RETURN (rat_read_leb128(value, 1)).n;
+    END IF;
+    RETURN rat_le_to_numeric(substring(
+        value FROM 5 FOR rat_le_to_numeric(substring(value FROM 1 FOR 4))::INTEGER
+    ));
+END
+$$;
+
+CREATE OR REPLACE FUNCTION rat_denom(value BYTEA) RETURNS NUMERIC
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+BEGIN
+    IF get_byte(value, 0) = 241 THEN
+        RETURN (rat_read_leb128(value, (rat_read_leb128(value, 1)).next)).n;
+    END IF;
+    RETURN rat_le_to_numeric(substring(
+        value FROM 5 + rat_le_to_numeric(substring(value FROM 1 FOR 4))::INTEGER
+    ));
+END
+$$;
+
+CREATE OR REPLACE FUNCTION rat_from_parts(numer NUMERIC, denom NUMERIC) RETURNS BYTEA
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+    divisor NUMERIC := gcd(numer, denom);
+BEGIN
+    RETURN '\xf1'::BYTEA
+        || rat_numeric_to_leb128(div(numer, divisor))
+        || rat_numeric_to_leb128(div(denom, divisor));
+END
+$$;
+
+-- The sum of no rows is zero in the new form
+DROP AGGREGATE sum_rat(BYTEA);
+
+CREATE AGGREGATE sum_rat(BYTEA) (
+    SFUNC = rat_add,
+    STYPE = BYTEA,
+    INITCOND = '\xf10001'
+);
+
+CREATE FUNCTION rat_canonical(value BYTEA) RETURNS BYTEA
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT rat_from_parts(rat_numer(value), rat_denom(value))
+$$;
+
+UPDATE budgets SET amount = rat_canonical(amount);
+UPDATE credits SET value = rat_canonical(value);
+UPDATE debits SET value = rat_canonical(value);
+UPDATE exchange_rates SET rate = rat_canonical(rate);
+UPDATE receipt_items SET value = rat_canonical(value);
+UPDATE recurring_credits SET value = rat_canonical(value);
+UPDATE recurring_debits SET value = rat_canonical(value);
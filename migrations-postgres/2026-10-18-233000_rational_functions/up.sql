@@ -0,0 +1,51 @@
+-- More functions on rationals, which the application registers on each
+-- SQLite connection
+
+CREATE FUNCTION rat_cmp(x BYTEA, y BYTEA) RETURNS INTEGER
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT sign(rat_numer(x) * rat_denom(y) - rat_numer(y) * rat_denom(x))::INTEGER
+$$;
+
+-- NULL when y is larger, as rationals are never negative
+CREATE FUNCTION rat_sub(x BYTEA, y BYTEA) RETURNS BYTEA
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT CASE WHEN rat_cmp(x, y) >= 0 THEN rat_from_parts(
+        rat_numer(x) * rat_denom(y) - rat_numer(y) * rat_denom(x),
+        rat_denom(x) * rat_denom(y)
+    ) END
+$$;
+
+CREATE FUNCTION rat_to_text(x BYTEA) RETURNS TEXT
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT CASE
+        WHEN rat_denom(x) = 1 THEN rat_numer(x)::TEXT
+        ELSE rat_numer(x)::TEXT || '/' || rat_denom(x)::TEXT
+    END
+$$;
+
+CREATE FUNCTION rat_to_real(x BYTEA) RETURNS DOUBLE PRECISION
+LANGUAGE sql IMMUTABLE STRICT AS $$
+    SELECT (rat_numer(x) / rat_denom(x))::DOUBLE PRECISION
+$$;
+
+-- Integers, decimals with "." or "," and fractions, or NULL
+CREATE FUNCTION rat_from_text(x TEXT) RETURNS BYTEA
+LANGUAGE plpgsql IMMUTABLE STRICT AS $$
+DECLARE
+    s TEXT := replace(btrim(x), ',', '.');
+BEGIN
+    IF s ~ '^[0-9]+/[0-9]+$' THEN
+        IF split_part(s, '/', 2)::NUMERIC = 0 THEN
+            RETURN NULL;
+        END IF;
+        RETURN rat_from_parts(split_part(s, '/', 1)::NUMERIC, split_part(s, '/', 2)::NUMERIC);
+    END IF;
+    IF s ~ '^([0-9]+\.?[0-9]*|\.[0-9]+)$' THEN
+        RETURN rat_from_parts(
+            replace(s, '.', '')::NUMERIC,
+            ('1' || repeat('0', length(split_part(s, '.', 2))))::NUMERIC
+        );
+    END IF;
+    RETURN NULL;
+END
+$$;
@@ -0,0 +1,31 @@
+PRAGMA foreign_keys = OFF;
+
+BEGIN;
+
+CREATE TABLE ledgers (
+    id INTEGER PRIMARY KEY NOT NULL,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL
+) STRICT;
+
+INSERT INTO ledgers (id, slug, name) VALUES (1, 'default', 'Sharebill');
+
+CREATE TABLE new_txs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    tx_time TEXT NOT NULL,
+    rev_time TEXT NOT NULL,
+    description TEXT NOT NULL
+) STRICT;
+
+INSERT INTO new_txs (id, ledger_id, tx_time, rev_time, description)
+    SELECT id, 1, tx_time, rev_time, description FROM txs;
+
+DROP TABLE txs;
+ALTER TABLE new_txs RENAME TO txs;
+
+CREATE INDEX txs_ledger_tx_time ON txs (ledger_id, tx_time);
+
+COMMIT;
+
+PRAGMA foreign_keys = ON;
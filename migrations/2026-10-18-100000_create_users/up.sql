@@ -0,0 +1,21 @@
+CREATE TABLE users (
+    id INTEGER PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL
+) STRICT;
+
+CREATE TABLE sessions (
+    token_hash BLOB PRIMARY KEY NOT NULL,
+    user_id INTEGER REFERENCES users (id) NOT NULL,
+    created_time TEXT NOT NULL,
+    expiry_time TEXT NOT NULL
+) STRICT;
+
+CREATE TABLE ledger_members (
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    user_id INTEGER REFERENCES users (id) NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('viewer', 'member', 'admin')),
+    PRIMARY KEY (ledger_id, user_id)
+) STRICT;
+
+ALTER TABLE txs ADD COLUMN rev_user_id INTEGER REFERENCES users (id);
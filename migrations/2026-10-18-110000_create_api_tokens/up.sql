@@ -0,0 +1,15 @@
+CREATE TABLE api_tokens (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user_id INTEGER REFERENCES users (id) NOT NULL,
+    name TEXT NOT NULL,
+    token_hash BLOB NOT NULL UNIQUE,
+    created_time TEXT NOT NULL,
+    last_used_time TEXT
+) STRICT;
+
+CREATE TABLE api_token_ledgers (
+    token_id INTEGER REFERENCES api_tokens (id) ON DELETE CASCADE NOT NULL,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    access TEXT NOT NULL CHECK (access IN ('read', 'read-write')),
+    PRIMARY KEY (token_id, ledger_id)
+) STRICT;
@@ -0,0 +1,5 @@
+ALTER TABLE ledgers ADD COLUMN decimals INTEGER NOT NULL DEFAULT 2 CHECK (decimals BETWEEN 0 AND 6);
+ALTER TABLE ledgers ADD COLUMN decimal_separator TEXT NOT NULL DEFAULT '.';
+ALTER TABLE ledgers ADD COLUMN thousands_separator TEXT NOT NULL DEFAULT '';
+ALTER TABLE ledgers ADD COLUMN currency_symbol TEXT NOT NULL DEFAULT 'kr';
+ALTER TABLE ledgers ADD COLUMN currency_before INTEGER NOT NULL DEFAULT 0 CHECK (currency_before IN (0, 1));
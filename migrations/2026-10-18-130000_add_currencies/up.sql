@@ -0,0 +1,47 @@
+ALTER TABLE ledgers ADD COLUMN base_currency TEXT NOT NULL DEFAULT 'NOK';
+
+-- Existing postings are in the base currency of their ledger
+
+CREATE TABLE new_credits (
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    account TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    value BLOB NOT NULL,
+    PRIMARY KEY (tx_id, account, currency)
+) STRICT;
+
+INSERT INTO new_credits (tx_id, account, currency, value)
+    SELECT credits.tx_id, credits.account, ledgers.base_currency, credits.value
+    FROM credits
+    JOIN txs ON txs.id = credits.tx_id
+    JOIN ledgers ON ledgers.id = txs.ledger_id;
+
+DROP TABLE credits;
+ALTER TABLE new_credits RENAME TO credits;
+
+CREATE TABLE new_debits (
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    account TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    value BLOB NOT NULL,
+    PRIMARY KEY (tx_id, account, currency)
+) STRICT;
+
+INSERT INTO new_debits (tx_id, account, currency, value)
+    SELECT debits.tx_id, debits.account, ledgers.base_currency, debits.value
+    FROM debits
+    JOIN txs ON txs.id = debits.tx_id
+    JOIN ledgers ON ledgers.id = txs.ledger_id;
+
+DROP TABLE debits;
+ALTER TABLE new_debits RENAME TO debits;
+
+-- How many units of the ledger's base currency one unit of `currency` was
+-- worth on `date`
+CREATE TABLE exchange_rates (
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    currency TEXT NOT NULL,
+    date TEXT NOT NULL,
+    rate BLOB NOT NULL,
+    PRIMARY KEY (ledger_id, currency, date)
+) STRICT;
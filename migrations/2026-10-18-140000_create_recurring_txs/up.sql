@@ -0,0 +1,30 @@
+-- Templates that are turned into ordinary transactions on a schedule
+CREATE TABLE recurring_txs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    description TEXT NOT NULL,
+    schedule TEXT NOT NULL,
+    start_date TEXT NOT NULL,
+    -- Inclusive, NULL repeats forever
+    end_date TEXT,
+    -- The latest occurrence that has been turned into a transaction
+    last_date TEXT
+) STRICT;
+
+CREATE TABLE recurring_credits (
+    recurring_tx_id INTEGER REFERENCES recurring_txs (id) NOT NULL,
+    account TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    value BLOB NOT NULL,
+    PRIMARY KEY (recurring_tx_id, account, currency)
+) STRICT;
+
+CREATE TABLE recurring_debits (
+    recurring_tx_id INTEGER REFERENCES recurring_txs (id) NOT NULL,
+    account TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    value BLOB NOT NULL,
+    PRIMARY KEY (recurring_tx_id, account, currency)
+) STRICT;
+
+ALTER TABLE txs ADD COLUMN recurring_tx_id INTEGER REFERENCES recurring_txs (id);
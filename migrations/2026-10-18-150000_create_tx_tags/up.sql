@@ -0,0 +1,9 @@
+-- Categories of transactions, such as "groceries". A transaction may have any
+-- number of them.
+CREATE TABLE tx_tags (
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (tx_id, tag)
+) STRICT;
+
+CREATE INDEX tx_tags_tag ON tx_tags (tag);
@@ -0,0 +1,14 @@
+-- Receipts and other files attached to transactions
+CREATE TABLE attachments (
+    id INTEGER PRIMARY KEY NOT NULL,
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    filename TEXT NOT NULL,
+    -- Detected from the contents, not taken from the upload
+    content_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    created_time TEXT NOT NULL,
+    user_id INTEGER REFERENCES users (id)
+) STRICT;
+
+CREATE INDEX attachments_tx_id ON attachments (tx_id);
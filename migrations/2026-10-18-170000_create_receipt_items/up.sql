@@ -0,0 +1,19 @@
+-- The line items of itemized receipts, kept so the post can be edited again.
+-- The payer is the only credit account of the transaction.
+CREATE TABLE receipt_items (
+    id INTEGER PRIMARY KEY NOT NULL,
+    tx_id INTEGER REFERENCES txs (id) NOT NULL,
+    position INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    value BLOB NOT NULL,
+    -- Tax, tip and the like, split in proportion to what each person had
+    shared INTEGER NOT NULL CHECK (shared IN (0, 1))
+) STRICT;
+
+CREATE INDEX receipt_items_tx_id ON receipt_items (tx_id);
+
+CREATE TABLE receipt_item_consumers (
+    item_id INTEGER REFERENCES receipt_items (id) NOT NULL,
+    account TEXT NOT NULL,
+    PRIMARY KEY (item_id, account)
+) STRICT;
@@ -0,0 +1,12 @@
+-- Soft limits on what is spent on an account or tag per week, month or year
+CREATE TABLE budgets (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    target TEXT NOT NULL CHECK (target IN ('account', 'tag')),
+    -- The account or tag
+    name TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    amount BLOB NOT NULL,
+    period TEXT NOT NULL CHECK (period IN ('week', 'month', 'year')),
+    UNIQUE (ledger_id, target, name, currency)
+) STRICT;
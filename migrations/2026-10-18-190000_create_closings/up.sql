@@ -0,0 +1,13 @@
+-- Closed periods, where posts before the date can no longer be changed
+CREATE TABLE closings (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    closed_before TEXT NOT NULL,
+    created_time TEXT NOT NULL,
+    user_id INTEGER REFERENCES users (id)
+) STRICT;
+
+CREATE INDEX closings_ledger_id ON closings (ledger_id, closed_before);
+
+-- Set on the closing and opening posts that carry the balances over a closing
+ALTER TABLE txs ADD COLUMN closing_id INTEGER REFERENCES closings (id);
@@ -0,0 +1,28 @@
+-- Append-only log of every change, written in the same database transaction
+-- as the change itself
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY NOT NULL,
+    time TEXT NOT NULL,
+    -- NULL for changes outside of ledgers, such as to users and API tokens
+    ledger_id INTEGER REFERENCES ledgers (id),
+    -- NULL for changes from the command line and the scheduler
+    user_id INTEGER REFERENCES users (id),
+    source TEXT NOT NULL CHECK (source IN ('web', 'cli', 'api', 'importer', 'scheduler')),
+    action TEXT NOT NULL CHECK (action IN ('create', 'edit', 'delete', 'import', 'close')),
+    -- What was changed, such as post or budget
+    object TEXT NOT NULL,
+    -- JSON describing the change
+    payload TEXT NOT NULL
+) STRICT;
+
+CREATE INDEX audit_log_ledger_id ON audit_log (ledger_id, time);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
@@ -0,0 +1,87 @@
+-- SQLite has no deferred CHECK constraints, so every transaction whose
+-- postings are empty or do not balance in each currency gets a row in
+-- unbalanced_txs instead. The rows refer to the always empty
+-- no_unbalanced_txs table through a deferred foreign key, which makes the
+-- commit fail while any of them remain. Transactions that were already
+-- unbalanced are left alone until they are changed.
+CREATE TABLE no_unbalanced_txs (
+    id INTEGER PRIMARY KEY NOT NULL
+) STRICT;
+
+CREATE TABLE unbalanced_txs (
+    tx_id INTEGER PRIMARY KEY NOT NULL
+        REFERENCES no_unbalanced_txs (id) DEFERRABLE INITIALLY DEFERRED
+) STRICT;
+
+-- Inserting a tx_id here checks that transaction again. The rat_eq and
+-- sum_rat functions are registered by the application on each connection.
+CREATE VIEW check_tx_balance AS SELECT NULL AS tx_id;
+
+CREATE TRIGGER check_tx_balance INSTEAD OF INSERT ON check_tx_balance
+BEGIN
+    DELETE FROM unbalanced_txs WHERE tx_id = NEW.tx_id;
+    INSERT INTO unbalanced_txs (tx_id)
+    SELECT id FROM txs
+    WHERE id = NEW.tx_id AND (
+        NOT EXISTS (
+            SELECT 1 FROM credits WHERE tx_id = NEW.tx_id
+            UNION ALL
+            SELECT 1 FROM debits WHERE tx_id = NEW.tx_id
+        )
+        OR EXISTS (
+            SELECT 1 FROM (
+                SELECT currency FROM credits WHERE tx_id = NEW.tx_id
+                UNION
+                SELECT currency FROM debits WHERE tx_id = NEW.tx_id
+            ) AS c
+            WHERE NOT rat_eq(
+                (SELECT sum_rat(value) FROM credits
+                 WHERE tx_id = NEW.tx_id AND currency = c.currency),
+                (SELECT sum_rat(value) FROM debits
+                 WHERE tx_id = NEW.tx_id AND currency = c.currency)
+            )
+        )
+    );
+END;
+
+CREATE TRIGGER txs_check_insert AFTER INSERT ON txs
+BEGIN
+    INSERT INTO check_tx_balance (tx_id) VALUES (NEW.id);
+END;
+
+CREATE TRIGGER txs_check_delete AFTER DELETE ON txs
+BEGIN
+    INSERT INTO check_tx_balance (tx_id) VALUES (OLD.id);
+END;
+
+CREATE TRIGGER credits_check_insert AFTER INSERT ON credits
+BEGIN
+    INSERT INTO check_tx_balance (tx_id) VALUES (NEW.tx_id);
+END;
+
+CREATE TRIGGER credits_check_update AFTER UPDATE ON credits
+BEGIN
+    INSERT INTO check_tx_balance (tx_id) VALUES (OLD.tx_id);
+    INSERT INTO check_tx_balance (tx_id) VALUES (NEW.tx_id);
+END;
+
+CREATE TRIGGER credits_check_delete AFTER DELETE ON credits
+BEGIN
+    INSERT INTO check_tx_balance (tx_id) VALUES (OLD.tx_id);
+END;
+
+CREATE TRIGGER debits_check_insert AFTER INSERT ON debits
+BEGIN
+    INSERT INTO check_tx_balance (tx_id) VALUES (NEW.tx_id);
+END;
+
+CREATE TRIGGER debits_check_update AFTER UPDATE ON debits
+BEGIN
+    INSERT INTO check_tx_balance (tx_id) VALUES (OLD.tx_id);
+    INSERT INTO check_tx_balance (tx_id) VALUES (NEW.tx_id);
+END;
+
+CREATE TRIGGER debits_check_delete AFTER DELETE ON debits
+BEGIN
+    INSERT INTO check_tx_balance (tx_id) VALUES (OLD.tx_id);
+END;
@@ -0,0 +1,39 @@
+-- Rewrites every stored rational in the versioned, canonical form of
+-- Rational::to_bytes.
+--
+-- This depends on the rat_canonical, rat_eq and sum_rat functions, which
+-- are registered by the application on each connection and not by the
+-- diesel CLI. Run it by starting the application, or any of its tools,
+-- which runs the pending migrations after registering them.
+
+-- Posts that were already unbalanced before
+-- 2026-10-18-210000_check_balanced_txs. Rewriting their postings would check
+-- them again and fail the migration, so their values are left in the old
+-- form, which is still read. fsck reports both the posts and the values.
+CREATE TEMP TABLE unbalanced_before AS
+SELECT txs.id AS tx_id FROM txs
+WHERE EXISTS (
+    SELECT 1 FROM (
+        SELECT currency FROM credits WHERE tx_id = txs.id
+        UNION
+        SELECT currency FROM debits WHERE tx_id = txs.id
+    ) AS c
+    WHERE NOT rat_eq(
+        (SELECT sum_rat(value) FROM credits
+         WHERE tx_id = txs.id AND currency = c.currency),
+        (SELECT sum_rat(value) FROM debits
+         WHERE tx_id = txs.id AND currency = c.currency)
+    )
+);
+
+UPDATE budgets SET amount = rat_canonical(amount);
+UPDATE credits SET value = rat_canonical(value)
+WHERE tx_id NOT IN (SELECT tx_id FROM unbalanced_before);
+UPDATE debits SET value = rat_canonical(value)
+WHERE tx_id NOT IN (SELECT tx_id FROM unbalanced_before);
+UPDATE exchange_rates SET rate = rat_canonical(rate);
+UPDATE receipt_items SET value = rat_canonical(value);
+UPDATE recurring_credits SET value = rat_canonical(value);
+UPDATE recurring_debits SET value = rat_canonical(value);
+
+DROP TABLE unbalanced_before;
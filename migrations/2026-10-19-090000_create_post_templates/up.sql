@@ -0,0 +1,23 @@
+-- Saved splits for posts that are entered again and again. The shares are
+-- ratios, which are scaled to the total of each post.
+CREATE TABLE post_templates (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ledger_id INTEGER REFERENCES ledgers (id) NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    UNIQUE (ledger_id, name)
+) STRICT;
+
+CREATE TABLE post_template_credits (
+    post_template_id INTEGER REFERENCES post_templates (id) NOT NULL,
+    account TEXT NOT NULL,
+    share BLOB NOT NULL,
+    PRIMARY KEY (post_template_id, account)
+) STRICT;
+
+CREATE TABLE post_template_debits (
+    post_template_id INTEGER REFERENCES post_templates (id) NOT NULL,
+    account TEXT NOT NULL,
+    share BLOB NOT NULL,
+    PRIMARY KEY (post_template_id, account)
+) STRICT;
@@ -0,0 +1,310 @@
+//! Parsing of amounts as people type them. Accepts integers, decimals with
+//! either "." or "," as the decimal separator, fractions, mixed numbers such as
+//! "3 1/2" and arithmetic with `+`, `-`, `*`, `/` and parentheses, such as
+//! "120/3 + 15".
+
+use num::{BigInt, BigRational, BigUint, Signed, Zero};
+use thiserror::Error;
+
+use crate::rational::Rational;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ParseAmountError {
+    #[error("invalid amount")]
+    Invalid,
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("amounts cannot be negative")]
+    Negative,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(BigRational),
+    Plus,
+    Minus,
+    Times,
+    Divide,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseAmountError> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '0'..='9' | '.' | ',' => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_digit() || c == '.' || c == ',') {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                Token::Number(parse_decimal(&number)?)
+            }
+            '+' => Token::Plus,
+            '-' | '−' => Token::Minus,
+            '*' | '×' => Token::Times,
+            '/' | '÷' => Token::Divide,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            _ => return Err(ParseAmountError::Invalid),
+        };
+        if !matches!(token, Token::Number(_)) {
+            chars.next();
+        }
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn parse_decimal(number: &str) -> Result<BigRational, ParseAmountError> {
+    let (int, frac) = match number.split_once(['.', ',']) {
+        Some((int, frac)) => (int, frac),
+        None => (number, ""),
+    };
+    if (int.is_empty() && frac.is_empty()) || frac.contains(['.', ',']) {
+        return Err(ParseAmountError::Invalid);
+    }
+
+    let digits = format!("{int}{frac}");
+    let numer = digits
+        .parse::<BigInt>()
+        .map_err(|_| ParseAmountError::Invalid)?;
+    let denom = num::pow(BigInt::from(10), frac.len());
+
+    Ok(BigRational::new(numer, denom))
+}
+
+/// Recursive descent parser over the tokens, with the usual precedence
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<BigRational, ParseAmountError> {
+        let mut value = self.term()?;
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.next();
+                    value += self.term()?;
+                }
+                Some(Token::Minus) => {
+                    self.next();
+                    value -= self.term()?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<BigRational, ParseAmountError> {
+        let mut value = self.factor()?;
+        loop {
+            match self.peek() {
+                Some(Token::Times) => {
+                    self.next();
+                    value *= self.factor()?;
+                }
+                Some(Token::Divide) => {
+                    self.next();
+                    let divisor = self.factor()?;
+                    if divisor.is_zero() {
+                        return Err(ParseAmountError::DivisionByZero);
+                    }
+                    value /= divisor;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn factor(&mut self) -> Result<BigRational, ParseAmountError> {
+        match self.next() {
+            Some(Token::Minus) => Ok(-self.factor()?),
+            Some(Token::Open) => {
+                let value = self.expr()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err(ParseAmountError::Invalid),
+                }
+            }
+            Some(Token::Number(whole)) => match self.tokens.get(self.pos..self.pos + 3) {
+                // A number directly followed by a fraction is a mixed number
+                Some([Token::Number(numer), Token::Divide, Token::Number(denom)])
+                    if whole.is_integer() && numer.is_integer() && denom.is_integer() =>
+                {
+                    if denom.is_zero() {
+                        return Err(ParseAmountError::DivisionByZero);
+                    }
+                    let fraction = numer / denom;
+                    self.pos += 3;
+                    Ok(whole + fraction)
+                }
+                _ => Ok(whole),
+            },
+            _ => Err(ParseAmountError::Invalid),
+        }
+    }
+}
+
+/// Evaluates an amount expression, which may come out negative
+pub fn evaluate(input: &str) -> Result<BigRational, ParseAmountError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let value = parser.expr()?;
+    if parser.pos != parser.tokens.len() {
+        return Err(ParseAmountError::Invalid);
+    }
+    Ok(value)
+}
+
+/// Parses an amount, which must not be negative.
+///
+/// # Examples
+///
+/// ```
+/// # use sharebill::{amount::parse_amount, rational::Rational};
+/// assert_eq!(parse_amount("12"), Ok(Rational::new(12u32, 1u32)));
+/// assert_eq!(parse_amount("12.50"), Ok(Rational::new(25u32, 2u32)));
+/// assert_eq!(parse_amount("12,50"), Ok(Rational::new(25u32, 2u32)));
+/// assert_eq!(parse_amount("3 1/2"), Ok(Rational::new(7u32, 2u32)));
+/// assert_eq!(parse_amount("120/3 + 15"), Ok(Rational::new(55u32, 1u32)));
+/// assert!(parse_amount("-5").is_err());
+/// ```
+pub fn parse_amount(input: &str) -> Result<Rational, ParseAmountError> {
+    let value = evaluate(input)?;
+    if value.is_negative() {
+        return Err(ParseAmountError::Negative);
+    }
+
+    let numer = value
+        .numer()
+        .to_biguint()
+        .ok_or(ParseAmountError::Negative)?;
+    let denom = value
+        .denom()
+        .to_biguint()
+        .ok_or(ParseAmountError::Negative)?;
+    Ok(Rational::new(numer, denom))
+}
+
+/// Parses a plain number: an integer, a decimal with either "." or "," as the
+/// decimal separator, or a fraction such as "25/2". Unlike [`parse_amount`],
+/// it reads no signs, mixed numbers or arithmetic. This is what the
+/// `rat_from_text` SQL function reads on both databases.
+///
+/// # Examples
+///
+/// ```
+/// # use sharebill::{amount::parse_number, rational::Rational};
+/// assert_eq!(parse_number("12,50"), Ok(Rational::new(25u32, 2u32)));
+/// assert_eq!(parse_number("25/2"), Ok(Rational::new(25u32, 2u32)));
+/// assert!(parse_number("3 1/2").is_err());
+/// assert!(parse_number("120/3 + 15").is_err());
+/// ```
+pub fn parse_number(input: &str) -> Result<Rational, ParseAmountError> {
+    let input = input.trim_matches(' ');
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    match input.split_once('/') {
+        Some((numer, denom)) if is_digits(numer) && is_digits(denom) => {
+            let numer = numer
+                .parse::<BigUint>()
+                .map_err(|_| ParseAmountError::Invalid)?;
+            let denom = denom
+                .parse::<BigUint>()
+                .map_err(|_| ParseAmountError::Invalid)?;
+            if denom.is_zero() {
+                return Err(ParseAmountError::DivisionByZero);
+            }
+            Ok(Rational::new(numer, denom))
+        }
+        None if input
+            .bytes()
+            .all(|b| b.is_ascii_digit() || b == b'.' || b == b',') =>
+        {
+            let value = parse_decimal(input)?;
+            let numer = value
+                .numer()
+                .to_biguint()
+                .ok_or(ParseAmountError::Invalid)?;
+            let denom = value
+                .denom()
+                .to_biguint()
+                .ok_or(ParseAmountError::Invalid)?;
+            Ok(Rational::new(numer, denom))
+        }
+        _ => Err(ParseAmountError::Invalid),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn r(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(evaluate("0"), Ok(r(0, 1)));
+        assert_eq!(evaluate(" 42 "), Ok(r(42, 1)));
+        assert_eq!(evaluate("0.125"), Ok(r(1, 8)));
+        assert_eq!(evaluate(".5"), Ok(r(1, 2)));
+        assert_eq!(evaluate("5."), Ok(r(5, 1)));
+        assert_eq!(evaluate("25/2"), Ok(r(25, 2)));
+        assert_eq!(evaluate("-3 1/2"), Ok(r(-7, 2)));
+        assert_eq!(evaluate("12 1/3"), Ok(r(37, 3)));
+        assert_eq!(evaluate("1.234,5"), Err(ParseAmountError::Invalid));
+        assert_eq!(evaluate("."), Err(ParseAmountError::Invalid));
+        assert_eq!(evaluate(""), Err(ParseAmountError::Invalid));
+        assert_eq!(evaluate("12 kr"), Err(ParseAmountError::Invalid));
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(r(7, 1)));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(r(9, 1)));
+        assert_eq!(evaluate("10 - 2 - 3"), Ok(r(5, 1)));
+        assert_eq!(evaluate("12 / 4 / 3"), Ok(r(1, 1)));
+        assert_eq!(evaluate("100/3"), Ok(r(100, 3)));
+        assert_eq!(evaluate("2 1/2 * 2"), Ok(r(5, 1)));
+        assert_eq!(evaluate("19,90 * 3"), Ok(r(597, 10)));
+        assert_eq!(evaluate("1 / 0"), Err(ParseAmountError::DivisionByZero));
+        assert_eq!(evaluate("(1 + 2"), Err(ParseAmountError::Invalid));
+        assert_eq!(evaluate("1 2"), Err(ParseAmountError::Invalid));
+        assert_eq!(evaluate("1 +"), Err(ParseAmountError::Invalid));
+    }
+
+    #[test]
+    fn amounts_are_not_negative() {
+        assert_eq!(parse_amount("5 - 2"), Ok(Rational::new(3u32, 1u32)));
+        assert_eq!(parse_amount("2 - 5"), Err(ParseAmountError::Negative));
+        assert_eq!(parse_amount("-0"), Ok(Rational::new(0u32, 1u32)));
+    }
+}
@@ -0,0 +1,208 @@
+//! Receipts and other files attached to transactions. The files are stored as
+//! blobs in the database, so they are part of every backup.
+
+use diesel::prelude::*;
+use thiserror::Error;
+
+use crate::models::{Attachment, NewAttachment};
+use crate::schema::attachments;
+use crate::DbConnection;
+
+pub const MAX_ATTACHMENT_SIZE: usize = 5 * 1024 * 1024;
+pub const MAX_FILENAME_LENGTH: usize = 100;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AttachmentError {
+    #[error("attachments cannot be larger than 5 MiB")]
+    TooLarge,
+    #[error("the file is empty")]
+    Empty,
+    #[error("only JPEG, PNG, GIF and WebP images and PDF documents can be attached")]
+    UnsupportedType,
+}
+
+/// The columns of [`Attachment`], which leave out the contents
+pub type AttachmentColumns = (
+    attachments::id,
+    attachments::tx_id,
+    attachments::filename,
+    attachments::content_type,
+    attachments::size,
+    attachments::created_time,
+    attachments::user_id,
+);
+
+pub const ATTACHMENT_COLUMNS: AttachmentColumns = (
+    attachments::id,
+    attachments::tx_id,
+    attachments::filename,
+    attachments::content_type,
+    attachments::size,
+    attachments::created_time,
+    attachments::user_id,
+);
+
+/// Detects the type of a file from its first bytes. Browsers may send any
+/// content type, so the one from the upload is not trusted.
+///
+/// # Examples
+///
+/// ```
+/// # use sharebill::attachments::detect_content_type;
+/// assert_eq!(detect_content_type(b"%PDF-1.7\n..."), Some("application/pdf"));
+/// assert_eq!(detect_content_type(b"<html>"), None);
+/// ```
+pub fn detect_content_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if data.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else {
+        None
+    }
+}
+
+/// Checks the size and type of a file, returning its content type
+pub fn check_attachment(data: &[u8]) -> Result<&'static str, AttachmentError> {
+    if data.is_empty() {
+        return Err(AttachmentError::Empty);
+    }
+    if data.len() > MAX_ATTACHMENT_SIZE {
+        return Err(AttachmentError::TooLarge);
+    }
+    detect_content_type(data).ok_or(AttachmentError::UnsupportedType)
+}
+
+/// The last component of an uploaded file name without control characters,
+/// or "attachment" if nothing is left
+///
+/// # Examples
+///
+/// ```
+/// # use sharebill::attachments::sanitize_filename;
+/// assert_eq!(sanitize_filename("C:\\Users\\me\\receipt.jpg"), "receipt.jpg");
+/// assert_eq!(sanitize_filename("../\n"), "attachment");
+/// ```
+pub fn sanitize_filename(filename: &str) -> String {
+    let filename = filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .take(MAX_FILENAME_LENGTH)
+        .collect::<String>();
+    let filename = filename.trim();
+    if filename.is_empty() || filename == "." || filename == ".." {
+        "attachment".to_owned()
+    } else {
+        filename.to_owned()
+    }
+}
+
+pub fn is_image(content_type: &str) -> bool {
+    content_type.starts_with("image/")
+}
+
+/// Attaches a file to a transaction, returning the id of the attachment. The
+/// file should have been checked with [`check_attachment`] first.
+pub fn insert_attachment(
+    conn: &mut DbConnection,
+    tx_id: i32,
+    filename: &str,
+    content_type: &str,
+    data: &[u8],
+    user_id: Option<i32>,
+) -> QueryResult<i32> {
+    let filename = sanitize_filename(filename);
+    diesel::insert_into(attachments::table)
+        .values(&NewAttachment {
+            tx_id,
+            filename: &filename,
+            content_type,
+            size: data.len() as i32,
+            data,
+            created_time: chrono::Utc::now().naive_utc(),
+            user_id,
+        })
+        .returning(attachments::id)
+        .get_result(conn)
+}
+
+/// The attachments of a transaction, oldest first
+pub fn tx_attachments(conn: &mut DbConnection, tx_id: i32) -> QueryResult<Vec<Attachment>> {
+    attachments::table
+        .filter(attachments::tx_id.eq(tx_id))
+        .order(attachments::id)
+        .select(ATTACHMENT_COLUMNS)
+        .load(conn)
+}
+
+/// Deletes all attachments of a transaction, which must be done before the
+/// transaction itself is deleted
+pub fn delete_tx_attachments(conn: &mut DbConnection, tx_id: i32) -> QueryResult<usize> {
+    diesel::delete(attachments::table.filter(attachments::tx_id.eq(tx_id))).execute(conn)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::Actor;
+    use crate::ledger::{Ledger, Postings, Transaction};
+
+    #[test]
+    fn checks_size_and_type() {
+        assert_eq!(check_attachment(b""), Err(AttachmentError::Empty));
+        assert_eq!(check_attachment(b"RIFF\0\0\0\0WEBPVP8 "), Ok("image/webp"));
+        assert_eq!(
+            check_attachment(b"<svg onload=alert(1)>"),
+            Err(AttachmentError::UnsupportedType)
+        );
+
+        let mut large = b"%PDF-".to_vec();
+        large.resize(MAX_ATTACHMENT_SIZE, b' ');
+        assert_eq!(check_attachment(&large), Ok("application/pdf"));
+        large.push(b' ');
+        assert_eq!(check_attachment(&large), Err(AttachmentError::TooLarge));
+    }
+
+    #[test]
+    fn insert_and_delete() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+
+        let now = chrono::Utc::now().naive_utc();
+        let posting = |account: &str| {
+            Postings::from([((account.to_owned(), "NOK".to_owned()), 10u32.into())])
+        };
+        let dinner = Transaction::new(now, "Dinner", "", posting("bob"), posting("alice"))?;
+        let tx_id = Ledger::new(&mut conn, 1).insert_transaction(Actor::cli(), &dinner)?;
+
+        let png = b"\x89PNG\r\n\x1a\n...";
+        let content_type = check_attachment(png)?;
+        insert_attachment(
+            &mut conn,
+            tx_id,
+            "/tmp/receipt.png",
+            content_type,
+            png,
+            None,
+        )?;
+
+        let attached = tx_attachments(&mut conn, tx_id)?;
+        assert_eq!(attached.len(), 1);
+        assert_eq!(attached[0].filename, "receipt.png");
+        assert_eq!(attached[0].content_type, "image/png");
+        assert_eq!(attached[0].size, png.len() as i32);
+
+        assert_eq!(delete_tx_attachments(&mut conn, tx_id)?, 1);
+        Ledger::new(&mut conn, 1).delete_transaction(Actor::cli(), tx_id)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! The audit log, an append-only record of every change. Entries are written
+//! in the same database transaction as the change they describe, so the log
+//! has exactly the changes that were made.
+
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel::{AsExpression, FromSqlRow};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::models::{AuditEntry, NewAuditEntry, TxItem};
+use crate::schema::{audit_log, credits, debits, txs};
+use crate::tags::tx_tags;
+use crate::DbConnection;
+
+/// Where a change was made from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum Source {
+    Web,
+    Cli,
+    Api,
+    Importer,
+    /// Recurring transactions that are created when they are due
+    Scheduler,
+}
+
+/// What kind of change was made
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum Action {
+    Create,
+    Edit,
+    Delete,
+    Import,
+    /// Closing a period
+    Close,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid audit source or action")]
+pub struct InvalidAuditValue;
+
+text_enum!(Source, InvalidAuditValue {
+    Web => "web",
+    Cli => "cli",
+    Api => "api",
+    Importer => "importer",
+    Scheduler => "scheduler",
+});
+
+text_enum!(Action, InvalidAuditValue {
+    Create => "create",
+    Edit => "edit",
+    Delete => "delete",
+    Import => "import",
+    Close => "close",
+});
+
+/// Who made a change, and from where
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Actor {
+    pub source: Source,
+    /// `None` for the command line and the scheduler, which have no user
+    pub user_id: Option<i32>,
+}
+
+impl Actor {
+    pub fn web(user_id: i32) -> Self {
+        Actor {
+            source: Source::Web,
+            user_id: Some(user_id),
+        }
+    }
+
+    pub fn api(user_id: i32) -> Self {
+        Actor {
+            source: Source::Api,
+            user_id: Some(user_id),
+        }
+    }
+
+    pub fn cli() -> Self {
+        Actor {
+            source: Source::Cli,
+            user_id: None,
+        }
+    }
+
+    pub fn importer() -> Self {
+        Actor {
+            source: Source::Importer,
+            user_id: None,
+        }
+    }
+
+    pub fn scheduler() -> Self {
+        Actor {
+            source: Source::Scheduler,
+            user_id: None,
+        }
+    }
+}
+
+/// Appends an entry to the audit log. Call this inside the database
+/// transaction that makes the change.
+pub fn record(
+    conn: &mut DbConnection,
+    actor: Actor,
+    ledger_id: Option<i32>,
+    action: Action,
+    object: &str,
+    payload: serde_json::Value,
+) -> QueryResult<()> {
+    diesel::insert_into(audit_log::table)
+        .values(&NewAuditEntry {
+            time: chrono::Utc::now().naive_utc(),
+            ledger_id,
+            user_id: actor.user_id,
+            source: actor.source,
+            action,
+            object,
+            payload: payload.to_string(),
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// A post as it is stored, for the payload of an audit entry. Take it after
+/// creating or editing a post, and before deleting it.
+pub fn tx_snapshot(conn: &mut DbConnection, tx_id: i32) -> QueryResult<serde_json::Value> {
+    let (tx_time, description) = txs::table
+        .find(tx_id)
+        .select((txs::tx_time, txs::description))
+        .first::<(NaiveDateTime, String)>(conn)?;
+    let debits = debits::table
+        .select((debits::account, debits::currency, debits::value))
+        .filter(debits::tx_id.eq(tx_id))
+        .order((debits::account, debits::currency))
+        .load::<TxItem>(conn)?;
+    let credits = credits::table
+        .select((credits::account, credits::currency, credits::value))
+        .filter(credits::tx_id.eq(tx_id))
+        .order((credits::account, credits::currency))
+        .load::<TxItem>(conn)?;
+    let postings = |items: Vec<TxItem>| {
+        items
+            .into_iter()
+            .map(|item| {
+                json!({
+                    "account": item.account,
+                    "currency": item.currency,
+                    "value": item.value,
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    Ok(json!({
+        "id": tx_id,
+        "when": tx_time,
+        "what": description,
+        "tags": tx_tags(conn, tx_id)?,
+        "debits": postings(debits),
+        "credits": postings(credits),
+    }))
+}
+
+/// Which audit entries to list. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub ledger_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub source: Option<Source>,
+    pub action: Option<Action>,
+    pub object: Option<String>,
+    /// The first day to list
+    pub from: Option<NaiveDate>,
+    /// The last day to list, inclusive
+    pub to: Option<NaiveDate>,
+}
+
+/// Audit entries that match `filter`, newest first
+pub fn audit_entries(
+    conn: &mut DbConnection,
+    filter: &AuditFilter,
+    limit: i64,
+) -> QueryResult<Vec<AuditEntry>> {
+    let mut query = audit_log::table.into_boxed();
+    if let Some(ledger_id) = filter.ledger_id {
+        query = query.filter(audit_log::ledger_id.eq(ledger_id));
+    }
+    if let Some(user_id) = filter.user_id {
+        query = query.filter(audit_log::user_id.eq(user_id));
+    }
+    if let Some(source) = filter.source {
+        query = query.filter(audit_log::source.eq(source));
+    }
+    if let Some(action) = filter.action {
+        query = query.filter(audit_log::action.eq(action));
+    }
+    if let Some(object) = &filter.object {
+        query = query.filter(audit_log::object.eq(object));
+    }
+    if let Some(from) = filter.from {
+        query = query.filter(audit_log::time.ge(from.and_hms_opt(0, 0, 0).unwrap()));
+    }
+    if let Some(to) = filter.to.and_then(|to| to.succ_opt()) {
+        query = query.filter(audit_log::time.lt(to.and_hms_opt(0, 0, 0).unwrap()));
+    }
+
+    query.order(audit_log::id.desc()).limit(limit).load(conn)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn append_only() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+
+        record(
+            &mut conn,
+            Actor::cli(),
+            Some(1),
+            Action::Create,
+            "budget",
+            json!({ "name": "food" }),
+        )?;
+        record(
+            &mut conn,
+            Actor::importer(),
+            None,
+            Action::Import,
+            "rate",
+            json!({ "currency": "EUR" }),
+        )?;
+
+        let entries = audit_entries(&mut conn, &AuditFilter::default(), 10)?;
+        assert_eq!(
+            entries.iter().map(|e| e.action).collect::<Vec<_>>(),
+            vec![Action::Import, Action::Create]
+        );
+        let filter = AuditFilter {
+            ledger_id: Some(1),
+            source: Some(Source::Cli),
+            ..Default::default()
+        };
+        let entries = audit_entries(&mut conn, &filter, 10)?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].payload, r#"{"name":"food"}"#);
+
+        assert!(diesel::update(audit_log::table)
+            .set(audit_log::object.eq("tampered"))
+            .execute(&mut conn)
+            .is_err());
+        assert!(diesel::delete(audit_log::table).execute(&mut conn).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use diesel::sql_types::Text;
+use diesel::{AsExpression, FromSqlRow};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Access level of a user in a ledger. Each role includes the rights of the
+/// roles before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum Role {
+    /// Can see balances and transactions
+    Viewer,
+    /// Can additionally post, edit and delete transactions
+    Member,
+    /// Can additionally change ledger settings and manage members
+    Admin,
+}
+
+#[derive(Debug)]
+pub struct InvalidRole;
+
+impl std::fmt::Display for InvalidRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid role, must be one of viewer, member or admin")
+    }
+}
+
+impl std::error::Error for InvalidRole {}
+
+text_enum!(Role, InvalidRole {
+    Viewer => "viewer",
+    Member => "member",
+    Admin => "admin",
+});
+
+/// Access granted to an API token in a ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum Access {
+    Read,
+    ReadWrite,
+}
+
+impl Access {
+    /// The role needed to perform operations with this access
+    pub fn role(&self) -> Role {
+        match self {
+            Access::Read => Role::Viewer,
+            Access::ReadWrite => Role::Member,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidAccess;
+
+impl std::fmt::Display for InvalidAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid access, must be read or read-write")
+    }
+}
+
+impl std::error::Error for InvalidAccess {}
+
+text_enum!(Access, InvalidAccess {
+    Read => "read",
+    ReadWrite => "read-write",
+});
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// A hash of a password that no one has, made with the same parameters as
+/// `hash_password`. Logins with an unknown username are checked against it,
+/// so they take as long as logins with a wrong password.
+pub const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$FTQnp2vVpkv/75uHo1o13Q$MFDchrFxMd4Ra6Gw2XFW+ukFlTr8sVnct5OIzeztfeg";
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// Generates a random token for use as a session identifier or API token. Only
+/// the hash of the token, see `hash_token`, should be stored.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn password_roundtrip() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("hunter2", "not a hash"));
+    }
+
+    #[test]
+    fn dummy_hash_costs_as_much_as_a_real_one() {
+        let hash = hash_password("hunter2").unwrap();
+        let params = |hash: &str| hash.split('$').take(4).collect::<Vec<_>>().join("$");
+        assert_eq!(params(DUMMY_PASSWORD_HASH), params(&hash));
+        assert!(PasswordHash::new(DUMMY_PASSWORD_HASH).is_ok());
+        assert!(!verify_password("hunter2", DUMMY_PASSWORD_HASH));
+    }
+
+    #[test]
+    fn roles_are_ordered_by_access() {
+        assert!(Role::Viewer < Role::Member);
+        assert!(Role::Member < Role::Admin);
+        assert_eq!("member".parse::<Role>().unwrap(), Role::Member);
+        assert!("owner".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn tokens_are_unique() {
+        let a = generate_token();
+        let b = generate_token();
+        assert_eq!(a.len(), 64);
+        assert_ne!(a, b);
+        assert_ne!(hash_token(&a), hash_token(&b));
+    }
+}
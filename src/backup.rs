@@ -0,0 +1,310 @@
+//! Backups of a live database, and restoring them. Backups are made with
+//! `VACUUM INTO`, which writes a consistent copy while others keep reading
+//! and writing the database. This is for SQLite databases only, PostgreSQL
+//! databases are backed up with its own tools, such as `pg_dump`.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use diesel::migration::MigrationSource;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::Text;
+use diesel::sqlite::Sqlite;
+use thiserror::Error;
+
+use crate::MIGRATIONS;
+
+const BACKUP_PREFIX: &str = "sharebill-";
+const BACKUP_SUFFIX: &str = ".db";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    #[error(transparent)]
+    Db(#[from] diesel::result::Error),
+    #[error("integrity check failed: {0}")]
+    Integrity(String),
+    #[error("not a sharebill database")]
+    NotADatabase,
+    #[error("made by a newer version, with migration {0}")]
+    NewerVersion(String),
+    #[error("backups are made of SQLite databases, use pg_dump for PostgreSQL")]
+    Unsupported,
+}
+
+#[derive(QueryableByName)]
+struct IntegrityCheck {
+    #[diesel(sql_type = Text)]
+    integrity_check: String,
+}
+
+#[derive(QueryableByName)]
+struct MigrationVersion {
+    #[diesel(sql_type = Text)]
+    version: String,
+}
+
+/// Checks that the programs store the ledgers in SQLite, which is what the
+/// backups are made of
+pub fn check_supported() -> Result<(), BackupError> {
+    if cfg!(feature = "postgres") {
+        Err(BackupError::Unsupported)
+    } else {
+        Ok(())
+    }
+}
+
+/// The name of a backup made at a time, such as
+/// `sharebill-20261018-120000.db`. The names sort by time.
+pub fn backup_filename(time: NaiveDateTime) -> String {
+    format!(
+        "{BACKUP_PREFIX}{}{BACKUP_SUFFIX}",
+        time.format(BACKUP_TIME_FORMAT)
+    )
+}
+
+/// Writes a backup of the database to a directory, which is created if
+/// needed, and returns its path
+pub fn backup(
+    conn: &mut SqliteConnection,
+    dir: &Path,
+    time: NaiveDateTime,
+) -> Result<PathBuf, BackupError> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(backup_filename(time));
+
+    sql_query("VACUUM INTO ?")
+        .bind::<Text, _>(path.to_string_lossy())
+        .execute(conn)?;
+
+    Ok(path)
+}
+
+/// The backups in a directory, oldest first
+pub fn backups(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(BACKUP_PREFIX))
+            .and_then(|name| name.strip_suffix(BACKUP_SUFFIX))
+            .is_some_and(|time| NaiveDateTime::parse_from_str(time, BACKUP_TIME_FORMAT).is_ok());
+        if is_backup {
+            backups.push(path);
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+/// Deletes all but the newest `keep` backups in a directory, and returns the
+/// deleted ones
+pub fn prune(dir: &Path, keep: usize) -> std::io::Result<Vec<PathBuf>> {
+    let mut backups = backups(dir)?;
+    let old = backups.len().saturating_sub(keep);
+    backups.truncate(old);
+    for path in &backups {
+        fs::remove_file(path)?;
+    }
+    Ok(backups)
+}
+
+/// Checks that a backup is intact, and that it was made by this version or
+/// an older one, so that the migrations can bring it up to date
+pub fn verify(path: &Path) -> Result<(), BackupError> {
+    // Connecting would create a missing file
+    if !path.is_file() {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+    }
+    let mut conn = SqliteConnection::establish(&path.to_string_lossy())?;
+
+    let messages = sql_query("PRAGMA integrity_check")
+        .load::<IntegrityCheck>(&mut conn)?
+        .into_iter()
+        .map(|row| row.integrity_check)
+        .collect::<Vec<_>>();
+    if messages != ["ok"] {
+        return Err(BackupError::Integrity(messages.join("; ")));
+    }
+
+    let applied = sql_query("SELECT version FROM __diesel_schema_migrations")
+        .load::<MigrationVersion>(&mut conn)
+        .map_err(|_| BackupError::NotADatabase)?;
+    let known = MigrationSource::<Sqlite>::migrations(&MIGRATIONS)
+        .map_err(|_| BackupError::NotADatabase)?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect::<Vec<_>>();
+    match applied
+        .into_iter()
+        .find(|row| !known.contains(&row.version))
+    {
+        Some(row) => Err(BackupError::NewerVersion(row.version)),
+        None => Ok(()),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path);
+    name.push(suffix);
+    name.into()
+}
+
+/// The files SQLite keeps next to a database in WAL mode. The log holds
+/// changes that are not in the database file yet.
+const WAL_SUFFIXES: [&str; 2] = ["-wal", "-shm"];
+
+/// Replaces a database with a backup, after verifying the backup. The
+/// replaced database is kept with `.before-restore` appended to its name,
+/// along with its write-ahead log. Nothing may have the database open while
+/// it is replaced.
+pub fn restore(backup: &Path, database: &Path) -> Result<(), BackupError> {
+    verify(backup)?;
+
+    // Copy next to the database first, so the swap itself is a rename
+    let restoring = with_suffix(database, ".restoring");
+    fs::copy(backup, &restoring)?;
+
+    // The log goes with the database it belongs to. Left behind, it would be
+    // applied to the restored database.
+    let kept = with_suffix(database, ".before-restore");
+    for suffix in WAL_SUFFIXES {
+        let (log, kept_log) = (with_suffix(database, suffix), with_suffix(&kept, suffix));
+        if log.exists() {
+            fs::rename(log, kept_log)?;
+        } else if kept_log.exists() {
+            fs::remove_file(kept_log)?;
+        }
+    }
+    if database.exists() {
+        fs::rename(database, &kept)?;
+    }
+    fs::rename(&restoring, database)?;
+
+    Ok(())
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod test {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::audit::Actor;
+    use crate::ledger::{Ledger, Postings, Transaction};
+
+    #[test]
+    fn backup_prune_and_restore() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("sharebill-backups-{}", std::process::id()));
+        let time = |h| {
+            NaiveDate::from_ymd_opt(2026, 10, 18)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        let mut conn = crate::establish_connection(":memory:");
+        let posting = |account: &str| {
+            Postings::from([((account.to_owned(), "NOK".to_owned()), 10u32.into())])
+        };
+        let dinner = Transaction::new(time(12), "Dinner", "", posting("bob"), posting("alice"))?;
+        Ledger::new(&mut conn, 1).insert_transaction(Actor::cli(), &dinner)?;
+
+        for h in [1, 2, 3] {
+            backup(&mut conn, &dir, time(h))?;
+        }
+        fs::write(dir.join("notes.txt"), "not a backup")?;
+        assert_eq!(
+            prune(&dir, 2)?,
+            vec![dir.join("sharebill-20261018-010000.db")]
+        );
+        let newest = dir.join("sharebill-20261018-030000.db");
+        assert_eq!(
+            backups(&dir)?,
+            vec![dir.join("sharebill-20261018-020000.db"), newest.clone()]
+        );
+
+        assert!(matches!(
+            verify(&dir.join("notes.txt")),
+            Err(BackupError::Db(_))
+        ));
+        assert!(matches!(
+            verify(&dir.join("missing.db")),
+            Err(BackupError::Io(_))
+        ));
+
+        let database = dir.join("restored.db");
+        restore(&newest, &database)?;
+        let mut restored = crate::establish_connection(&database.to_string_lossy());
+        let listed = Ledger::new(&mut restored, 1).list_transactions(None, None, None, 10)?;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].transaction, dinner);
+
+        // Backups from a newer version cannot be brought up to date
+        let older = dir.join("sharebill-20261018-020000.db");
+        sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES ('29991231000000')")
+            .execute(&mut SqliteConnection::establish(&older.to_string_lossy())?)?;
+        assert!(matches!(
+            restore(&older, &database),
+            Err(BackupError::NewerVersion(_))
+        ));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn restore_keeps_the_log_with_the_replaced_database() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let dir = std::env::temp_dir().join(format!("sharebill-wal-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let when = NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let posting = |account: &str| {
+            Postings::from([((account.to_owned(), "NOK".to_owned()), 10u32.into())])
+        };
+
+        let mut conn = crate::establish_connection(":memory:");
+        let dinner = Transaction::new(when, "Dinner", "", posting("bob"), posting("alice"))?;
+        Ledger::new(&mut conn, 1).insert_transaction(Actor::cli(), &dinner)?;
+        let backup = backup(&mut conn, &dir, when)?;
+
+        let database = dir.join("live.db");
+        let mut live = crate::establish_connection(&database.to_string_lossy());
+        sql_query("PRAGMA journal_mode = WAL").execute(&mut live)?;
+        let lunch = Transaction::new(when, "Lunch", "", posting("alice"), posting("bob"))?;
+        Ledger::new(&mut live, 1).insert_transaction(Actor::cli(), &lunch)?;
+        // Closing would move the log into the database, as if the server had
+        // been killed
+        std::mem::forget(live);
+        assert!(with_suffix(&database, "-wal").exists());
+
+        restore(&backup, &database)?;
+        for suffix in WAL_SUFFIXES {
+            assert!(!with_suffix(&database, suffix).exists());
+        }
+        let mut restored = crate::establish_connection(&database.to_string_lossy());
+        let listed = Ledger::new(&mut restored, 1).list_transactions(None, None, None, 10)?;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].transaction, dinner);
+
+        let kept = with_suffix(&database, ".before-restore");
+        let mut kept = crate::establish_connection(&kept.to_string_lossy());
+        let listed = Ledger::new(&mut kept, 1).list_transactions(None, None, None, 10)?;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].transaction, lunch);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
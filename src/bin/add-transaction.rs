@@ -1,88 +1,120 @@
+use clap::{Arg, ArgAction, Command};
 use diesel::prelude::*;
-use diesel::result::Error;
-use num::{BigInt, Zero};
 use sharebill::{
-    models::{NewCredit, NewDebit, NewTx, Tx},
+    amount::parse_amount,
+    audit::Actor,
+    currency::parse_currency,
+    ledger::{Postings, Transaction},
+    models::Ledger,
     parse_arg::{parse_arg, EntryType},
+    post_templates::find_template,
     rational::Rational,
 };
 
 fn main() {
-    let description = std::env::args()
-        .nth(1)
-        .expect("Usage: add-transaction \"My transaction\" ABC+1/2 XYZ-1/2");
+    let matches = Command::new("add-transaction")
+        .about("Adds a transaction to a ledger")
+        .override_usage(
+            "add-transaction [--ledger SLUG] [--currency CODE] \"My transaction\" ABC+1/2 XYZ-1/2\n       \
+             add-transaction [--ledger SLUG] [--currency CODE] --template NAME AMOUNT [\"My transaction\"]",
+        )
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .default_value("default")
+                .help("Slug of the ledger to add the transaction to"),
+        )
+        .arg(
+            Arg::new("currency")
+                .long("currency")
+                .help("Currency of all the amounts, defaults to the base currency of the ledger"),
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .num_args(2)
+                .value_names(["NAME", "AMOUNT"])
+                .conflicts_with("entries")
+                .help("Split AMOUNT by the shares of a saved template, using its description unless one is given"),
+        )
+        .arg(Arg::new("description").required_unless_present("template"))
+        .arg(Arg::new("entries").action(ArgAction::Append))
+        .get_matches();
 
-    let args: Vec<String> = std::env::args().skip(2).map(|x| x).collect();
+    let slug = matches.get_one::<String>("ledger").unwrap();
+    let description = matches.get_one::<String>("description");
+    let template = matches
+        .get_many::<String>("template")
+        .map(|mut values| (values.next().unwrap(), values.next().unwrap()));
+
+    let args: Vec<String> = matches
+        .get_many::<String>("entries")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
     let entries: Vec<(EntryType, &str, Rational)> = args
         .iter()
-        .map(|x| parse_arg(&x).unwrap_or_else(|| panic!("Failed to parse argument '{}'. Should be account +/- amount, e.g. JH+5/2 or MHO-2", x)))
+        .map(|x| parse_arg(x).unwrap_or_else(|| panic!("Failed to parse argument '{}'. Should be account +/- amount, e.g. JH+5/2 or MHO-2", x)))
         .collect();
 
-    if entries.len() == 0 {
-        return;
-    }
-    let mut sum = num::BigRational::from((0.into(), 1.into()));
-    for (entry_type, _, amount) in &entries {
-        let v = amount.clone().into_inner();
-        let amount = num::BigRational::from((
-            BigInt::from(v.numer().clone()),
-            BigInt::from(v.denom().clone()),
-        ));
-        match entry_type {
-            EntryType::Credit => sum += amount,
-            EntryType::Debit => sum -= amount,
-        }
-    }
-    if !sum.is_zero() {
-        println!("Transaction doesn't sum to zero. Aborting.");
+    if entries.is_empty() && template.is_none() {
         return;
     }
 
-    let now = chrono::Utc::now().naive_utc();
-    let new_tx = NewTx {
-        tx_time: now,
-        rev_time: now,
-        description: &description,
-    };
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
 
-    let conn = &mut sharebill::establish_connection("test.db");
-    conn.transaction::<_, Error, _>(|conn| {
-        use sharebill::schema::{credits, debits, txs};
+    let ledger = {
+        use sharebill::schema::ledgers;
 
-        let tx: Tx = diesel::insert_into(txs::table)
-            .values(&new_tx)
-            .get_result(conn)
-            .expect("Error saving transaction");
+        ledgers::table
+            .filter(ledgers::slug.eq(slug))
+            .first::<Ledger>(conn)
+            .unwrap_or_else(|_| panic!("No such ledger: {}", slug))
+    };
 
-        let mut credits = Vec::<NewCredit>::new();
-        let mut debits = Vec::<NewDebit>::new();
+    let currency = match matches.get_one::<String>("currency") {
+        Some(currency) => parse_currency(currency).unwrap_or_else(|err| panic!("{}", err)),
+        None => ledger.base_currency.clone(),
+    };
 
-        for entry in entries {
-            match entry {
-                (EntryType::Credit, account, value) => credits.push(NewCredit {
-                    tx_id: tx.id,
-                    account,
-                    value,
-                }),
-                (EntryType::Debit, account, value) => debits.push(NewDebit {
-                    tx_id: tx.id,
-                    account,
-                    value,
-                }),
-            }
+    let (mut debits, mut credits, description) = match template {
+        Some((name, amount)) => {
+            let total = parse_amount(amount).unwrap_or_else(|err| panic!("{}: '{}'", err, amount));
+            let template = find_template(conn, ledger.id, name)
+                .expect("Error loading template")
+                .unwrap_or_else(|| panic!("No such template: {}", name));
+            let (debits, credits) = template.postings(&total, &currency);
+            let description = description
+                .map_or(template.description(), String::as_str)
+                .to_owned();
+            (debits, credits, description)
         }
+        None => (
+            Postings::new(),
+            Postings::new(),
+            description.unwrap().to_owned(),
+        ),
+    };
+    for (entry_type, account, value) in entries {
+        let postings = match entry_type {
+            EntryType::Credit => &mut credits,
+            EntryType::Debit => &mut debits,
+        };
+        *postings
+            .entry((account.to_owned(), currency.clone()))
+            .or_default() += value;
+    }
 
-        diesel::insert_into(credits::table)
-            .values(&credits)
-            .execute(conn)
-            .expect("Error saving transaction");
-
-        diesel::insert_into(debits::table)
-            .values(&debits)
-            .execute(conn)
-            .expect("Error saving transaction");
+    let now = chrono::Utc::now().naive_utc();
+    let transaction = match Transaction::new(now, description, "", debits, credits) {
+        Ok(transaction) => transaction,
+        Err(err) => {
+            println!("Invalid transaction: {}. Aborting.", err);
+            return;
+        }
+    };
 
-        Ok(())
-    })
-    .expect("Error storing transaction");
+    sharebill::ledger::Ledger::new(conn, ledger.id)
+        .insert_transaction(Actor::cli(), &transaction)
+        .expect("Error storing transaction");
 }
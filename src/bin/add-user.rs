@@ -0,0 +1,127 @@
+use std::io::BufRead;
+
+use clap::{Arg, ArgAction, Command};
+use diesel::prelude::*;
+use diesel::result::Error;
+use sharebill::{
+    audit::{self, Action, Actor},
+    auth::{hash_password, Role},
+    models::{Ledger, NewLedgerMember, NewUser},
+    schema::{ledger_members, ledgers, users},
+};
+
+fn read_password() -> String {
+    eprint!("Password: ");
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .expect("Error reading password");
+    let password = password.trim_end_matches(['\r', '\n']).to_owned();
+    if password.is_empty() {
+        panic!("Password cannot be empty");
+    }
+    password
+}
+
+fn main() {
+    let matches = Command::new("add-user")
+        .about(
+            "Creates a web user and grants it access to a ledger. The password is read from stdin.",
+        )
+        .arg(Arg::new("username").required(true))
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .help("Slug of a ledger to grant the user access to"),
+        )
+        .arg(
+            Arg::new("role")
+                .long("role")
+                .default_value("member")
+                .value_parser(["viewer", "member", "admin"])
+                .help("Role of the user in the ledger"),
+        )
+        .arg(
+            Arg::new("reset-password")
+                .long("reset-password")
+                .action(ArgAction::SetTrue)
+                .help("Set a new password for an existing user"),
+        )
+        .get_matches();
+
+    let username = matches.get_one::<String>("username").unwrap();
+    let slug = matches.get_one::<String>("ledger");
+    let role: Role = matches.get_one::<String>("role").unwrap().parse().unwrap();
+    let reset_password = matches.get_flag("reset-password");
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let existing_user = users::table
+        .filter(users::username.eq(username))
+        .select(users::id)
+        .first::<i32>(conn)
+        .optional()
+        .expect("Error loading user");
+
+    let password_hash = match existing_user {
+        Some(_) if !reset_password => None,
+        _ => Some(hash_password(&read_password()).expect("Error hashing password")),
+    };
+
+    conn.transaction::<_, Error, _>(|conn| {
+        let user_id = match (existing_user, &password_hash) {
+            (Some(user_id), Some(password_hash)) => {
+                diesel::update(users::table.find(user_id))
+                    .set(users::password_hash.eq(password_hash))
+                    .execute(conn)?;
+                let payload = serde_json::json!({ "id": user_id, "username": username });
+                audit::record(conn, Actor::cli(), None, Action::Edit, "user", payload)?;
+                user_id
+            }
+            (Some(user_id), None) => user_id,
+            (None, password_hash) => {
+                let user_id = diesel::insert_into(users::table)
+                    .values(&NewUser {
+                        username,
+                        password_hash: password_hash.as_deref().unwrap(),
+                    })
+                    .returning(users::id)
+                    .get_result(conn)?;
+                let payload = serde_json::json!({ "id": user_id, "username": username });
+                audit::record(conn, Actor::cli(), None, Action::Create, "user", payload)?;
+                user_id
+            }
+        };
+
+        if let Some(slug) = slug {
+            let ledger = ledgers::table
+                .filter(ledgers::slug.eq(slug))
+                .first::<Ledger>(conn)
+                .unwrap_or_else(|_| panic!("No such ledger: {}", slug));
+
+            diesel::insert_into(ledger_members::table)
+                .values(&NewLedgerMember {
+                    ledger_id: ledger.id,
+                    user_id,
+                    role,
+                })
+                .on_conflict((ledger_members::ledger_id, ledger_members::user_id))
+                .do_update()
+                .set(ledger_members::role.eq(role))
+                .execute(conn)?;
+
+            audit::record(
+                conn,
+                Actor::cli(),
+                Some(ledger.id),
+                Action::Edit,
+                "member",
+                serde_json::json!({ "username": username, "role": role.as_str() }),
+            )?;
+        }
+
+        Ok(())
+    })
+    .expect("Error storing user");
+}
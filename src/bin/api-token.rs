@@ -0,0 +1,172 @@
+use clap::{Arg, ArgAction, Command};
+use diesel::prelude::*;
+use diesel::result::Error;
+use sharebill::DbConnection;
+use sharebill::{
+    audit::{self, Action, Actor},
+    auth::{generate_token, hash_token, Access},
+    models::{ApiToken, NewApiToken, NewApiTokenLedger},
+    schema::{api_token_ledgers, api_tokens, ledgers, users},
+};
+
+fn find_user(conn: &mut DbConnection, username: &str) -> i32 {
+    users::table
+        .filter(users::username.eq(username))
+        .select(users::id)
+        .first::<i32>(conn)
+        .unwrap_or_else(|_| panic!("No such user: {}", username))
+}
+
+fn main() {
+    let matches = Command::new("api-token")
+        .about("Manages personal API tokens for the JSON endpoints")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("create")
+                .about("Creates a token and prints it. The token cannot be shown again.")
+                .arg(Arg::new("username").required(true))
+                .arg(
+                    Arg::new("name")
+                        .required(true)
+                        .help("What the token is for"),
+                )
+                .arg(
+                    Arg::new("ledger")
+                        .long("ledger")
+                        .action(ArgAction::Append)
+                        .value_name("SLUG:ACCESS")
+                        .help("Grant read or read-write access to a ledger, may be repeated"),
+                ),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Lists the tokens of a user")
+                .arg(Arg::new("username").required(true)),
+        )
+        .subcommand(
+            Command::new("revoke").about("Revokes a token").arg(
+                Arg::new("id")
+                    .required(true)
+                    .value_parser(clap::value_parser!(i32)),
+            ),
+        )
+        .get_matches();
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    match matches.subcommand() {
+        Some(("create", matches)) => {
+            let user_id = find_user(conn, matches.get_one::<String>("username").unwrap());
+            let name = matches.get_one::<String>("name").unwrap();
+            let grants = matches
+                .get_many::<String>("ledger")
+                .unwrap_or_default()
+                .map(|grant| {
+                    let (slug, access) = grant
+                        .split_once(':')
+                        .unwrap_or_else(|| panic!("Expected SLUG:ACCESS, got {}", grant));
+                    let access: Access = access.parse().unwrap();
+                    let ledger_id = ledgers::table
+                        .filter(ledgers::slug.eq(slug))
+                        .select(ledgers::id)
+                        .first::<i32>(conn)
+                        .unwrap_or_else(|_| panic!("No such ledger: {}", slug));
+                    (ledger_id, access)
+                })
+                .collect::<Vec<_>>();
+
+            let token = generate_token();
+            conn.transaction::<_, Error, _>(|conn| {
+                let token_id = diesel::insert_into(api_tokens::table)
+                    .values(&NewApiToken {
+                        user_id,
+                        name,
+                        token_hash: hash_token(&token),
+                        created_time: chrono::Utc::now().naive_utc(),
+                    })
+                    .returning(api_tokens::id)
+                    .get_result::<i32>(conn)?;
+
+                let ledgers = grants
+                    .iter()
+                    .map(|(ledger_id, access)| {
+                        serde_json::json!({ "ledger_id": ledger_id, "access": access.as_str() })
+                    })
+                    .collect::<Vec<_>>();
+                let payload =
+                    serde_json::json!({ "id": token_id, "name": name, "ledgers": ledgers });
+                audit::record(conn, Actor::cli(), None, Action::Create, "token", payload)?;
+
+                diesel::insert_into(api_token_ledgers::table)
+                    .values(
+                        grants
+                            .into_iter()
+                            .map(|(ledger_id, access)| NewApiTokenLedger {
+                                token_id,
+                                ledger_id,
+                                access,
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .execute(conn)?;
+
+                Ok(())
+            })
+            .expect("Error storing token");
+
+            println!("{}", token);
+        }
+        Some(("list", matches)) => {
+            let user_id = find_user(conn, matches.get_one::<String>("username").unwrap());
+            let tokens = api_tokens::table
+                .filter(api_tokens::user_id.eq(user_id))
+                .order(api_tokens::id)
+                .load::<ApiToken>(conn)
+                .expect("Error loading tokens");
+
+            for token in tokens {
+                let grants = api_token_ledgers::table
+                    .inner_join(ledgers::table)
+                    .filter(api_token_ledgers::token_id.eq(token.id))
+                    .order(ledgers::slug)
+                    .select((ledgers::slug, api_token_ledgers::access))
+                    .load::<(String, Access)>(conn)
+                    .expect("Error loading token access")
+                    .into_iter()
+                    .map(|(slug, access)| format!("{}:{}", slug, access))
+                    .collect::<Vec<_>>();
+
+                let last_used = token
+                    .last_used_time
+                    .map(|time| time.to_string())
+                    .unwrap_or_else(|| "never".to_owned());
+
+                println!(
+                    "{}\t{}\t{}\tcreated {}\tlast used {}",
+                    token.id,
+                    token.name,
+                    grants.join(","),
+                    token.created_time,
+                    last_used
+                );
+            }
+        }
+        Some(("revoke", matches)) => {
+            let id = *matches.get_one::<i32>("id").unwrap();
+            let deleted = conn
+                .transaction::<_, Error, _>(|conn| {
+                    let deleted = diesel::delete(api_tokens::table.find(id)).execute(conn)?;
+                    if deleted > 0 {
+                        let payload = serde_json::json!({ "id": id });
+                        audit::record(conn, Actor::cli(), None, Action::Delete, "token", payload)?;
+                    }
+                    Ok(deleted)
+                })
+                .expect("Error revoking token");
+            if deleted == 0 {
+                panic!("No such token: {}", id);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
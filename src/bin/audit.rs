@@ -0,0 +1,111 @@
+use chrono::NaiveDate;
+use clap::{value_parser, Arg, Command};
+use diesel::prelude::*;
+use sharebill::{
+    audit::{audit_entries, Action, AuditFilter, Source},
+    schema::{ledgers, users},
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("audit")
+        .about("Lists the audit log, newest first")
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .help("Slug of a ledger to list the changes of, defaults to all changes"),
+        )
+        .arg(
+            Arg::new("user")
+                .long("user")
+                .help("Only list changes made by this user"),
+        )
+        .arg(
+            Arg::new("source")
+                .long("source")
+                .value_parser(value_parser!(Source))
+                .help("Only list changes from web, cli, api, importer or scheduler"),
+        )
+        .arg(
+            Arg::new("action")
+                .long("action")
+                .value_parser(value_parser!(Action))
+                .help("Only list create, edit, delete, import or close changes"),
+        )
+        .arg(
+            Arg::new("object")
+                .long("object")
+                .help("Only list changes to this kind of object, such as post or member"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_parser(value_parser!(NaiveDate))
+                .help("The first day to list"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_parser(value_parser!(NaiveDate))
+                .help("The last day to list"),
+        )
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .default_value("100")
+                .value_parser(value_parser!(i64))
+                .help("How many changes to list at most"),
+        )
+        .get_matches();
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let ledger_id = match matches.get_one::<String>("ledger") {
+        Some(slug) => Some(
+            ledgers::table
+                .filter(ledgers::slug.eq(slug))
+                .select(ledgers::id)
+                .first::<i32>(conn)
+                .optional()?
+                .ok_or_else(|| format!("No such ledger: {slug}"))?,
+        ),
+        None => None,
+    };
+    let user_id = match matches.get_one::<String>("user") {
+        Some(username) => Some(
+            users::table
+                .filter(users::username.eq(username))
+                .select(users::id)
+                .first::<i32>(conn)
+                .optional()?
+                .ok_or_else(|| format!("No such user: {username}"))?,
+        ),
+        None => None,
+    };
+
+    let filter = AuditFilter {
+        ledger_id,
+        user_id,
+        source: matches.get_one::<Source>("source").copied(),
+        action: matches.get_one::<Action>("action").copied(),
+        object: matches.get_one::<String>("object").cloned(),
+        from: matches.get_one::<NaiveDate>("from").copied(),
+        to: matches.get_one::<NaiveDate>("to").copied(),
+    };
+    let entries = audit_entries(conn, &filter, *matches.get_one::<i64>("limit").unwrap())?;
+
+    for entry in entries {
+        let username = match entry.user_id {
+            Some(user_id) => users::table
+                .find(user_id)
+                .select(users::username)
+                .first::<String>(conn)?,
+            None => "-".to_owned(),
+        };
+        println!(
+            "{}\t{}\t{}\t{} {}\t{}",
+            entry.time, username, entry.source, entry.action, entry.object, entry.payload
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use clap::{value_parser, Arg, Command};
+use diesel::{Connection, SqliteConnection};
+use sharebill::backup::{backup, check_supported, prune};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("backup")
+        .about("Writes a backup of the database, which can be done while the web server runs")
+        .arg(
+            Arg::new("dir")
+                .long("dir")
+                .default_value("backups")
+                .value_parser(value_parser!(PathBuf))
+                .help("Directory to write the backup to"),
+        )
+        .arg(
+            Arg::new("keep")
+                .long("keep")
+                .default_value("7")
+                .value_parser(value_parser!(usize))
+                .help("How many backups to keep, deleting the oldest ones"),
+        )
+        .get_matches();
+    let dir = matches.get_one::<PathBuf>("dir").unwrap();
+    let keep = *matches.get_one::<usize>("keep").unwrap();
+
+    check_supported()?;
+    let conn = &mut SqliteConnection::establish(&sharebill::database_url())?;
+
+    let path = backup(conn, dir, chrono::Utc::now().naive_utc())?;
+    println!("Wrote {}", path.display());
+    for path in prune(dir, keep)? {
+        println!("Deleted {}", path.display());
+    }
+
+    Ok(())
+}
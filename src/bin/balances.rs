@@ -1,50 +1,70 @@
-use std::collections::HashMap;
-
+use clap::{Arg, ArgAction, Command};
 use diesel::prelude::*;
-use num::{BigInt, ToPrimitive, Zero};
-use sharebill::{
-    rational::{sum_rat, Rational},
-    schema::{credits, debits},
-};
+use num::{BigRational, Zero};
+use sharebill::{budgets::budget_statuses, format::AmountFormat, models::Ledger, schema::ledgers};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let conn = &mut sharebill::establish_connection("test.db");
-
-    let cre = credits::table
-        .group_by(credits::account)
-        .select((credits::account, sum_rat(credits::value)))
-        .load::<(String, Rational)>(conn)?;
-    let deb = debits::table
-        .group_by(debits::account)
-        .select((debits::account, sum_rat(debits::value)))
-        .load::<(String, Rational)>(conn)?;
-
-    let mut balances = cre
-        .into_iter()
-        .map(|(account, value)| {
-            let v = value.into_inner();
-            let v = num::BigRational::from((
-                BigInt::from(v.numer().clone()),
-                BigInt::from(v.denom().clone()),
-            ));
-            (account, v)
-        })
-        .collect::<HashMap<_, _>>();
-
-    for (account, value) in deb {
-        let v = value.into_inner();
-        let v = num::BigRational::from((
-            BigInt::from(v.numer().clone()),
-            BigInt::from(v.denom().clone()),
-        ));
-        *balances.entry(account).or_default() -= v;
+    let matches = Command::new("balances")
+        .about("Displays the account balances in a ledger")
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .default_value("default")
+                .help("Slug of the ledger to display"),
+        )
+        .arg(
+            Arg::new("budgets")
+                .long("budgets")
+                .action(ArgAction::SetTrue)
+                .help("Display how much of each budget is spent instead"),
+        )
+        .get_matches();
+    let slug = matches.get_one::<String>("ledger").unwrap();
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let ledger = ledgers::table
+        .filter(ledgers::slug.eq(slug))
+        .first::<Ledger>(conn)
+        .optional()?
+        .ok_or_else(|| format!("No such ledger: {slug}"))?;
+
+    let format = AmountFormat::from(&ledger);
+    let amount = |value: &BigRational, currency: &str| {
+        if currency == ledger.base_currency {
+            format.amount(value)
+        } else {
+            format!("{} {currency}", format.number(value))
+        }
+    };
+
+    if matches.get_flag("budgets") {
+        let today = chrono::Utc::now().date_naive();
+        for status in budget_statuses(conn, ledger.id, today)? {
+            let budget = &status.budget;
+            println!(
+                "{} {}: {} of {} per {}, {} to {} ({}%){}",
+                budget.target,
+                budget.name,
+                amount(&status.spent, &budget.currency),
+                amount(&budget.amount.to_big_rational(), &budget.currency),
+                budget.period,
+                status.from,
+                status.to,
+                status.percent(),
+                if status.exceeded() { ", exceeded" } else { "" },
+            );
+        }
+        return Ok(());
     }
 
-    for (account, balance) in balances
+    let balances = sharebill::ledger::Ledger::new(conn, ledger.id).balances(None)?;
+
+    for ((account, currency), balance) in balances
         .into_iter()
         .filter(|(_, balance)| !balance.is_zero())
     {
-        println!("{account}: {:.2}", balance.to_f64().unwrap());
+        println!("{account}: {}", amount(&balance, &currency));
     }
 
     Ok(())
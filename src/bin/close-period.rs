@@ -0,0 +1,44 @@
+use chrono::NaiveDate;
+use clap::{value_parser, Arg, Command};
+use diesel::prelude::*;
+use sharebill::{audit::Actor, closing::close_period, models::Ledger, schema::ledgers};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("close-period")
+        .about("Closes the posts in a ledger before a date")
+        .long_about(
+            "Closes the posts in a ledger before a date, so they can no longer be \
+             added, changed or deleted. The balances of all accounts are carried \
+             over by a closing post and an opening post, and balances are summed \
+             from the last closing from then on.",
+        )
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .default_value("default")
+                .help("Slug of the ledger to close"),
+        )
+        .arg(
+            Arg::new("date")
+                .required(true)
+                .value_parser(value_parser!(NaiveDate))
+                .help("The first date that stays open"),
+        )
+        .get_matches();
+    let slug = matches.get_one::<String>("ledger").unwrap();
+    let date = *matches.get_one::<NaiveDate>("date").unwrap();
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let ledger = ledgers::table
+        .filter(ledgers::slug.eq(slug))
+        .first::<Ledger>(conn)
+        .optional()?
+        .ok_or_else(|| format!("No such ledger: {slug}"))?;
+
+    let today = chrono::Utc::now().date_naive();
+    close_period(conn, ledger.id, date, today, Actor::cli())?;
+    println!("Closed the posts in {slug} before {date}");
+
+    Ok(())
+}
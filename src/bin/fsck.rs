@@ -0,0 +1,43 @@
+use clap::{Arg, ArgAction, Command};
+use sharebill::fsck::{check, repair};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("fsck")
+        .about("Checks the database for inconsistent data")
+        .long_about(
+            "Checks the database for inconsistent data: unbalanced or empty posts, \
+             credits and debits of posts that do not exist, zero values, empty account \
+             names, unreadable or non-canonical values and posts revised before their \
+             time.",
+        )
+        .arg(
+            Arg::new("repair")
+                .long("repair")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Repair what can be repaired without losing anything, by deleting \
+                     orphaned and zero credits and debits and rewriting non-canonical values",
+                ),
+        )
+        .get_matches();
+    let repairing = matches.get_flag("repair");
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let problems = check(conn)?;
+    let mut remaining = 0;
+    for problem in &problems {
+        if repairing && problem.is_repairable() && repair(conn, problem)? {
+            println!("{problem} (repaired)");
+        } else {
+            println!("{problem}");
+            remaining += 1;
+        }
+    }
+
+    if remaining > 0 {
+        return Err(format!("{remaining} problems found").into());
+    }
+
+    Ok(())
+}
@@ -1,50 +1,21 @@
+use clap::{Arg, Command};
 use diesel::prelude::*;
-use diesel::result::Error;
 use num::bigint::ToBigUint;
-use regex::Regex;
 use serde::{
     de::{self, MapAccess, Unexpected, Visitor},
     Deserializer,
 };
 use sharebill::{
-    models::{NewCredit, NewDebit, NewTx, Tx},
+    amount::parse_amount,
+    audit::Actor,
+    ledger::{self, LedgerError, Postings},
+    models::Ledger,
     rational::Rational,
 };
-use std::sync::OnceLock;
 use std::{collections::BTreeMap, fmt, marker::PhantomData};
 
 struct RationalVisitor;
 
-pub fn parse_mixed_number(number: &str) -> Result<Rational, String> {
-    use std::str::FromStr;
-
-    static MIXED_NUMBER: OnceLock<Regex> = OnceLock::new();
-
-    let mixed_number =
-        MIXED_NUMBER.get_or_init(|| Regex::new(r"^((-)?(\d+)( (\d+/\d+))?|(-?\d+/\d+))$").unwrap());
-
-    match mixed_number.captures(number) {
-        Some(groups) => {
-            let mut result = Rational::from_str("0").unwrap();
-            if let Some(x) = groups.get(3) {
-                result = result + Rational::from_str(x.as_str()).unwrap();
-            }
-            if let Some(x) = groups.get(5) {
-                result = result + Rational::from_str(x.as_str()).unwrap();
-            }
-            if let Some(x) = groups.get(6) {
-                result = result + Rational::from_str(x.as_str()).unwrap();
-            }
-            if let Some(_) = groups.get(2) {
-                // result = -result;
-                return Err("Cannot import negative numbers".to_owned());
-            }
-            Ok(result)
-        }
-        None => Err("Not a valid mixed number".to_string()),
-    }
-}
-
 impl<'de> Visitor<'de> for RationalVisitor {
     type Value = Rational;
 
@@ -56,7 +27,7 @@ impl<'de> Visitor<'de> for RationalVisitor {
     where
         E: de::Error,
     {
-        parse_mixed_number(s).map_err(|_| de::Error::invalid_value(Unexpected::Str(s), &self))
+        parse_amount(s).map_err(|_| de::Error::invalid_value(Unexpected::Str(s), &self))
     }
 
     fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
@@ -182,6 +153,17 @@ struct AllDocs {
 }
 
 fn main() {
+    let matches = Command::new("import-couchdb")
+        .about("Imports a CouchDB _all_docs dump from stdin into a ledger")
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .default_value("default")
+                .help("Slug of the ledger to import into"),
+        )
+        .get_matches();
+    let slug = matches.get_one::<String>("ledger").unwrap();
+
     // 1. Input JSON from somewhere (stdin? file?)
     let input = std::io::stdin().lock();
 
@@ -191,55 +173,38 @@ fn main() {
     println!("{docs:?}");
 
     // 3. Shove it into the database
-    let conn = &mut sharebill::establish_connection("test.db");
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    sharebill::transaction(conn, |conn| -> Result<_, LedgerError> {
+        use sharebill::schema::ledgers;
 
-    conn.transaction::<_, Error, _>(|conn| {
-        use sharebill::schema::{credits, debits, txs};
+        let ledger = ledgers::table
+            .filter(ledgers::slug.eq(slug))
+            .first::<Ledger>(conn)?;
 
         for row in docs.rows {
             let meta = row.value.meta;
             let transaction = row.value.transaction;
 
-            let new_tx = NewTx {
-                tx_time: meta.timestamp.naive_utc(),
-                rev_time: meta.timestamp.naive_utc(),
-                description: &meta.description,
+            let postings = |items: Vec<(String, Rational)>| {
+                let mut postings = Postings::new();
+                for (account, value) in items {
+                    *postings
+                        .entry((account, ledger.base_currency.clone()))
+                        .or_default() += value;
+                }
+                postings
             };
-
-            let tx: Tx = diesel::insert_into(txs::table)
-                .values(&new_tx)
-                .get_result(conn)
-                .expect("Error saving transaction");
-
-            let new_credits: Vec<NewCredit> = transaction
-                .credits
-                .iter()
-                .map(|credit| NewCredit {
-                    tx_id: tx.id,
-                    account: &credit.0,
-                    value: credit.1.clone(),
-                })
-                .collect();
-
-            diesel::insert_into(credits::table)
-                .values(&new_credits)
-                .execute(conn)
-                .expect("Error saving transaction");
-
-            let new_debits: Vec<NewDebit> = transaction
-                .debits
-                .iter()
-                .map(|debit| NewDebit {
-                    tx_id: tx.id,
-                    account: &debit.0,
-                    value: debit.1.clone(),
-                })
-                .collect();
-
-            diesel::insert_into(debits::table)
-                .values(&new_debits)
-                .execute(conn)
-                .expect("Error saving transaction");
+            let transaction = ledger::Transaction::new(
+                meta.timestamp.naive_utc(),
+                meta.description,
+                "",
+                postings(transaction.debits),
+                postings(transaction.credits),
+            )?;
+
+            ledger::Ledger::new(conn, ledger.id)
+                .insert_transaction(Actor::importer(), &transaction)?;
         }
 
         Ok(())
@@ -0,0 +1,105 @@
+use std::io::{BufRead, BufReader};
+
+use clap::{Arg, Command};
+use diesel::prelude::*;
+use diesel::result::Error;
+use sharebill::{
+    audit::{self, Action, Actor},
+    currency::{parse_currency, parse_rate},
+    models::{Ledger, NewExchangeRate},
+    schema::{exchange_rates, ledgers},
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("import-rates")
+        .about("Imports exchange rates to a ledger from a file")
+        .long_about(
+            "Imports exchange rates to a ledger from a file with one rate per line, \
+             such as \"2026-10-18,EUR,11.62\". The rate is the price of one unit of \
+             the currency in the base currency of the ledger. Empty lines and lines \
+             starting with # are skipped, and existing rates for the same currency \
+             and date are replaced.",
+        )
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .default_value("default")
+                .help("Slug of the ledger to import the rates to"),
+        )
+        .arg(Arg::new("file").required(true))
+        .get_matches();
+    let slug = matches.get_one::<String>("ledger").unwrap();
+    let file = matches.get_one::<String>("file").unwrap();
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let ledger = ledgers::table
+        .filter(ledgers::slug.eq(slug))
+        .first::<Ledger>(conn)
+        .optional()?
+        .ok_or_else(|| format!("No such ledger: {slug}"))?;
+
+    let mut rates = vec![];
+    for (index, line) in BufReader::new(std::fs::File::open(file)?)
+        .lines()
+        .enumerate()
+    {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |what: &str| format!("{file}:{}: {what}", index + 1);
+        let [date, currency, rate] = line.splitn(3, ',').collect::<Vec<_>>()[..] else {
+            return Err(invalid("expected date,currency,rate").into());
+        };
+        let date = date
+            .trim()
+            .parse::<chrono::NaiveDate>()
+            .map_err(|_| invalid("invalid date"))?;
+        let currency = parse_currency(currency).map_err(|err| invalid(&err.to_string()))?;
+        if currency == ledger.base_currency {
+            return Err(invalid("rate for the base currency").into());
+        }
+        let rate = parse_rate(rate).map_err(|err| invalid(&err.to_string()))?;
+
+        rates.push((date, currency, rate));
+    }
+
+    conn.transaction::<_, Error, _>(|conn| {
+        for (date, currency, rate) in &rates {
+            diesel::insert_into(exchange_rates::table)
+                .values(&NewExchangeRate {
+                    ledger_id: ledger.id,
+                    currency,
+                    date: *date,
+                    rate: rate.clone(),
+                })
+                .on_conflict((
+                    exchange_rates::ledger_id,
+                    exchange_rates::currency,
+                    exchange_rates::date,
+                ))
+                .do_update()
+                .set(exchange_rates::rate.eq(rate))
+                .execute(conn)?;
+
+            let payload = serde_json::json!({ "currency": currency, "date": date, "rate": rate });
+            audit::record(
+                conn,
+                Actor::importer(),
+                Some(ledger.id),
+                Action::Import,
+                "rate",
+                payload,
+            )?;
+        }
+
+        Ok(())
+    })?;
+
+    println!("Imported {} rates", rates.len());
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use chrono::{Datelike, NaiveDate};
+use clap::{value_parser, Arg, Command};
+use diesel::prelude::*;
+use sharebill::{format::AmountFormat, models::Ledger, schema::ledgers, tags::expense_report};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("report")
+        .about("Displays spending per tag and per account in a ledger")
+        .long_about(
+            "Displays the sum of the debits per tag and per account in a ledger \
+             over a period. Transactions with several tags count towards each of \
+             them.",
+        )
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .default_value("default")
+                .help("Slug of the ledger to report on"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_parser(value_parser!(NaiveDate))
+                .help("First date of the period, defaults to the start of the year"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_parser(value_parser!(NaiveDate))
+                .help("Last date of the period, defaults to today"),
+        )
+        .get_matches();
+    let slug = matches.get_one::<String>("ledger").unwrap();
+    let to = matches
+        .get_one::<NaiveDate>("to")
+        .copied()
+        .unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let from = matches
+        .get_one::<NaiveDate>("from")
+        .copied()
+        .unwrap_or_else(|| NaiveDate::from_ymd_opt(to.year(), 1, 1).unwrap());
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let ledger = ledgers::table
+        .filter(ledgers::slug.eq(slug))
+        .first::<Ledger>(conn)
+        .optional()?
+        .ok_or_else(|| format!("No such ledger: {slug}"))?;
+
+    let report = expense_report(conn, ledger.id, from, to)?;
+
+    let format = AmountFormat::from(&ledger);
+    let amount = |value, currency: &str| {
+        if currency == ledger.base_currency {
+            format.amount(value)
+        } else {
+            format!("{} {currency}", format.number(value))
+        }
+    };
+
+    println!("Spending from {from} to {to}");
+    println!();
+    println!("By tag:");
+    for (tag, currency, sum) in &report.by_tag {
+        let tag = tag.as_deref().unwrap_or("(no tag)");
+        println!("{tag}: {}", amount(sum, currency));
+    }
+    println!();
+    println!("By account:");
+    for (account, currency, sum) in &report.by_account {
+        println!("{account}: {}", amount(sum, currency));
+    }
+
+    Ok(())
+}
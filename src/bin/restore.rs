@@ -0,0 +1,28 @@
+use std::path::{Path, PathBuf};
+
+use clap::{value_parser, Arg, Command};
+use sharebill::backup::{check_supported, restore};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("restore")
+        .about("Replaces the database with a backup")
+        .long_about(
+            "Replaces the database with a backup, after checking that the backup is \
+             intact and not from a newer version. The replaced database is kept with \
+             .before-restore appended to its name. Stop the web server first.",
+        )
+        .arg(
+            Arg::new("backup")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .help("The backup to restore"),
+        )
+        .get_matches();
+    let backup = matches.get_one::<PathBuf>("backup").unwrap();
+
+    check_supported()?;
+    restore(backup, Path::new(&sharebill::database_url()))?;
+    println!("Restored {}", backup.display());
+
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use clap::{Arg, Command};
+use sharebill::audit::Actor;
+use sharebill::recurring::materialize_all;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("run-recurring")
+        .about("Posts the recurring transactions that are due, in all ledgers")
+        .long_about(
+            "Posts the recurring transactions that are due, in all ledgers. Each \
+             occurrence is only posted once, so this can safely be run as often as \
+             needed, such as from cron, also while the web server is running.",
+        )
+        .arg(
+            Arg::new("date")
+                .long("date")
+                .value_parser(clap::value_parser!(chrono::NaiveDate))
+                .help("Post what is due up to and including this date instead of today"),
+        )
+        .get_matches();
+    let today = matches
+        .get_one::<chrono::NaiveDate>("date")
+        .copied()
+        .unwrap_or_else(|| chrono::Utc::now().date_naive());
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let materialized = materialize_all(conn, today, Actor::cli())?;
+    for (id, err) in &materialized.failed {
+        eprintln!("Error posting recurring transaction {id}: {err}");
+    }
+    println!("Posted {} transactions", materialized.created);
+
+    if !materialized.failed.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -1,42 +1,45 @@
+use clap::{Arg, Command};
 use diesel::prelude::*;
-use sharebill::schema::{credits, debits, txs};
+use sharebill::schema::ledgers;
 
 fn main() {
-    let conn = &mut sharebill::establish_connection("test.db");
-
-    let results = txs::table
-        .order(txs::tx_time.desc())
-        .limit(10)
-        .load::<sharebill::models::Tx>(conn)
+    let matches = Command::new("transactions")
+        .about("Displays the latest transactions in a ledger")
+        .arg(
+            Arg::new("ledger")
+                .long("ledger")
+                .default_value("default")
+                .help("Slug of the ledger to display"),
+        )
+        .get_matches();
+    let slug = matches.get_one::<String>("ledger").unwrap();
+
+    let conn = &mut sharebill::establish_connection(&sharebill::database_url());
+
+    let ledger = ledgers::table
+        .filter(ledgers::slug.eq(slug))
+        .first::<sharebill::models::Ledger>(conn)
+        .unwrap_or_else(|_| panic!("No such ledger: {}", slug));
+
+    let results = sharebill::ledger::Ledger::new(conn, ledger.id)
+        .list_transactions(None, None, None, 10)
         .expect("Error loading transactions");
 
     println!("Displaying {} transactions", results.len());
-    for tx in results.iter().rev() {
-        let tx_time = chrono::DateTime::<chrono::Utc>::from_utc(tx.tx_time, chrono::Utc);
-        println!("{} : {}", tx_time.to_rfc3339(), tx.description);
+    for stored in results.iter().rev() {
+        let tx_time = chrono::DateTime::<chrono::Utc>::from_utc(stored.tx.tx_time, chrono::Utc);
+        println!("{} : {}", tx_time.to_rfc3339(), stored.tx.description);
 
         println!("  Credits:");
 
-        let items = credits::table
-            .select((credits::account, credits::value))
-            .filter(credits::tx_id.eq(tx.id))
-            .load::<sharebill::models::TxItem>(conn)
-            .expect("Error loading transactions");
-
-        for item in items {
-            println!("    {} {}", item.account, item.value);
+        for ((account, currency), value) in stored.transaction.credits() {
+            println!("    {} {} {}", account, value, currency);
         }
 
         println!("  Debits:");
 
-        let items = debits::table
-            .select((debits::account, debits::value))
-            .filter(debits::tx_id.eq(tx.id))
-            .load::<sharebill::models::TxItem>(conn)
-            .expect("Error loading transactions");
-
-        for item in items {
-            println!("    {} {}", item.account, item.value);
+        for ((account, currency), value) in stored.transaction.debits() {
+            println!("    {} {} {}", account, value, currency);
         }
     }
 }
@@ -0,0 +1,548 @@
+//! JSON endpoints for scripts and bots. Requests authenticate with a personal
+//! API token in an `Authorization: Bearer <token>` header instead of a session
+//! cookie, so they need no CSRF token either.
+
+use std::collections::HashMap;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use futures::future::LocalBoxFuture;
+use serde_derive::{Deserialize, Serialize};
+use sharebill::audit::Actor;
+use sharebill::auth::{hash_token, Access};
+use sharebill::ledger::{
+    resolve_currencies, LedgerError, Postings, StoredTransaction, Transaction,
+};
+use sharebill::models::{Ledger, User};
+use sharebill::rational::Rational;
+use sharebill::schema::{api_token_ledgers, api_tokens, users};
+use sharebill::DbConnection;
+use thiserror::Error;
+
+use crate::auth::ledger_role;
+use crate::events::Broadcaster;
+use crate::{find_ledger, ledger_balances};
+use crate::{DbPool, ValidationError};
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("missing or invalid API token")]
+    Unauthorized,
+    #[error("the API token does not have access to do this")]
+    Forbidden,
+    #[error("not found")]
+    NotFound,
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+    #[error("internal server error")]
+    Internal,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status_code());
+        if let ApiError::Unauthorized = self {
+            res.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        res.json(ErrorBody {
+            error: self.to_string(),
+        })
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::NotFound => ApiError::NotFound,
+            _ => ApiError::Internal,
+        }
+    }
+}
+
+impl From<LedgerError> for ApiError {
+    fn from(err: LedgerError) -> Self {
+        match err {
+            LedgerError::NotFound => ApiError::NotFound,
+            LedgerError::Db(_) => ApiError::Internal,
+            err => ValidationError::from(err).into(),
+        }
+    }
+}
+
+impl From<actix_web::error::BlockingError> for ApiError {
+    fn from(_: actix_web::error::BlockingError) -> Self {
+        ApiError::Internal
+    }
+}
+
+/// The owner of the API token given in the request. Using the token updates
+/// its last used timestamp.
+pub struct ApiUser {
+    pub user: User,
+    pub token_id: i32,
+}
+
+impl FromRequest for ApiUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<DbPool>>().cloned();
+        let token = bearer_token(req);
+
+        Box::pin(async move {
+            let Some(pool) = pool else {
+                return Err(ApiError::Unauthorized);
+            };
+            ApiUser::authenticate(pool, token).await
+        })
+    }
+}
+
+impl ApiUser {
+    /// Looks up the owner of `token`, for endpoints that also take the token
+    /// elsewhere than in the header
+    pub async fn authenticate(
+        pool: web::Data<DbPool>,
+        token: Option<String>,
+    ) -> Result<Self, ApiError> {
+        let Some(token) = token else {
+            return Err(ApiError::Unauthorized);
+        };
+
+        let api_user = web::block(move || -> Result<_, diesel::result::Error> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let found = api_tokens::table
+                .inner_join(users::table)
+                .filter(api_tokens::token_hash.eq(hash_token(&token)))
+                .select((
+                    api_tokens::id,
+                    (users::id, users::username, users::password_hash),
+                ))
+                .first::<(i32, User)>(&mut conn)
+                .optional()?;
+
+            let Some((token_id, user)) = found else {
+                return Ok(None);
+            };
+
+            diesel::update(api_tokens::table.find(token_id))
+                .set(api_tokens::last_used_time.eq(chrono::Utc::now().naive_utc()))
+                .execute(&mut conn)?;
+
+            Ok(Some(ApiUser { user, token_id }))
+        })
+        .await??;
+
+        api_user.ok_or(ApiError::Unauthorized)
+    }
+}
+
+/// The token of an `Authorization: Bearer <token>` header
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_owned())
+}
+
+/// Looks up the ledger with the given slug, requiring both the token to grant
+/// `access` to it and its owner to still have the matching role
+pub fn authorize(
+    conn: &mut DbConnection,
+    api_user: &ApiUser,
+    slug: &str,
+    access: Access,
+) -> Result<Ledger, ApiError> {
+    let ledger = find_ledger(conn, slug)?.ok_or(ApiError::NotFound)?;
+
+    let granted = api_token_ledgers::table
+        .find((api_user.token_id, ledger.id))
+        .select(api_token_ledgers::access)
+        .first::<Access>(conn)
+        .optional()?;
+    let user_role = ledger_role(conn, ledger.id, api_user.user.id)?;
+
+    // None, i.e. no grant or not a member, orders before everything else
+    if granted < Some(access) || user_role < Some(access.role()) {
+        return Err(ApiError::Forbidden);
+    }
+
+    Ok(ledger)
+}
+
+#[derive(Serialize)]
+struct BalanceJson {
+    account: String,
+    currency: String,
+    /// Exact balance as a fraction, positive for credit balances
+    balance: String,
+}
+
+pub async fn get_balances(
+    api_user: ApiUser,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let balances = web::block(move || -> Result<_, ApiError> {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+        let ledger = authorize(&mut conn, &api_user, &slug, Access::Read)?;
+        let balances = ledger_balances(&mut conn, ledger.id)?
+            .into_iter()
+            .map(|(account, currency, balance)| BalanceJson {
+                account,
+                currency,
+                balance: balance.to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(balances)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(balances))
+}
+
+#[derive(Serialize)]
+struct PostingJson {
+    account: String,
+    currency: String,
+    amount: Rational,
+}
+
+#[derive(Serialize)]
+struct TransactionJson {
+    id: i32,
+    when: DateTime<Utc>,
+    what: String,
+    rev_time: DateTime<Utc>,
+    tags: Vec<String>,
+    debits: Vec<PostingJson>,
+    credits: Vec<PostingJson>,
+}
+
+impl From<StoredTransaction> for TransactionJson {
+    fn from(StoredTransaction { tx, transaction }: StoredTransaction) -> Self {
+        let postings = |postings: &Postings| {
+            postings
+                .iter()
+                .map(|((account, currency), amount)| PostingJson {
+                    account: account.clone(),
+                    currency: currency.clone(),
+                    amount: amount.clone(),
+                })
+                .collect()
+        };
+
+        TransactionJson {
+            id: tx.id,
+            when: tx.tx_time.and_local_timezone(Utc).unwrap(),
+            what: tx.description,
+            rev_time: tx.rev_time.and_local_timezone(Utc).unwrap(),
+            tags: transaction.tags().to_vec(),
+            debits: postings(transaction.debits()),
+            credits: postings(transaction.credits()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionsQuery {
+    limit: Option<i64>,
+}
+
+pub async fn get_transactions(
+    api_user: ApiUser,
+    slug: web::Path<String>,
+    query: web::Query<TransactionsQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let transactions = web::block(move || -> Result<_, ApiError> {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+        let ledger = authorize(&mut conn, &api_user, &slug, Access::Read)?;
+        let transactions = sharebill::ledger::Ledger::new(&mut conn, ledger.id)
+            .list_transactions(None, None, None, limit)?
+            .into_iter()
+            .map(TransactionJson::from)
+            .collect::<Vec<_>>();
+
+        Ok(transactions)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(transactions))
+}
+
+pub async fn get_transaction(
+    api_user: ApiUser,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let (slug, id) = path.into_inner();
+
+    let transaction = web::block(move || -> Result<_, ApiError> {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+        let ledger = authorize(&mut conn, &api_user, &slug, Access::Read)?;
+        let stored = sharebill::ledger::Ledger::new(&mut conn, ledger.id).get_transaction(id)?;
+
+        Ok(TransactionJson::from(stored))
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(transaction))
+}
+
+#[derive(Debug, Deserialize)]
+struct NewPostingJson {
+    account: String,
+    amount: Rational,
+    /// Defaults to the currency of the transaction
+    currency: Option<String>,
+}
+
+/// Postings are given either as a list, or as a map from account to amount
+/// when they are all in the currency of the transaction
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum NewPostingsJson {
+    List(Vec<NewPostingJson>),
+    Map(HashMap<String, Rational>),
+}
+
+impl NewPostingsJson {
+    fn postings(self, currency: &str) -> Postings {
+        let mut postings = Postings::new();
+        let items: Vec<(String, String, Rational)> = match self {
+            NewPostingsJson::List(list) => list
+                .into_iter()
+                .map(|p| {
+                    (
+                        p.account,
+                        p.currency.unwrap_or_else(|| currency.to_owned()),
+                        p.amount,
+                    )
+                })
+                .collect(),
+            NewPostingsJson::Map(map) => map
+                .into_iter()
+                .map(|(account, amount)| (account, currency.to_owned(), amount))
+                .collect(),
+        };
+        for (account, currency, amount) in items {
+            *postings.entry((account, currency)).or_default() += amount;
+        }
+        postings
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewTransactionJson {
+    /// Defaults to the current time
+    when: Option<DateTime<Utc>>,
+    what: String,
+    /// Defaults to the base currency of the ledger
+    currency: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    debits: NewPostingsJson,
+    credits: NewPostingsJson,
+}
+
+pub async fn post_transaction(
+    api_user: ApiUser,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Json(doc): web::Json<NewTransactionJson>,
+) -> Result<HttpResponse, ApiError> {
+    let slug = slug.into_inner();
+    let changed = slug.clone();
+    let transaction = web::block(move || -> Result<_, ApiError> {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+        let ledger = authorize(&mut conn, &api_user, &slug, Access::ReadWrite)?;
+
+        let currency = doc.currency.unwrap_or_default();
+        let transaction = Transaction::new(
+            doc.when.unwrap_or_else(Utc::now).naive_utc(),
+            doc.what,
+            &doc.tags.join(","),
+            resolve_currencies(doc.debits.postings(&currency), &ledger.base_currency)
+                .map_err(LedgerError::from)?,
+            resolve_currencies(doc.credits.postings(&currency), &ledger.base_currency)
+                .map_err(LedgerError::from)?,
+        )?;
+
+        let mut ledger = sharebill::ledger::Ledger::new(&mut conn, ledger.id);
+        let id = ledger.insert_transaction(Actor::api(api_user.user.id), &transaction)?;
+
+        Ok(TransactionJson::from(ledger.get_transaction(id)?))
+    })
+    .await??;
+    broadcaster.changed(&changed);
+
+    Ok(HttpResponse::Created().json(transaction))
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+pub(crate) mod test {
+    use sharebill::auth::{generate_token, Role};
+    use sharebill::models::{NewApiToken, NewApiTokenLedger, NewLedger, NewLedgerMember, NewUser};
+    use sharebill::schema::{ledger_members, ledgers};
+
+    use super::*;
+
+    /// Adds a ledger, and returns its id
+    pub(crate) fn add_ledger(conn: &mut DbConnection, slug: &str) -> i32 {
+        diesel::insert_into(ledgers::table)
+            .values(&NewLedger { slug, name: slug })
+            .returning(ledgers::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Adds a user with a role in each of `roles`, and a token of theirs with
+    /// the access in each of `grants`, both by ledger id. Returns the token
+    /// along with its owner.
+    pub(crate) fn add_token(
+        conn: &mut DbConnection,
+        username: &str,
+        roles: &[(i32, Role)],
+        grants: &[(i32, Access)],
+    ) -> (String, ApiUser) {
+        let user = diesel::insert_into(users::table)
+            .values(&NewUser {
+                username,
+                password_hash: "",
+            })
+            .returning((users::id, users::username, users::password_hash))
+            .get_result::<User>(conn)
+            .unwrap();
+        for &(ledger_id, role) in roles {
+            diesel::insert_into(ledger_members::table)
+                .values(&NewLedgerMember {
+                    ledger_id,
+                    user_id: user.id,
+                    role,
+                })
+                .execute(conn)
+                .unwrap();
+        }
+
+        let token = generate_token();
+        let token_id = diesel::insert_into(api_tokens::table)
+            .values(&NewApiToken {
+                user_id: user.id,
+                name: "test",
+                token_hash: hash_token(&token),
+                created_time: Utc::now().naive_utc(),
+            })
+            .returning(api_tokens::id)
+            .get_result::<i32>(conn)
+            .unwrap();
+        for &(ledger_id, access) in grants {
+            diesel::insert_into(api_token_ledgers::table)
+                .values(&NewApiTokenLedger {
+                    token_id,
+                    ledger_id,
+                    access,
+                })
+                .execute(conn)
+                .unwrap();
+        }
+
+        (token, ApiUser { user, token_id })
+    }
+
+    #[test]
+    fn read_only_tokens_cannot_write() {
+        let mut conn = sharebill::establish_connection(":memory:");
+        let (_, alice) = add_token(
+            &mut conn,
+            "alice",
+            &[(1, Role::Member)],
+            &[(1, Access::Read)],
+        );
+
+        assert!(authorize(&mut conn, &alice, "default", Access::Read).is_ok());
+        assert!(matches!(
+            authorize(&mut conn, &alice, "default", Access::ReadWrite),
+            Err(ApiError::Forbidden)
+        ));
+    }
+
+    #[test]
+    fn tokens_reach_only_the_granted_ledgers() {
+        let mut conn = sharebill::establish_connection(":memory:");
+        let other = add_ledger(&mut conn, "other");
+        let (_, alice) = add_token(
+            &mut conn,
+            "alice",
+            &[(1, Role::Admin), (other, Role::Admin)],
+            &[(1, Access::ReadWrite)],
+        );
+
+        assert!(authorize(&mut conn, &alice, "default", Access::ReadWrite).is_ok());
+        assert!(matches!(
+            authorize(&mut conn, &alice, "other", Access::Read),
+            Err(ApiError::Forbidden)
+        ));
+        assert!(matches!(
+            authorize(&mut conn, &alice, "missing", Access::Read),
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn grants_are_limited_by_the_current_role() {
+        let mut conn = sharebill::establish_connection(":memory:");
+        let (_, alice) = add_token(
+            &mut conn,
+            "alice",
+            &[(1, Role::Viewer)],
+            &[(1, Access::ReadWrite)],
+        );
+
+        assert!(authorize(&mut conn, &alice, "default", Access::Read).is_ok());
+        assert!(matches!(
+            authorize(&mut conn, &alice, "default", Access::ReadWrite),
+            Err(ApiError::Forbidden)
+        ));
+
+        // Leaving the ledger takes the access of the token with it
+        diesel::delete(ledger_members::table.find((1, alice.user.id)))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(matches!(
+            authorize(&mut conn, &alice, "default", Access::Read),
+            Err(ApiError::Forbidden)
+        ));
+    }
+}
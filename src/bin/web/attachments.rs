@@ -0,0 +1,224 @@
+//! Uploading, downloading and deleting the attachments of a post
+
+use actix_multipart::{Field, Multipart};
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Redirect;
+use actix_web::{web, HttpResponse, Responder};
+use diesel::prelude::*;
+use futures::TryStreamExt;
+use sharebill::attachments::{
+    check_attachment, insert_attachment, is_image, AttachmentError, MAX_ATTACHMENT_SIZE,
+};
+use sharebill::audit::{self, Action, Actor};
+use sharebill::auth::Role;
+use sharebill::closing::check_tx_open;
+use sharebill::ledger::LedgerError;
+use sharebill::models::Attachment;
+use sharebill::schema::{attachments, txs};
+use sharebill::DbConnection;
+
+use crate::auth::CurrentUser;
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::events::Broadcaster;
+use crate::{load_ledger, map_db_error, map_ledger_error, DbPool, ValidationError};
+
+/// An attachment as listed on the post page
+pub struct AttachmentEntry {
+    pub id: i32,
+    pub filename: String,
+    pub is_image: bool,
+    pub size: String,
+}
+
+impl From<Attachment> for AttachmentEntry {
+    fn from(attachment: Attachment) -> Self {
+        AttachmentEntry {
+            id: attachment.id,
+            is_image: is_image(&attachment.content_type),
+            size: format_size(attachment.size),
+            filename: attachment.filename,
+        }
+    }
+}
+
+/// A file size in bytes, kB or MB
+fn format_size(size: i32) -> String {
+    match size {
+        0..=999 => format!("{size} bytes"),
+        1000..=999_999 => format!("{:.0} kB", f64::from(size) / 1e3),
+        _ => format!("{:.1} MB", f64::from(size) / 1e6),
+    }
+}
+
+/// Reads a multipart field, giving up once it is longer than `limit`
+async fn read_field(field: &mut Field, limit: usize) -> actix_web::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.try_next().await? {
+        if data.len() + chunk.len() > limit {
+            return Err(ValidationError::from(AttachmentError::TooLarge).into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Checks that the post exists in the ledger
+fn find_tx(conn: &mut DbConnection, ledger_id: i32, id: i32) -> QueryResult<i32> {
+    txs::table
+        .find(id)
+        .filter(txs::ledger_id.eq(ledger_id))
+        .select(txs::id)
+        .first(conn)
+}
+
+pub async fn upload_attachment(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    mut payload: Multipart,
+) -> actix_web::Result<impl Responder> {
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+
+    let mut csrf_token = None;
+    let mut file = None;
+    while let Some(mut field) = payload.try_next().await? {
+        match field.name() {
+            Some("csrf_token") => {
+                let token = read_field(&mut field, 100).await?;
+                csrf_token = Some(String::from_utf8_lossy(&token).into_owned());
+            }
+            Some("file") => {
+                let filename = field
+                    .content_disposition()
+                    .and_then(|disposition| disposition.get_filename())
+                    .unwrap_or_default()
+                    .to_owned();
+                let data = read_field(&mut field, MAX_ATTACHMENT_SIZE).await?;
+                file = Some((filename, data));
+            }
+            _ => {}
+        }
+    }
+    csrf.verify(csrf_token.as_deref().unwrap_or_default())?;
+
+    let (filename, data) = file.ok_or(ValidationError::from(AttachmentError::Empty))?;
+    let content_type = check_attachment(&data).map_err(ValidationError::from)?;
+
+    web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        conn.transaction::<_, LedgerError, _>(|conn| {
+            find_tx(conn, ledger.id, id)?;
+            // Posts in closed periods are locked, attachments included
+            check_tx_open(conn, id)?;
+            let attachment_id =
+                insert_attachment(conn, id, &filename, content_type, &data, Some(user.id))?;
+
+            audit::record(
+                conn,
+                Actor::web(user.id),
+                Some(ledger.id),
+                Action::Create,
+                "attachment",
+                serde_json::json!({
+                    "id": attachment_id,
+                    "tx_id": id,
+                    "filename": filename,
+                    "content_type": content_type,
+                    "size": data.len(),
+                }),
+            )?;
+            Ok(())
+        })
+    })
+    .await?
+    .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to(format!("../{id}")).see_other())
+}
+
+pub async fn get_attachment(
+    CurrentUser(user): CurrentUser,
+    path: web::Path<(String, i32, i32)>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let (slug, id, attachment_id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Viewer).await?;
+
+    let (filename, content_type, data) = web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        attachments::table
+            .inner_join(txs::table)
+            .filter(attachments::id.eq(attachment_id))
+            .filter(attachments::tx_id.eq(id))
+            .filter(txs::ledger_id.eq(ledger.id))
+            .select((
+                attachments::filename,
+                attachments::content_type,
+                attachments::data,
+            ))
+            .first::<(String, String, Vec<u8>)>(&mut conn)
+    })
+    .await?
+    .map_err(map_db_error)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Inline,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .insert_header((header::CACHE_CONTROL, "private, max-age=86400"))
+        // Keep documents from running scripts, should a PDF viewer allow it
+        .insert_header((header::CONTENT_SECURITY_POLICY, "sandbox"))
+        .body(data))
+}
+
+pub async fn delete_attachment(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<CsrfForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let (slug, id, attachment_id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+
+    web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        conn.transaction::<_, LedgerError, _>(|conn| {
+            find_tx(conn, ledger.id, id)?;
+            check_tx_open(conn, id)?;
+            let deleted = diesel::delete(
+                attachments::table
+                    .filter(attachments::id.eq(attachment_id))
+                    .filter(attachments::tx_id.eq(id)),
+            )
+            .execute(conn)?;
+            if deleted == 0 {
+                return Err(LedgerError::NotFound);
+            }
+
+            audit::record(
+                conn,
+                Actor::web(user.id),
+                Some(ledger.id),
+                Action::Delete,
+                "attachment",
+                serde_json::json!({ "id": attachment_id, "tx_id": id }),
+            )?;
+            Ok(())
+        })
+    })
+    .await?
+    .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to(format!("../../../{id}")).see_other())
+}
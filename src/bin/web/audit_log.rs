@@ -0,0 +1,142 @@
+//! The audit log of a ledger, with filters
+
+use std::collections::HashMap;
+
+use actix_web::{web, Responder};
+use askama::Template;
+use chrono::{NaiveDate, SecondsFormat};
+use diesel::prelude::*;
+use serde_derive::Deserialize;
+use sharebill::audit::{audit_entries, AuditFilter};
+use sharebill::auth::Role;
+use sharebill::models::{Ledger, User};
+use sharebill::schema::users;
+
+use crate::auth::CurrentUser;
+use crate::csrf::CsrfToken;
+use crate::{load_ledger, DbPool};
+
+/// How many entries are shown at most
+const AUDIT_LIMIT: i64 = 500;
+
+struct AuditRow {
+    time: String,
+    username: Option<String>,
+    source: String,
+    action: String,
+    object: String,
+    payload: String,
+}
+
+#[derive(Template)]
+#[template(path = "audit.html")]
+struct AuditTemplate {
+    csrf_token: String,
+    user: User,
+    ledger: Ledger,
+    query: AuditQuery,
+    entries: Vec<AuditRow>,
+}
+
+/// The filters of the audit page. Empty fields, as sent by the form, match
+/// everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    source: String,
+    #[serde(default)]
+    action: String,
+    #[serde(default)]
+    object: String,
+    #[serde(default)]
+    user: String,
+    #[serde(default)]
+    from: String,
+    #[serde(default)]
+    to: String,
+}
+
+/// Parses a filter field, where empty means no filter
+fn parse_field<T: std::str::FromStr>(value: &str) -> actix_web::Result<Option<T>> {
+    match value.trim() {
+        "" => Ok(None),
+        value => value
+            .parse()
+            .map(Some)
+            .map_err(|_| actix_web::error::ErrorBadRequest(format!("invalid filter: {value}"))),
+    }
+}
+
+pub async fn get_audit(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    query: web::Query<AuditQuery>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Admin).await?;
+    let query = query.into_inner();
+
+    let mut filter = AuditFilter {
+        ledger_id: Some(ledger.id),
+        source: parse_field(&query.source)?,
+        action: parse_field(&query.action)?,
+        object: parse_field(&query.object)?,
+        from: parse_field::<NaiveDate>(&query.from)?,
+        to: parse_field::<NaiveDate>(&query.to)?,
+        ..Default::default()
+    };
+    let username = parse_field::<String>(&query.user)?;
+
+    let entries = web::block(move || -> QueryResult<_> {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+        if let Some(username) = username {
+            match users::table
+                .filter(users::username.eq(username))
+                .select(users::id)
+                .first::<i32>(&mut conn)
+                .optional()?
+            {
+                Some(user_id) => filter.user_id = Some(user_id),
+                None => return Ok(vec![]),
+            }
+        }
+
+        let entries = audit_entries(&mut conn, &filter, AUDIT_LIMIT)?;
+        let usernames = users::table
+            .filter(users::id.eq_any(entries.iter().filter_map(|entry| entry.user_id)))
+            .select((users::id, users::username))
+            .load::<(i32, String)>(&mut conn)?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| AuditRow {
+                time: entry
+                    .time
+                    .and_local_timezone(chrono::Utc)
+                    .unwrap()
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+                username: entry
+                    .user_id
+                    .and_then(|user_id| usernames.get(&user_id).cloned()),
+                source: entry.source.to_string(),
+                action: entry.action.to_string(),
+                object: entry.object,
+                payload: entry.payload,
+            })
+            .collect::<Vec<_>>())
+    })
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(AuditTemplate {
+        csrf_token,
+        user,
+        ledger,
+        query,
+        entries,
+    })
+}
@@ -0,0 +1,222 @@
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, Responder, ResponseError};
+use askama::Template;
+use diesel::prelude::*;
+use futures::future::LocalBoxFuture;
+use serde_derive::Deserialize;
+use sharebill::auth::{generate_token, hash_token, verify_password, Role, DUMMY_PASSWORD_HASH};
+use sharebill::models::{NewSession, User};
+use sharebill::schema::{ledger_members, sessions, users};
+use sharebill::DbConnection;
+use thiserror::Error;
+
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::DbPool;
+
+pub const SESSION_COOKIE: &str = "sharebill_session";
+const SESSION_DAYS: i64 = 30;
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("login required")]
+    LoginRequired,
+    #[error("you do not have access to do this")]
+    Forbidden,
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::LoginRequired => StatusCode::SEE_OTHER,
+            AuthError::Forbidden => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AuthError::LoginRequired => HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, "/login"))
+                .finish(),
+            AuthError::Forbidden => HttpResponse::Forbidden().body(self.to_string()),
+        }
+    }
+}
+
+/// The user owning the session cookie of the request. Extracting this
+/// redirects to the login page if there is no valid session.
+pub struct CurrentUser(pub User);
+
+impl FromRequest for CurrentUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<DbPool>>().cloned();
+        let token = req
+            .cookie(SESSION_COOKIE)
+            .map(|cookie| cookie.value().to_owned());
+
+        Box::pin(async move {
+            let (Some(pool), Some(token)) = (pool, token) else {
+                return Err(AuthError::LoginRequired.into());
+            };
+
+            let user = web::block(
+                move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+                    let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+                    let user = sessions::table
+                        .inner_join(users::table)
+                        .filter(sessions::token_hash.eq(hash_token(&token)))
+                        .filter(sessions::expiry_time.gt(chrono::Utc::now().naive_utc()))
+                        .select((users::id, users::username, users::password_hash))
+                        .first::<User>(&mut conn)
+                        .optional()?;
+
+                    Ok(user)
+                },
+            )
+            .await?
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+
+            user.map(CurrentUser)
+                .ok_or_else(|| AuthError::LoginRequired.into())
+        })
+    }
+}
+
+pub fn ledger_role(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    user_id: i32,
+) -> QueryResult<Option<Role>> {
+    ledger_members::table
+        .find((ledger_id, user_id))
+        .select(ledger_members::role)
+        .first::<Role>(conn)
+        .optional()
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    csrf_token: String,
+    failed: bool,
+}
+
+pub async fn get_login(CsrfToken(csrf_token): CsrfToken) -> impl Responder {
+    LoginTemplate {
+        csrf_token,
+        failed: false,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    csrf_token: String,
+    username: String,
+    password: String,
+}
+
+pub async fn post_login(
+    req: HttpRequest,
+    csrf: CsrfToken,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<LoginForm>,
+) -> actix_web::Result<HttpResponse> {
+    csrf.verify(&form.csrf_token)?;
+
+    let token = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let user = users::table
+                .filter(users::username.eq(&form.username))
+                .first::<User>(&mut conn)
+                .optional()?;
+
+            // Unknown usernames are checked against a dummy hash, so the time
+            // taken does not tell which usernames exist
+            let password_hash = user
+                .as_ref()
+                .map_or(DUMMY_PASSWORD_HASH, |user| &user.password_hash);
+            let valid = verify_password(&form.password, password_hash);
+            let Some(user) = user.filter(|_| valid) else {
+                return Ok(None);
+            };
+
+            let token = generate_token();
+            let now = chrono::Utc::now().naive_utc();
+            diesel::insert_into(sessions::table)
+                .values(&NewSession {
+                    token_hash: hash_token(&token),
+                    user_id: user.id,
+                    created_time: now,
+                    expiry_time: now + chrono::Duration::days(SESSION_DAYS),
+                })
+                .execute(&mut conn)?;
+
+            Ok(Some(token))
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let Some(token) = token else {
+        let body = LoginTemplate {
+            csrf_token: csrf.0,
+            failed: true,
+        }
+        .render()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+        return Ok(HttpResponse::Unauthorized()
+            .content_type("text/html; charset=utf-8")
+            .body(body));
+    };
+
+    let cookie = Cookie::build(SESSION_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        .secure(req.connection_info().scheme() == "https")
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::days(SESSION_DAYS))
+        .finish();
+
+    Ok(HttpResponse::SeeOther()
+        .cookie(cookie)
+        .insert_header((header::LOCATION, "/"))
+        .finish())
+}
+
+pub async fn post_logout(
+    req: HttpRequest,
+    csrf: CsrfToken,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<CsrfForm>,
+) -> actix_web::Result<HttpResponse> {
+    csrf.verify(&form.csrf_token)?;
+
+    if let Some(token) = req.cookie(SESSION_COOKIE).map(|c| c.value().to_owned()) {
+        web::block(
+            move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+                let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+                diesel::delete(sessions::table.filter(sessions::token_hash.eq(hash_token(&token))))
+                    .execute(&mut conn)?;
+
+                Ok(())
+            },
+        )
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+
+    Ok(HttpResponse::SeeOther()
+        .cookie(cookie)
+        .insert_header((header::LOCATION, "/login"))
+        .finish())
+}
@@ -0,0 +1,38 @@
+//! Periodic backups taken by the web server, when enabled with `--backup-dir`
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix_web::web;
+use diesel::{Connection, SqliteConnection};
+use sharebill::backup::{backup, prune};
+
+/// Writes a backup of the database at `database_url` to `dir` now and then
+/// every `interval` for as long as the server runs, keeping the newest `keep`
+/// backups
+pub fn spawn_backups(database_url: String, dir: PathBuf, interval: Duration, keep: usize) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let database_url = database_url.clone();
+            let dir = dir.clone();
+            let result = web::block(
+                move || -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+                    let mut conn = SqliteConnection::establish(&database_url)?;
+                    backup(&mut conn, &dir, chrono::Utc::now().naive_utc())?;
+                    prune(&dir, keep)?;
+                    Ok(())
+                },
+            )
+            .await;
+
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => eprintln!("Error backing up the database: {err}"),
+                Err(err) => eprintln!("Error backing up the database: {err}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,251 @@
+//! Budgets of a ledger and how much of them is spent
+
+use actix_web::web::Redirect;
+use actix_web::{web, Responder};
+use askama::Template;
+use diesel::prelude::*;
+use num::{BigRational, Signed};
+use serde_derive::Deserialize;
+use sharebill::amount::parse_amount;
+use sharebill::audit::{self, Action, Actor};
+use sharebill::auth::Role;
+use sharebill::budgets::{budget_statuses, BudgetStatus, BudgetTarget, InvalidBudget, Period};
+use sharebill::currency::parse_currency;
+use sharebill::format::AmountFormat;
+use sharebill::models::{Ledger, NewBudget, User};
+use sharebill::schema::budgets;
+use sharebill::tags::parse_tags;
+
+use crate::auth::CurrentUser;
+use crate::csrf::CsrfToken;
+use crate::events::Broadcaster;
+use crate::{load_ledger, DbPool, ValidationError};
+
+struct BudgetEntry {
+    target: &'static str,
+    name: String,
+    period: &'static str,
+    from: String,
+    to: String,
+    spent: String,
+    amount: String,
+    percent: u32,
+    exceeded: bool,
+}
+
+#[derive(Template)]
+#[template(path = "budgets.html")]
+struct BudgetsTemplate {
+    csrf_token: String,
+    user: User,
+    ledger: Ledger,
+    budgets: Vec<BudgetEntry>,
+}
+
+/// An amount with the currency symbol of the ledger, or the currency code for
+/// other currencies
+fn amount_text(
+    format: &AmountFormat,
+    value: &BigRational,
+    currency: &str,
+    base_currency: &str,
+) -> String {
+    if currency == base_currency {
+        format.amount(value)
+    } else {
+        format!("{} {currency}", format.number(value))
+    }
+}
+
+/// A one line description of an exceeded budget for the overview, such as
+/// "tag supplies: 2,200.00 kr of 2,000.00 kr this month"
+pub fn exceeded_summary(
+    format: &AmountFormat,
+    base_currency: &str,
+    status: &BudgetStatus,
+) -> String {
+    let budget = &status.budget;
+    format!(
+        "{} {}: {} of {} this {}",
+        budget.target,
+        budget.name,
+        amount_text(format, &status.spent, &budget.currency, base_currency),
+        amount_text(
+            format,
+            &budget.amount.to_big_rational(),
+            &budget.currency,
+            base_currency
+        ),
+        budget.period,
+    )
+}
+
+pub async fn get_budgets(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+    let format = AmountFormat::from(&ledger);
+
+    let statuses = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+            let today = chrono::Utc::now().date_naive();
+            Ok(budget_statuses(&mut conn, ledger_id, today)?)
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let budgets = statuses
+        .into_iter()
+        .map(|status| BudgetEntry {
+            target: status.budget.target.as_str(),
+            period: status.budget.period.as_str(),
+            from: status.from.to_string(),
+            to: status.to.to_string(),
+            spent: amount_text(
+                &format,
+                &status.spent,
+                &status.budget.currency,
+                &ledger.base_currency,
+            ),
+            amount: amount_text(
+                &format,
+                &status.budget.amount.to_big_rational(),
+                &status.budget.currency,
+                &ledger.base_currency,
+            ),
+            percent: status.percent(),
+            exceeded: status.exceeded(),
+            name: status.budget.name,
+        })
+        .collect();
+
+    Ok(BudgetsTemplate {
+        csrf_token,
+        user,
+        ledger,
+        budgets,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetForm {
+    csrf_token: String,
+    target: String,
+    /// The account or tag
+    name: String,
+    /// Defaults to the base currency of the ledger
+    #[serde(default)]
+    currency: String,
+    /// Empty removes the budget
+    amount: String,
+    period: String,
+}
+
+pub async fn post_budget(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<BudgetForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let target = form
+        .target
+        .parse::<BudgetTarget>()
+        .map_err(ValidationError::from)?;
+    let period = form
+        .period
+        .parse::<Period>()
+        .map_err(ValidationError::from)?;
+    let name = match target {
+        BudgetTarget::Account => form.name.clone(),
+        // Tags are stored normalized, so the budget must be too
+        BudgetTarget::Tag => match &parse_tags(&form.name).map_err(ValidationError::from)?[..] {
+            [tag] => tag.clone(),
+            _ => return Err(ValidationError::from(InvalidBudget).into()),
+        },
+    };
+    if name.is_empty() {
+        return Err(ValidationError::EmptyAccountName.into());
+    }
+    let amount = match form.amount.trim() {
+        "" => None,
+        amount => match parse_amount(amount) {
+            Ok(amount) if amount.to_big_rational().is_positive() => Some(amount),
+            _ => return Err(ValidationError::InvalidBudgetAmount.into()),
+        },
+    };
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let currency = if form.currency.trim().is_empty() {
+        ledger.base_currency.clone()
+    } else {
+        parse_currency(&form.currency).map_err(ValidationError::from)?
+    };
+
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    conn.transaction(|conn| {
+        let payload = serde_json::json!({
+            "target": target.as_str(),
+            "name": name,
+            "currency": currency,
+            "amount": amount,
+            "period": period.as_str(),
+        });
+        let action = match amount {
+            Some(amount) => {
+                diesel::insert_into(budgets::table)
+                    .values(&NewBudget {
+                        ledger_id: ledger.id,
+                        target,
+                        name: &name,
+                        currency: &currency,
+                        amount: amount.clone(),
+                        period,
+                    })
+                    .on_conflict((
+                        budgets::ledger_id,
+                        budgets::target,
+                        budgets::name,
+                        budgets::currency,
+                    ))
+                    .do_update()
+                    .set((budgets::amount.eq(amount), budgets::period.eq(period)))
+                    .execute(conn)?;
+                Action::Edit
+            }
+            None => {
+                diesel::delete(
+                    budgets::table
+                        .filter(budgets::ledger_id.eq(ledger.id))
+                        .filter(budgets::target.eq(target))
+                        .filter(budgets::name.eq(&name))
+                        .filter(budgets::currency.eq(&currency)),
+                )
+                .execute(conn)?;
+                Action::Delete
+            }
+        };
+
+        audit::record(
+            conn,
+            Actor::web(user.id),
+            Some(ledger.id),
+            action,
+            "budget",
+            payload,
+        )
+    })
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to("budgets").see_other())
+}
@@ -0,0 +1,160 @@
+//! CSRF protection using the double submit cookie pattern. Every client gets a
+//! random token in a `SameSite` cookie, and every form must echo that token
+//! back in a `csrf_token` field. A cross-site page can neither read the cookie
+//! nor make the browser send it along with a POST, so it cannot forge a
+//! matching form.
+
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, ResponseError};
+use futures::future::{ready, Ready};
+use serde_derive::Deserialize;
+use sharebill::auth::generate_token;
+use thiserror::Error;
+
+pub const CSRF_COOKIE: &str = "sharebill_csrf";
+
+#[derive(Error, Debug)]
+#[error("invalid or missing CSRF token, please reload the page and try again")]
+pub struct CsrfError;
+
+impl ResponseError for CsrfError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+/// The CSRF token of the client, to be embedded in forms
+#[derive(Clone)]
+pub struct CsrfToken(pub String);
+
+impl CsrfToken {
+    pub fn verify(&self, submitted: &str) -> Result<(), CsrfError> {
+        let expected = self.0.as_bytes();
+        let submitted = submitted.as_bytes();
+
+        // Constant time comparison, so the token cannot be guessed byte by byte
+        let difference = expected
+            .iter()
+            .zip(submitted)
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        if expected.len() == submitted.len() && difference == 0 {
+            Ok(())
+        } else {
+            Err(CsrfError)
+        }
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = CsrfError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let token = req.extensions().get::<CsrfToken>().cloned().or_else(|| {
+            req.cookie(CSRF_COOKIE)
+                .map(|cookie| CsrfToken(cookie.value().to_owned()))
+        });
+
+        ready(token.ok_or(CsrfError))
+    }
+}
+
+/// Form body for POST endpoints that take no other input
+#[derive(Debug, Deserialize)]
+pub struct CsrfForm {
+    pub csrf_token: String,
+}
+
+/// Makes sure the request has a CSRF token available for the `CsrfToken`
+/// extractor. Returns the new token if one had to be generated, which must
+/// then be set as a cookie on the response.
+pub fn ensure_token(req: &ServiceRequest) -> Option<String> {
+    if req.cookie(CSRF_COOKIE).is_some() {
+        return None;
+    }
+
+    let token = generate_token();
+    req.extensions_mut().insert(CsrfToken(token.clone()));
+    Some(token)
+}
+
+pub fn cookie(token: String, secure: bool) -> Cookie<'static> {
+    Cookie::build(CSRF_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::dev::Service;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App};
+
+    use super::*;
+
+    #[test]
+    fn tokens_must_match() {
+        let token = CsrfToken("0123456789abcdef".to_owned());
+        assert!(token.verify("0123456789abcdef").is_ok());
+        assert!(token.verify("0123456789abcdeF").is_err());
+        assert!(token.verify("fedcba9876543210").is_err());
+        // Tokens that agree as far as they go are not enough
+        assert!(token.verify("0123456789abcde").is_err());
+        assert!(token.verify("0123456789abcdef0").is_err());
+        assert!(token.verify("").is_err());
+    }
+
+    async fn submit(csrf: CsrfToken, form: web::Form<CsrfForm>) -> actix_web::Result<&'static str> {
+        csrf.verify(&form.csrf_token)?;
+        Ok("ok")
+    }
+
+    #[actix_web::test]
+    async fn posts_without_the_cookie_fail() {
+        let app = init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let new_csrf_token = ensure_token(&req);
+                    let res = srv.call(req);
+                    async move {
+                        let mut res = res.await?;
+                        if let Some(token) = new_csrf_token {
+                            res.response_mut().add_cookie(&cookie(token, false))?;
+                        }
+                        Ok(res)
+                    }
+                })
+                .route("/", web::post().to(submit)),
+        )
+        .await;
+
+        // A forged form gets a fresh token, which it cannot know
+        for forged in ["", "0123456789abcdef"] {
+            let req = TestRequest::post()
+                .uri("/")
+                .set_form([("csrf_token", forged)])
+                .to_request();
+            let res = call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let fresh = res
+                .response()
+                .cookies()
+                .find(|cookie| cookie.name() == CSRF_COOKIE)
+                .unwrap();
+            assert_ne!(fresh.value(), forged);
+        }
+
+        let req = TestRequest::post()
+            .uri("/")
+            .cookie(cookie("0123456789abcdef".to_owned(), false))
+            .set_form([("csrf_token", "0123456789abcdef")])
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,133 @@
+//! Server-sent events telling open overviews that a ledger has changed, so
+//! they can update without a full reload
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpResponse, Responder};
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::StreamExt;
+use sharebill::auth::Role;
+
+use crate::auth::CurrentUser;
+use crate::{load_ledger, DbPool};
+
+/// How often to send a comment to every client, which keeps the connections
+/// open through proxies and finds the clients that have gone away
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The event sent when a ledger changes
+const CHANGE: Bytes = Bytes::from_static(b"event: change\ndata: {}\n\n");
+
+/// The clients listening for changes, by ledger slug
+#[derive(Clone, Default)]
+pub struct Broadcaster {
+    clients: Arc<Mutex<HashMap<String, Vec<UnboundedSender<Bytes>>>>>,
+}
+
+impl Broadcaster {
+    /// A broadcaster that pings its clients for as long as the server runs
+    pub fn spawn() -> Self {
+        let broadcaster = Broadcaster::default();
+
+        let pinger = broadcaster.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(PING_INTERVAL);
+            loop {
+                interval.tick().await;
+                pinger.send_all(Bytes::from_static(b": ping\n\n"));
+            }
+        });
+
+        broadcaster
+    }
+
+    fn subscribe(&self, slug: String) -> futures::channel::mpsc::UnboundedReceiver<Bytes> {
+        let (tx, rx) = unbounded();
+        // Tells the client that the stream is open, before the first change
+        let _ = tx.unbounded_send(Bytes::from_static(b": connected\n\n"));
+        self.clients
+            .lock()
+            .unwrap()
+            .entry(slug)
+            .or_default()
+            .push(tx);
+        rx
+    }
+
+    /// Sends `event` to the clients of every ledger, dropping the ones that
+    /// have disconnected
+    fn send_all(&self, event: Bytes) {
+        let mut clients = self.clients.lock().unwrap();
+        for senders in clients.values_mut() {
+            senders.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+        }
+        clients.retain(|_, senders| !senders.is_empty());
+    }
+
+    /// Tells the clients of a ledger that it has changed
+    pub fn changed(&self, slug: &str) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(senders) = clients.get_mut(slug) {
+            senders.retain(|tx| tx.unbounded_send(CHANGE).is_ok());
+            if senders.is_empty() {
+                clients.remove(slug);
+            }
+        }
+    }
+
+    /// Tells the clients of every ledger that it may have changed, for
+    /// changes that are not made through a request to a ledger
+    pub fn all_changed(&self) {
+        self.send_all(CHANGE);
+    }
+}
+
+/// A stream of `change` events for a ledger, sent after each committed change
+/// to it
+pub async fn get_events(
+    CurrentUser(user): CurrentUser,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Viewer).await?;
+    let events = broadcaster.subscribe(ledger.slug);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // Keeps nginx from buffering the events
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events.map(Ok::<_, actix_web::Error>)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changes_reach_the_clients_of_the_ledger() {
+        let broadcaster = Broadcaster::default();
+        let mut home = broadcaster.subscribe("home".to_owned());
+        let gone = broadcaster.subscribe("home".to_owned());
+        let mut trip = broadcaster.subscribe("trip".to_owned());
+        drop(gone);
+
+        broadcaster.changed("home");
+
+        let connected = Bytes::from_static(b": connected\n\n");
+        assert_eq!(home.try_next().unwrap(), Some(connected.clone()));
+        assert_eq!(home.try_next().unwrap(), Some(CHANGE));
+        assert!(home.try_next().is_err());
+        assert_eq!(trip.try_next().unwrap(), Some(connected));
+        assert!(trip.try_next().is_err());
+        assert_eq!(broadcaster.clients.lock().unwrap()["home"].len(), 1);
+
+        drop(home);
+        broadcaster.changed("home");
+        assert!(!broadcaster.clients.lock().unwrap().contains_key("home"));
+    }
+}
@@ -0,0 +1,382 @@
+//! Atom feeds of the latest transactions of a ledger or an account, for feed
+//! readers and chat bridges. Feed readers seldom let you set headers, so the
+//! API token can also be given as a `token` query parameter. URLs end up in
+//! logs and histories, so only tokens that cannot write to any ledger are
+//! accepted there.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama::Template;
+use chrono::{SecondsFormat, Utc};
+use diesel::prelude::*;
+use serde_derive::Deserialize;
+use sharebill::auth::Access;
+use sharebill::format::AmountFormat;
+use sharebill::ledger::StoredTransaction;
+use sharebill::models::Ledger;
+use sharebill::rational::Rational;
+use sharebill::schema::api_token_ledgers;
+use sharebill::DbConnection;
+
+use crate::api::{authorize, bearer_token, ApiError, ApiUser};
+use crate::DbPool;
+
+/// Number of transactions in a feed
+const FEED_LENGTH: i64 = 20;
+
+struct FeedPosting {
+    account: String,
+    debit: String,
+    credit: String,
+}
+
+struct FeedEntry {
+    url: String,
+    what: String,
+    /// RFC 3339 timestamps
+    when: String,
+    updated: String,
+    postings: Vec<FeedPosting>,
+}
+
+#[derive(Template)]
+#[template(path = "feed.xml")]
+struct FeedTemplate {
+    /// The URL of the feed itself
+    url: String,
+    /// The URL of the page the feed follows
+    overview_url: String,
+    title: String,
+    ledger: Ledger,
+    updated: String,
+    entries: Vec<FeedEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    token: Option<String>,
+}
+
+/// An amount with the currency symbol of the ledger, or with the currency
+/// code for other currencies than the base currency
+fn feed_amount(format: &AmountFormat, ledger: &Ledger, value: &Rational, currency: &str) -> String {
+    let value = value.to_big_rational();
+    if currency == ledger.base_currency {
+        format.amount(&value)
+    } else {
+        format!("{} {currency}", format.number(&value))
+    }
+}
+
+fn feed_entry(base_url: &str, ledger: &Ledger, stored: StoredTransaction) -> FeedEntry {
+    let StoredTransaction { tx, transaction } = stored;
+    let format = AmountFormat::from(ledger);
+
+    // One row per account and currency, debits first
+    let mut postings = Vec::new();
+    for ((account, currency), value) in transaction.debits() {
+        if !value.is_zero() {
+            postings.push(FeedPosting {
+                account: account.clone(),
+                debit: feed_amount(&format, ledger, value, currency),
+                credit: String::new(),
+            });
+        }
+    }
+    for ((account, currency), value) in transaction.credits() {
+        if !value.is_zero() {
+            postings.push(FeedPosting {
+                account: account.clone(),
+                debit: String::new(),
+                credit: feed_amount(&format, ledger, value, currency),
+            });
+        }
+    }
+
+    let timestamp = |time: chrono::NaiveDateTime| {
+        time.and_local_timezone(Utc)
+            .unwrap()
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    };
+
+    FeedEntry {
+        url: format!("{base_url}/{}/post/{}", ledger.slug, tx.id),
+        what: tx.description,
+        when: timestamp(tx.tx_time),
+        updated: timestamp(tx.rev_time),
+        postings,
+    }
+}
+
+/// Whether a token has read access alone, in every ledger it has access to
+fn is_read_only(conn: &mut DbConnection, token_id: i32) -> QueryResult<bool> {
+    let writable = api_token_ledgers::table
+        .filter(api_token_ledgers::token_id.eq(token_id))
+        .filter(api_token_ledgers::access.eq(Access::ReadWrite))
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(writable == 0)
+}
+
+async fn feed(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query_token: Option<String>,
+    slug: String,
+    account: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let (token, in_query) = match bearer_token(&req) {
+        Some(token) => (Some(token), false),
+        None => (query_token, true),
+    };
+    let api_user = ApiUser::authenticate(pool.clone(), token).await?;
+    let title_account = account.clone();
+
+    let (ledger, transactions) =
+        web::block(move || -> Result<_, ApiError> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            if in_query && !is_read_only(&mut conn, api_user.token_id)? {
+                return Err(ApiError::Forbidden);
+            }
+            let ledger = authorize(&mut conn, &api_user, &slug, Access::Read)?;
+            let transactions = sharebill::ledger::Ledger::new(&mut conn, ledger.id)
+                .list_transactions(None, account.as_deref(), None, FEED_LENGTH)?;
+
+            Ok((ledger, transactions))
+        })
+        .await??;
+
+    let info = req.connection_info();
+    let base_url = format!("{}://{}", info.scheme(), info.host());
+    let overview_url = format!("{base_url}/{}/", ledger.slug);
+    let title = match title_account {
+        Some(account) => format!("{account} – {}", ledger.name),
+        None => ledger.name.clone(),
+    };
+
+    // An empty feed has not changed since the epoch, so that readers do not
+    // see it change on every fetch
+    let updated = transactions
+        .iter()
+        .map(|stored| stored.tx.rev_time)
+        .max()
+        .unwrap_or_default()
+        .and_local_timezone(Utc)
+        .unwrap()
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+    let entries = transactions
+        .into_iter()
+        .map(|stored| feed_entry(&base_url, &ledger, stored))
+        .collect();
+
+    let feed = FeedTemplate {
+        url: format!("{base_url}{}", req.path()),
+        overview_url,
+        title,
+        ledger,
+        updated,
+        entries,
+    }
+    .render()
+    .map_err(|_| ApiError::Internal)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        // Keeps shared caches from storing feeds fetched with the token in
+        // the URL
+        .insert_header(("Cache-Control", "private"))
+        .body(feed))
+}
+
+/// The latest transactions of a ledger
+pub async fn get_ledger_feed(
+    req: HttpRequest,
+    slug: web::Path<String>,
+    query: web::Query<FeedQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    feed(req, pool, query.into_inner().token, slug.into_inner(), None).await
+}
+
+/// The latest transactions with a debit or credit for an account
+pub async fn get_account_feed(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<FeedQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let (slug, account) = path.into_inner();
+    feed(req, pool, query.into_inner().token, slug, Some(account)).await
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod test {
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use chrono::NaiveDate;
+    use sharebill::audit::Actor;
+    use sharebill::auth::Role;
+    use sharebill::ledger::{Postings, Transaction};
+
+    use super::*;
+    use crate::api::test::{add_ledger, add_token};
+
+    /// A pool of connections to a new database file, which is deleted when
+    /// it is dropped
+    struct TestDb {
+        pool: DbPool,
+        path: std::path::PathBuf,
+    }
+
+    impl TestDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("sharebill-feeds-{name}-{}.db", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let pool = sharebill::create_pool(path.to_string_lossy()).unwrap();
+            TestDb { pool, path }
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn add_post(conn: &mut DbConnection, day: u32, debit: &str, credit: &str) {
+        let when = NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let posting = |account: &str| {
+            Postings::from([((account.to_owned(), "NOK".to_owned()), 10u32.into())])
+        };
+        let transaction = Transaction::new(
+            when,
+            format!("{debit} owes {credit}"),
+            "",
+            posting(debit),
+            posting(credit),
+        )
+        .unwrap();
+        sharebill::ledger::Ledger::new(conn, 1)
+            .insert_transaction(Actor::cli(), &transaction)
+            .unwrap();
+    }
+
+    /// The status and body of a GET of `uri`, with `token` in the header if
+    /// given
+    async fn fetch(db: &TestDb, uri: &str, token: Option<&str>) -> (StatusCode, String) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db.pool.clone()))
+                .route("/api/{ledger}/feed.atom", web::get().to(get_ledger_feed))
+                .route(
+                    "/api/{ledger}/accounts/{account}/feed.atom",
+                    web::get().to(get_account_feed),
+                ),
+        )
+        .await;
+        let mut req = test::TestRequest::get().uri(uri);
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {token}")));
+        }
+        let res = test::call_service(&app, req.to_request()).await;
+        let status = res.status();
+        let body = test::read_body(res).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn account_feeds_have_the_posts_of_the_account() {
+        let db = TestDb::new("accounts");
+        let mut conn = db.pool.get().unwrap();
+        let (token, _) = add_token(
+            &mut conn,
+            "alice",
+            &[(1, Role::Member)],
+            &[(1, Access::Read)],
+        );
+        // The post of carol is older than a full feed of the others
+        add_post(&mut conn, 1, "carol", "alice");
+        for day in 2..=(FEED_LENGTH as u32 + 2) {
+            add_post(&mut conn, day, "bob", "alice");
+        }
+
+        let (status, feed) =
+            fetch(&db, &format!("/api/default/feed.atom?token={token}"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(feed.matches("<entry>").count(), FEED_LENGTH as usize);
+        assert!(!feed.contains("carol owes alice"));
+
+        let uri = format!("/api/default/accounts/carol/feed.atom?token={token}");
+        let (status, feed) = fetch(&db, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(feed.matches("<entry>").count(), 1);
+        assert!(feed.contains("<title>carol owes alice</title>"));
+
+        // An empty feed stays the same from one fetch to the next
+        let uri = format!("/api/default/accounts/dave/feed.atom?token={token}");
+        let (_, feed) = fetch(&db, &uri, None).await;
+        assert_eq!(feed.matches("<entry>").count(), 0);
+        assert!(feed.contains("<updated>1970-01-01T00:00:00Z</updated>"));
+        assert_eq!(fetch(&db, &uri, None).await.1, feed);
+    }
+
+    #[actix_web::test]
+    async fn only_read_only_tokens_go_in_the_query() {
+        let db = TestDb::new("query");
+        let mut conn = db.pool.get().unwrap();
+        let (reader, _) = add_token(
+            &mut conn,
+            "alice",
+            &[(1, Role::Member)],
+            &[(1, Access::Read)],
+        );
+        let (writer, _) = add_token(
+            &mut conn,
+            "bob",
+            &[(1, Role::Member)],
+            &[(1, Access::ReadWrite)],
+        );
+
+        let feed = |token: &str| format!("/api/default/feed.atom?token={token}");
+        assert_eq!(fetch(&db, &feed(&reader), None).await.0, StatusCode::OK);
+        assert_eq!(
+            fetch(&db, &feed(&writer), None).await.0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            fetch(&db, "/api/default/feed.atom", Some(&writer)).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            fetch(&db, "/api/default/feed.atom", None).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn tokens_only_read_the_feeds_of_their_ledgers() {
+        let db = TestDb::new("ledgers");
+        let mut conn = db.pool.get().unwrap();
+        let other = add_ledger(&mut conn, "other");
+        let (token, _) = add_token(
+            &mut conn,
+            "alice",
+            &[(1, Role::Member), (other, Role::Member)],
+            &[(other, Access::Read)],
+        );
+
+        let (status, _) = fetch(&db, &format!("/api/other/feed.atom?token={token}"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        for uri in [
+            "/api/default/feed.atom",
+            "/api/default/accounts/alice/feed.atom",
+        ] {
+            let (status, _) = fetch(&db, &format!("{uri}?token={token}"), None).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! The itemized receipt entry mode, where each line item is split among the
+//! people who had it. The receipt is stored along with the resulting
+//! transaction, so it can be edited again.
+
+use actix_web::web::Redirect;
+use actix_web::{web, Responder};
+use askama::Template;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::de::Error;
+use serde_derive::Deserialize;
+use sharebill::amount::parse_amount;
+use sharebill::audit::Actor;
+use sharebill::auth::Role;
+use sharebill::currency::parse_currency;
+use sharebill::format::AmountFormat;
+use sharebill::ledger::{LedgerError, Postings, Transaction};
+use sharebill::models::Ledger;
+use sharebill::rational::{Rational, RationalVisitor};
+use sharebill::receipts::{load_receipt, save_receipt, LineItem, Receipt, SharedLine};
+
+use crate::auth::CurrentUser;
+use crate::csrf::CsrfToken;
+use crate::events::Broadcaster;
+use crate::{load_ledger, map_ledger_error, DbPool, FormattedAmount, ValidationError};
+
+#[derive(Template)]
+#[template(path = "itemized.html")]
+struct ItemizedTemplate {
+    csrf_token: String,
+    ledger: Ledger,
+    /// None for a receipt that has not been saved yet
+    id: Option<i32>,
+    when: String,
+    what: String,
+    payer: String,
+    currency: String,
+    /// Descriptions, amounts and comma separated consumers for the input
+    /// fields
+    items: Vec<(String, String, String)>,
+    /// Descriptions and amounts of tax, tip and such
+    shared: Vec<(String, String)>,
+    /// What each person owes for a saved receipt
+    shares: Vec<(String, FormattedAmount)>,
+    total: Option<FormattedAmount>,
+}
+
+/// Parses the comma separated accounts that had an item
+fn parse_consumers(consumers: &str) -> Vec<String> {
+    let mut consumers = consumers
+        .split(',')
+        .map(str::trim)
+        .filter(|account| !account.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    consumers.sort_unstable();
+    consumers.dedup();
+    consumers
+}
+
+struct ReceiptVisitor;
+
+impl<'de> serde::de::Visitor<'de> for ReceiptVisitor {
+    type Value = Receipt;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("line items and shared lines of a receipt")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        // (description, amount, consumers) for each row of the form
+        let mut items = Vec::<(String, String, String)>::new();
+        let mut shared = Vec::<(String, String)>::new();
+
+        while let Some((key, value)) = map.next_entry::<String, String>()? {
+            match key.as_str() {
+                "item_description" => items.push((value, String::new(), String::new())),
+                "item_amount" => {
+                    if let Some(row) = items.last_mut() {
+                        row.1 = value;
+                    }
+                }
+                "item_consumers" => {
+                    if let Some(row) = items.last_mut() {
+                        row.2 = value;
+                    }
+                }
+                "shared_description" => shared.push((value, String::new())),
+                "shared_amount" => {
+                    if let Some(row) = shared.last_mut() {
+                        row.1 = value;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let amount = |value: &str| {
+            parse_amount(value).map_err(|_| {
+                A::Error::invalid_value(serde::de::Unexpected::Str(value), &RationalVisitor)
+            })
+        };
+
+        Ok(Receipt {
+            items: items
+                .into_iter()
+                // ignore empty rows
+                .filter(|(description, value, consumers)| {
+                    !(description.is_empty() && value.is_empty() && consumers.is_empty())
+                })
+                .map(|(description, value, consumers)| {
+                    Ok(LineItem {
+                        amount: amount(&value)?,
+                        description,
+                        consumers: parse_consumers(&consumers),
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            shared: shared
+                .into_iter()
+                .filter(|(description, value)| !(description.is_empty() && value.is_empty()))
+                .map(|(description, value)| {
+                    Ok(SharedLine {
+                        amount: amount(&value)?,
+                        description,
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+fn deserialize_receipt<'de, D>(deserializer: D) -> Result<Receipt, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserializer.deserialize_map(ReceiptVisitor)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ItemizedForm {
+    csrf_token: String,
+    when: DateTime<Utc>,
+    what: String,
+    payer: String,
+    /// Defaults to the base currency of the ledger
+    #[serde(default)]
+    currency: String,
+
+    #[serde(flatten, deserialize_with = "deserialize_receipt")]
+    receipt: Receipt,
+}
+
+impl ItemizedForm {
+    /// The debits and credits of the transaction, where each person is debited
+    /// their share and the payer is credited the total
+    fn transaction(&self, base_currency: &str) -> Result<Transaction, ValidationError> {
+        let currency = if self.currency.trim().is_empty() {
+            base_currency.to_owned()
+        } else {
+            parse_currency(&self.currency)?
+        };
+
+        let debits = self
+            .receipt
+            .split()?
+            .into_iter()
+            .map(|(account, share)| ((account, currency.clone()), share))
+            .collect::<Postings>();
+        let credits = Postings::from([((self.payer.clone(), currency), self.receipt.total())]);
+
+        Ok(Transaction::new(
+            self.when.naive_utc(),
+            self.what.clone(),
+            "",
+            debits,
+            credits,
+        )?)
+    }
+}
+
+/// The line item and shared line inputs of a receipt form
+type ReceiptRows = (Vec<(String, String, String)>, Vec<(String, String)>);
+
+/// The input rows of a receipt, with some empty ones to fill in
+fn receipt_rows(format: &AmountFormat, receipt: &Receipt) -> ReceiptRows {
+    let amount = |value: &Rational| format.exact(&value.to_big_rational());
+
+    let mut items = receipt
+        .items
+        .iter()
+        .map(|item| {
+            (
+                item.description.clone(),
+                amount(&item.amount),
+                item.consumers.join(", "),
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut shared = receipt
+        .shared
+        .iter()
+        .map(|line| (line.description.clone(), amount(&line.amount)))
+        .collect::<Vec<_>>();
+
+    items.resize(std::cmp::max(items.len() + 3, 6), Default::default());
+    shared.resize(std::cmp::max(shared.len() + 1, 2), Default::default());
+    (items, shared)
+}
+
+pub async fn new_itemized(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let (items, shared) = receipt_rows(&AmountFormat::from(&ledger), &Receipt::default());
+
+    Ok(ItemizedTemplate {
+        csrf_token,
+        id: None,
+        when: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        what: String::new(),
+        payer: user.username,
+        currency: ledger.base_currency.clone(),
+        items,
+        shared,
+        shares: vec![],
+        total: None,
+        ledger,
+    })
+}
+
+pub async fn get_itemized(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+
+    let (tx, payers, receipt) = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let stored = sharebill::ledger::Ledger::new(&mut conn, ledger_id).get_transaction(id);
+            let stored = match stored {
+                Ok(stored) => stored,
+                Err(LedgerError::NotFound) => return Ok((None, vec![], None)),
+                Err(err) => return Err(err.into()),
+            };
+            let payers = stored
+                .transaction
+                .credits()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+            let receipt = load_receipt(&mut conn, id)?;
+
+            Ok((Some(stored.tx), payers, receipt))
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    let tx = tx.ok_or_else(|| actix_web::error::ErrorNotFound("no such post"))?;
+
+    // An itemized receipt has a single payer. Posts that are not itemized yet
+    // start out with theirs, if they too have just one.
+    let (payer, currency) = match &payers[..] {
+        [(payer, currency)] => (payer.clone(), currency.clone()),
+        _ => (String::new(), ledger.base_currency.clone()),
+    };
+
+    let format = AmountFormat::from(&ledger);
+    let receipt = receipt.unwrap_or_default();
+    let (items, shared) = receipt_rows(&format, &receipt);
+    let amount = |value: &Rational| {
+        FormattedAmount::new(
+            &format,
+            &value.to_big_rational(),
+            &currency,
+            &ledger.base_currency,
+        )
+    };
+    let shares = receipt
+        .split()
+        .map(|shares| {
+            shares
+                .iter()
+                .map(|(account, share)| (account.clone(), amount(share)))
+                .collect()
+        })
+        .unwrap_or_default();
+    let total = (!receipt.items.is_empty()).then(|| amount(&receipt.total()));
+
+    Ok(ItemizedTemplate {
+        csrf_token,
+        id: Some(id),
+        when: tx
+            .tx_time
+            .and_local_timezone(Utc)
+            .unwrap()
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+        what: tx.description,
+        payer,
+        currency,
+        items,
+        shared,
+        shares,
+        total,
+        ledger,
+    })
+}
+
+pub async fn create_itemized(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(doc): web::Form<ItemizedForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&doc.csrf_token)?;
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let transaction = doc.transaction(&ledger.base_currency)?;
+
+    let id = web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        sharebill::transaction(&mut conn, |conn| -> Result<_, LedgerError> {
+            let id = sharebill::ledger::Ledger::new(conn, ledger.id)
+                .insert_transaction(Actor::web(user.id), &transaction)?;
+            save_receipt(conn, id, &doc.receipt)?;
+            Ok(id)
+        })
+    })
+    .await?
+    .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to(format!("../{id}/itemized")).see_other())
+}
+
+pub async fn post_itemized(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(doc): web::Form<ItemizedForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&doc.csrf_token)?;
+
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+    let transaction = doc.transaction(&ledger.base_currency)?;
+
+    web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        sharebill::transaction(&mut conn, |conn| -> Result<_, LedgerError> {
+            let mut ledger = sharebill::ledger::Ledger::new(conn, ledger.id);
+            // The form has no tags, so the tags of the post are kept
+            let tags = ledger.get_transaction(id)?.transaction.tags().join(",");
+            let transaction = transaction.with_tags(&tags)?;
+            ledger.replace_transaction(Actor::web(user.id), id, &transaction)?;
+            save_receipt(conn, id, &doc.receipt)?;
+            Ok(())
+        })
+    })
+    .await?
+    .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to("itemized").see_other())
+}
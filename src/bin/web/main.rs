@@ -0,0 +1,1490 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{self};
+
+use actix_web::dev::Service;
+use actix_web::web::Redirect;
+use actix_web::{middleware, web, App, HttpServer, Responder, ResponseError};
+use askama::{Template, *};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use clap::{value_parser, Arg, Command};
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+};
+use num::{BigInt, BigRational, Signed, Zero};
+use serde::de::Error;
+use serde_derive::Deserialize;
+use sharebill::amount::{parse_amount, ParseAmountError};
+use sharebill::attachments::{tx_attachments, AttachmentError};
+use sharebill::audit::{self, Action, Actor};
+use sharebill::auth::Role;
+use sharebill::budgets::{budget_statuses, InvalidBudget};
+use sharebill::closing::{check_tx_open, close_period, closed_before, ClosingError};
+use sharebill::currency::{parse_currency, InvalidCurrency};
+use sharebill::format::AmountFormat;
+use sharebill::ledger::{
+    resolve_currencies, LedgerError, Postings, StoredTransaction, Transaction,
+};
+use sharebill::models::{self, Ledger, NewLedger, NewLedgerMember, TxItem, User};
+use sharebill::post_templates::{ledger_templates, load_template, InvalidTemplate};
+use sharebill::rational::{Rational, RationalVisitor};
+use sharebill::receipts::{has_receipt, ItemizeError};
+use sharebill::schedule::InvalidSchedule;
+use sharebill::schema::{ledger_members, ledgers, users};
+use sharebill::tags::InvalidTag;
+use sharebill::DbConnection;
+use thiserror::Error;
+
+use attachments::AttachmentEntry;
+use auth::{ledger_role, AuthError, CurrentUser};
+use csrf::{CsrfForm, CsrfToken};
+use events::Broadcaster;
+
+mod api;
+mod attachments;
+mod audit_log;
+mod auth;
+mod backups;
+mod budgets;
+mod csrf;
+mod events;
+mod feeds;
+mod itemized;
+mod post_templates;
+mod rates;
+mod recurring;
+mod report;
+mod tokens;
+
+type DbPool = Pool<ConnectionManager<DbConnection>>;
+
+/// Slugs that would be shadowed by other top level routes
+const RESERVED_SLUGS: &[&str] = &["api", "assets", "login", "logout", "tokens"];
+
+/// An amount formatted for display, along with its exact value
+#[derive(Clone)]
+struct FormattedAmount {
+    text: String,
+    exact: String,
+}
+
+impl FormattedAmount {
+    /// Amounts in other currencies than the base currency of the ledger are
+    /// marked with the currency code
+    fn new(
+        format: &AmountFormat,
+        value: &BigRational,
+        currency: &str,
+        base_currency: &str,
+    ) -> Self {
+        if currency == base_currency {
+            Self {
+                text: format.number(value),
+                exact: format.exact(value),
+            }
+        } else {
+            Self {
+                text: format!("{} {currency}", format.number(value)),
+                exact: format!("{} {currency}", format.exact(value)),
+            }
+        }
+    }
+
+    /// Combines amounts in different currencies that go in the same cell
+    fn join(self, other: Self) -> Self {
+        Self {
+            text: format!("{} + {}", self.text, other.text),
+            exact: format!("{} + {}", self.exact, other.exact),
+        }
+    }
+}
+
+struct AccountBalance {
+    account: String,
+    debit: Option<FormattedAmount>,
+    credit: Option<FormattedAmount>,
+}
+
+struct TransactionEntry {
+    id: i32,
+    when_absolute: String,
+    when_relative: String,
+    what: String,
+    tags: Vec<String>,
+    debits: Vec<Option<FormattedAmount>>,
+    credits: Vec<Option<FormattedAmount>>,
+}
+
+struct Transactions {
+    debit_accounts: Vec<String>,
+    credit_accounts: Vec<String>,
+    transactions: Vec<TransactionEntry>,
+}
+
+#[derive(Template)]
+#[template(path = "ledgers.html")]
+struct LedgersTemplate {
+    csrf_token: String,
+    user: User,
+    ledgers: Vec<Ledger>,
+}
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+struct SettingsTemplate {
+    csrf_token: String,
+    ledger: Ledger,
+    members: Vec<(String, Role)>,
+    /// An amount formatted with the ledger's settings
+    example: String,
+    closed_before: Option<NaiveDate>,
+}
+
+#[derive(Template)]
+#[template(path = "overview.html")]
+struct OverviewTemplate {
+    csrf_token: String,
+    user: User,
+    ledger: Ledger,
+    balances: Vec<AccountBalance>,
+    transactions: Transactions,
+    /// All tags in the ledger, for filtering the activity list
+    tags: Vec<String>,
+    /// The tag the activity list is filtered by
+    tag: Option<String>,
+    /// The smallest debit in the activity list, as typed, or empty
+    min_amount: String,
+    /// Budgets that are exceeded in their current period
+    exceeded_budgets: Vec<String>,
+    /// The templates of the quick buttons
+    templates: Vec<models::PostTemplate>,
+}
+
+#[derive(Template)]
+#[template(path = "post.html")]
+struct PostTemplate {
+    csrf_token: String,
+    ledger: Ledger,
+    /// None for a new post that has not been saved yet
+    id: Option<i32>,
+    when: String,
+    what: String,
+    /// Comma separated tags
+    tags: String,
+    /// Account names, amounts and currencies for the input fields
+    debits: Vec<(String, String, String)>,
+    credits: Vec<(String, String, String)>,
+    sum_debits: String,
+    sum_credits: String,
+    rev_time: Option<String>,
+    rev_user: Option<String>,
+    /// The recurring transaction this post was created from
+    recurring_tx_id: Option<i32>,
+    /// Whether the post was entered as an itemized receipt
+    itemized: bool,
+    attachments: Vec<AttachmentEntry>,
+    /// The date the ledger is closed before, when the post cannot be changed
+    closed: Option<NaiveDate>,
+}
+
+fn find_ledger(conn: &mut DbConnection, slug: &str) -> QueryResult<Option<Ledger>> {
+    ledgers::table
+        .filter(ledgers::slug.eq(slug))
+        .first::<Ledger>(conn)
+        .optional()
+}
+
+/// Looks up the ledger with the given slug, requiring `user` to have at least
+/// `role` in it
+async fn load_ledger(
+    pool: &web::Data<DbPool>,
+    slug: String,
+    user: &User,
+    role: Role,
+) -> actix_web::Result<Ledger> {
+    let pool = pool.clone();
+    let user_id = user.id;
+    let (ledger, user_role) = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let Some(ledger) = find_ledger(&mut conn, &slug)? else {
+                return Ok((None, None));
+            };
+            let user_role = ledger_role(&mut conn, ledger.id, user_id)?;
+
+            Ok((Some(ledger), user_role))
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let ledger = ledger.ok_or_else(|| actix_web::error::ErrorNotFound("no such ledger"))?;
+    // None, i.e. not a member, orders before all roles
+    if user_role < Some(role) {
+        return Err(AuthError::Forbidden.into());
+    }
+
+    Ok(ledger)
+}
+
+fn map_db_error(err: diesel::result::Error) -> actix_web::Error {
+    match err {
+        diesel::result::Error::NotFound => actix_web::error::ErrorNotFound(err),
+        err => actix_web::error::ErrorInternalServerError(err),
+    }
+}
+
+/// Changes to closed periods are refused as invalid, and the rest are
+/// database errors
+fn map_closing_error(err: ClosingError) -> actix_web::Error {
+    match err {
+        ClosingError::Db(err) => map_db_error(err),
+        err => ValidationError::from(err).into(),
+    }
+}
+
+/// Invalid transactions and changes to closed periods are refused as
+/// invalid, and the rest are database errors
+fn map_ledger_error(err: LedgerError) -> actix_web::Error {
+    match err {
+        LedgerError::NotFound => actix_web::error::ErrorNotFound(err),
+        LedgerError::Db(err) => actix_web::error::ErrorInternalServerError(err),
+        err => ValidationError::from(err).into(),
+    }
+}
+
+/// Nonzero balances of all accounts in the ledger, per account and currency,
+/// sorted by account name and currency. Positive balances are credit balances.
+fn ledger_balances(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+) -> Result<Vec<(String, String, BigRational)>, LedgerError> {
+    Ok(sharebill::ledger::Ledger::new(conn, ledger_id)
+        .balances(None)?
+        .into_iter()
+        .filter(|(_, balance)| !balance.is_zero())
+        .map(|((account, currency), balance)| (account, currency, balance))
+        .collect())
+}
+
+async fn ledgers_overview(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let user_id = user.id;
+    let ledgers = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let ledgers = ledgers::table
+                .inner_join(ledger_members::table)
+                .filter(ledger_members::user_id.eq(user_id))
+                .order(ledgers::name)
+                .select(ledgers::all_columns)
+                .load::<Ledger>(&mut conn)?;
+
+            Ok(ledgers)
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(LedgersTemplate {
+        csrf_token,
+        user,
+        ledgers,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct LedgerForm {
+    csrf_token: String,
+    slug: String,
+    name: String,
+}
+
+impl LedgerForm {
+    fn validate(&self) -> Result<(), ValidationError> {
+        let valid_slug = !self.slug.is_empty()
+            && self
+                .slug
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && !RESERVED_SLUGS.contains(&self.slug.as_str());
+        if !valid_slug {
+            return Err(ValidationError::InvalidSlug);
+        }
+
+        if self.name.is_empty() {
+            return Err(ValidationError::MissingName);
+        }
+
+        Ok(())
+    }
+}
+
+async fn create_ledger(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<LedgerForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+    form.validate()?;
+
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let ledger_id = diesel::insert_into(ledgers::table)
+            .values(&NewLedger {
+                slug: &form.slug,
+                name: &form.name,
+            })
+            .returning(ledgers::id)
+            .get_result::<i32>(conn)?;
+
+        diesel::insert_into(ledger_members::table)
+            .values(&NewLedgerMember {
+                ledger_id,
+                user_id: user.id,
+                role: Role::Admin,
+            })
+            .execute(conn)?;
+
+        audit::record(
+            conn,
+            Actor::web(user.id),
+            Some(ledger_id),
+            Action::Create,
+            "ledger",
+            serde_json::json!({ "slug": form.slug, "name": form.name }),
+        )
+    })
+    .map_err(|err| match err {
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        ) => ValidationError::DuplicateSlug.into(),
+        err => actix_web::error::ErrorInternalServerError(err),
+    })?;
+
+    Ok(Redirect::to(format!("/{}/", form.slug)).see_other())
+}
+
+async fn ledger_redirect(slug: web::Path<String>) -> impl Responder {
+    Redirect::to(format!("/{slug}/")).permanent()
+}
+
+async fn get_settings(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Admin).await?;
+    let ledger_id = ledger.id;
+
+    let (members, closed_before) = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let members = ledger_members::table
+                .inner_join(users::table)
+                .filter(ledger_members::ledger_id.eq(ledger_id))
+                .order(users::username)
+                .select((users::username, ledger_members::role))
+                .load::<(String, Role)>(&mut conn)?;
+
+            Ok((members, closed_before(&mut conn, ledger_id)?))
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let example = AmountFormat::from(&ledger).amount(&BigRational::new(
+        BigInt::from(-123456789),
+        BigInt::from(100),
+    ));
+
+    Ok(SettingsTemplate {
+        csrf_token,
+        ledger,
+        members,
+        example,
+        closed_before,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct SettingsForm {
+    csrf_token: String,
+    name: String,
+    decimals: i32,
+    decimal_separator: String,
+    thousands_separator: String,
+    currency_symbol: String,
+    /// Checkbox, only present when checked
+    currency_before: Option<String>,
+    base_currency: String,
+}
+
+/// Matches the check constraint on `ledgers.decimals`
+const MAX_DECIMALS: i32 = 6;
+
+impl SettingsForm {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.name.is_empty() {
+            return Err(ValidationError::MissingName);
+        }
+
+        if !(0..=MAX_DECIMALS).contains(&self.decimals) {
+            return Err(ValidationError::InvalidDecimals);
+        }
+
+        // Amounts are entered with the decimal separator, so it has to be one
+        // that `parse_amount` understands
+        if !matches!(self.decimal_separator.as_str(), "." | ",")
+            || self.thousands_separator.chars().count() > 1
+            || self
+                .thousands_separator
+                .chars()
+                .any(|c| c.is_ascii_digit() || c == '-')
+            || self.thousands_separator == self.decimal_separator
+        {
+            return Err(ValidationError::InvalidSeparator);
+        }
+
+        parse_currency(&self.base_currency)?;
+
+        Ok(())
+    }
+}
+
+async fn post_settings(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<SettingsForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+    form.validate()?;
+
+    let base_currency = parse_currency(&form.base_currency).map_err(ValidationError::from)?;
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Admin).await?;
+
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    conn.transaction(|conn| {
+        diesel::update(ledgers::table.find(ledger.id))
+            .set((
+                ledgers::name.eq(&form.name),
+                ledgers::decimals.eq(form.decimals),
+                ledgers::decimal_separator.eq(&form.decimal_separator),
+                ledgers::thousands_separator.eq(&form.thousands_separator),
+                ledgers::currency_symbol.eq(&form.currency_symbol),
+                ledgers::currency_before.eq(form.currency_before.is_some()),
+                ledgers::base_currency.eq(&base_currency),
+            ))
+            .execute(conn)?;
+
+        audit::record(
+            conn,
+            Actor::web(user.id),
+            Some(ledger.id),
+            Action::Edit,
+            "settings",
+            serde_json::json!({
+                "name": form.name,
+                "decimals": form.decimals,
+                "decimal_separator": form.decimal_separator,
+                "thousands_separator": form.thousands_separator,
+                "currency_symbol": form.currency_symbol,
+                "currency_before": form.currency_before.is_some(),
+                "base_currency": base_currency,
+            }),
+        )
+    })
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to("settings").see_other())
+}
+
+#[derive(Debug, Deserialize)]
+struct MemberForm {
+    csrf_token: String,
+    username: String,
+    /// Missing role removes the member
+    role: Option<String>,
+}
+
+async fn post_member(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<MemberForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let role = form
+        .role
+        .as_deref()
+        .map(str::parse::<Role>)
+        .transpose()
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Admin).await?;
+
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    conn.transaction::<_, MemberError, _>(|conn| {
+        let user_id = users::table
+            .filter(users::username.eq(&form.username))
+            .select(users::id)
+            .first::<i32>(conn)
+            .optional()?
+            .ok_or(ValidationError::UnknownUser)?;
+
+        match role {
+            Some(role) => {
+                diesel::insert_into(ledger_members::table)
+                    .values(&NewLedgerMember {
+                        ledger_id: ledger.id,
+                        user_id,
+                        role,
+                    })
+                    .on_conflict((ledger_members::ledger_id, ledger_members::user_id))
+                    .do_update()
+                    .set(ledger_members::role.eq(role))
+                    .execute(conn)?;
+            }
+            None => {
+                diesel::delete(ledger_members::table.find((ledger.id, user_id))).execute(conn)?;
+            }
+        }
+
+        let admins = ledger_members::table
+            .filter(ledger_members::ledger_id.eq(ledger.id))
+            .filter(ledger_members::role.eq(Role::Admin))
+            .count()
+            .get_result::<i64>(conn)?;
+        if admins == 0 {
+            return Err(ValidationError::LastAdmin.into());
+        }
+
+        audit::record(
+            conn,
+            Actor::web(user.id),
+            Some(ledger.id),
+            if role.is_some() {
+                Action::Edit
+            } else {
+                Action::Delete
+            },
+            "member",
+            serde_json::json!({
+                "username": form.username,
+                "role": role.map(|role| role.as_str()),
+            }),
+        )?;
+
+        Ok(())
+    })
+    .map_err(|err| match err {
+        MemberError::Validation(err) => err.into(),
+        MemberError::Db(err) => actix_web::error::ErrorInternalServerError(err),
+    })?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to("settings").see_other())
+}
+
+#[derive(Error, Debug)]
+enum MemberError {
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+    #[error(transparent)]
+    Db(#[from] diesel::result::Error),
+}
+
+#[derive(Debug, Deserialize)]
+struct CloseForm {
+    csrf_token: String,
+    /// Posts before this date are closed
+    date: NaiveDate,
+}
+
+async fn post_close(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<CloseForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Admin).await?;
+
+    let slug = ledger.slug.clone();
+    web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        let today = chrono::Utc::now().date_naive();
+        close_period(&mut conn, ledger.id, form.date, today, Actor::web(user.id))
+    })
+    .await?
+    .map_err(map_closing_error)?;
+    broadcaster.changed(&slug);
+
+    Ok(Redirect::to("settings").see_other())
+}
+
+#[derive(Deserialize)]
+struct OverviewQuery {
+    tag: Option<String>,
+    /// The smallest debit of the listed transactions, as typed
+    min_amount: Option<String>,
+}
+
+async fn overview(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    query: web::Query<OverviewQuery>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+    let format = AmountFormat::from(&ledger);
+    let format1 = format.clone();
+    let base_currency = ledger.base_currency.clone();
+    let base_currency1 = base_currency.clone();
+    let query = query.into_inner();
+    let tag = query.tag.filter(|tag| !tag.is_empty());
+    let tag1 = tag.clone();
+    let min_amount = query.min_amount.unwrap_or_default();
+    let min_value = match min_amount.trim() {
+        "" => None,
+        amount => Some(parse_amount(amount).map_err(ValidationError::from)?),
+    };
+
+    let pool1 = pool.clone();
+    let balances = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool1.get().expect("couldn't get db connection from pool");
+
+            let balances = ledger_balances(&mut conn, ledger_id)?
+                .into_iter()
+                .map(|(account, currency, balance)| AccountBalance {
+                    account,
+                    debit: balance.is_negative().then(|| {
+                        FormattedAmount::new(&format1, &-&balance, &currency, &base_currency1)
+                    }),
+                    credit: balance.is_positive().then(|| {
+                        FormattedAmount::new(&format1, &balance, &currency, &base_currency1)
+                    }),
+                })
+                .collect::<Vec<_>>();
+
+            let tags = sharebill::tags::ledger_tags(&mut conn, ledger_id)?;
+
+            let today = chrono::Utc::now().date_naive();
+            let exceeded_budgets = budget_statuses(&mut conn, ledger_id, today)?
+                .iter()
+                .filter(|status| status.exceeded())
+                .map(|status| budgets::exceeded_summary(&format1, &base_currency1, status))
+                .collect::<Vec<_>>();
+
+            let templates = ledger_templates(&mut conn, ledger_id)?;
+
+            Ok((balances, tags, exceeded_budgets, templates))
+        },
+    );
+
+    let transactions = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let latest_transactions = sharebill::ledger::Ledger::new(&mut conn, ledger_id)
+                .list_transactions(tag1.as_deref(), None, min_value.as_ref(), 10)?;
+
+            let mut debit_accounts = HashMap::<String, usize>::new();
+            let mut credit_accounts = HashMap::<String, usize>::new();
+
+            for stored in &latest_transactions {
+                for (account, _) in stored.transaction.debits().keys() {
+                    debit_accounts.insert(account.clone(), 0);
+                }
+                for (account, _) in stored.transaction.credits().keys() {
+                    credit_accounts.insert(account.clone(), 0);
+                }
+            }
+
+            let mut debit_account_list: Vec<_> = debit_accounts.keys().cloned().collect();
+            debit_account_list.sort_unstable();
+            for (index, account) in debit_account_list.iter().enumerate() {
+                *debit_accounts.get_mut(account).unwrap() = index;
+            }
+
+            let mut credit_account_list: Vec<_> = credit_accounts.keys().cloned().collect();
+            credit_account_list.sort_unstable();
+            for (index, account) in credit_account_list.iter().enumerate() {
+                *credit_accounts.get_mut(account).unwrap() = index;
+            }
+
+            let transactions = latest_transactions
+                .into_iter()
+                .rev()
+                .map(|StoredTransaction { tx, transaction }| {
+                    let amount = |value: &Rational, currency: &str| {
+                        FormattedAmount::new(
+                            &format,
+                            &value.to_big_rational(),
+                            currency,
+                            &base_currency,
+                        )
+                    };
+
+                    let mut d: Vec<Option<FormattedAmount>> = vec![];
+                    d.resize(debit_account_list.len(), Default::default());
+                    for ((account, currency), value) in transaction.debits() {
+                        let cell = &mut d[*debit_accounts.get(account).unwrap()];
+                        let amount = amount(value, currency);
+                        *cell = Some(match cell.take() {
+                            Some(other) => other.join(amount),
+                            None => amount,
+                        });
+                    }
+
+                    let mut c: Vec<Option<FormattedAmount>> = vec![];
+                    c.resize(credit_account_list.len(), Default::default());
+                    for ((account, currency), value) in transaction.credits() {
+                        let cell = &mut c[*credit_accounts.get(account).unwrap()];
+                        let amount = amount(value, currency);
+                        *cell = Some(match cell.take() {
+                            Some(other) => other.join(amount),
+                            None => amount,
+                        });
+                    }
+
+                    let tx_time = tx.tx_time.and_local_timezone(chrono::Utc).unwrap();
+                    TransactionEntry {
+                        id: tx.id,
+                        when_absolute: tx_time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                        when_relative: chrono_humanize::HumanTime::from(
+                            tx_time.signed_duration_since(chrono::Utc::now()),
+                        )
+                        .to_string(),
+                        what: tx.description,
+                        tags: transaction.tags().to_vec(),
+                        debits: d,
+                        credits: c,
+                    }
+                })
+                .collect();
+
+            Ok(Transactions {
+                debit_accounts: debit_account_list,
+                credit_accounts: credit_account_list,
+                transactions,
+            })
+        },
+    );
+
+    let (balances, transactions) = futures::future::join(balances, transactions).await;
+    let (balances, tags, exceeded_budgets, templates) =
+        balances?.map_err(actix_web::error::ErrorInternalServerError)?;
+    let transactions = transactions?.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(OverviewTemplate {
+        csrf_token,
+        user,
+        ledger,
+        balances,
+        transactions,
+        tags,
+        tag,
+        min_amount,
+        exceeded_budgets,
+        templates,
+    })
+}
+
+/// The sum of the postings for each currency, such as "100.00 kr + 12.00 EUR"
+fn sum_by_currency(format: &AmountFormat, items: &[TxItem], base_currency: &str) -> String {
+    let mut sums = BTreeMap::<&str, BigRational>::new();
+    sums.insert(base_currency, BigRational::zero());
+    for item in items {
+        *sums.entry(&item.currency).or_default() += item.value.to_big_rational();
+    }
+
+    sums.into_iter()
+        .filter(|(currency, sum)| *currency == base_currency || !sum.is_zero())
+        .map(|(currency, sum)| {
+            if currency == base_currency {
+                format.amount(&sum)
+            } else {
+                format!("{} {currency}", format.number(&sum))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// The account, amount and currency inputs of a posting in a form
+fn posting_input(format: &AmountFormat, item: TxItem) -> (String, String, String) {
+    let value = item.value.to_big_rational();
+    let value = if value.is_zero() {
+        String::new()
+    } else {
+        format.exact(&value)
+    };
+    (item.account, value, item.currency)
+}
+
+async fn get_transaction(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+
+    let transaction = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let stored = sharebill::ledger::Ledger::new(&mut conn, ledger_id).get_transaction(id);
+            let stored = match stored {
+                Ok(stored) => stored,
+                Err(LedgerError::NotFound) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+
+            let rev_user = match stored.tx.rev_user_id {
+                Some(rev_user_id) => Some(
+                    users::table
+                        .find(rev_user_id)
+                        .select(users::username)
+                        .first::<String>(&mut conn)?,
+                ),
+                None => None,
+            };
+
+            let itemized = has_receipt(&mut conn, id)?;
+            let attachments = tx_attachments(&mut conn, id)?;
+
+            let closed = match check_tx_open(&mut conn, id) {
+                Ok(()) => None,
+                Err(ClosingError::Closed(date)) => Some(date),
+                Err(err) => return Err(err.into()),
+            };
+
+            Ok(Some((stored, rev_user, itemized, attachments, closed)))
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    let (stored, rev_user, itemized, attachments, closed) =
+        transaction.ok_or_else(|| actix_web::error::ErrorNotFound("no such post"))?;
+    let transaction = stored.tx;
+    let tags = stored.transaction.tags().join(", ");
+    let items = |postings: &Postings| {
+        postings
+            .iter()
+            .map(|((account, currency), value)| TxItem {
+                account: account.clone(),
+                currency: currency.clone(),
+                value: value.clone(),
+            })
+            .collect::<Vec<_>>()
+    };
+    let debits = items(stored.transaction.debits());
+    let credits = items(stored.transaction.credits());
+    let rev_time = transaction
+        .rev_time
+        .and_local_timezone(chrono::Utc)
+        .unwrap()
+        .to_rfc3339_opts(SecondsFormat::Millis, true);
+
+    let format = AmountFormat::from(&ledger);
+    let sum_debits = sum_by_currency(&format, &debits, &ledger.base_currency);
+    let sum_credits = sum_by_currency(&format, &credits, &ledger.base_currency);
+
+    let input = |item| posting_input(&format, item);
+    let mut debits = debits.into_iter().map(input).collect::<Vec<_>>();
+    let mut credits = credits.into_iter().map(input).collect::<Vec<_>>();
+
+    let rows = std::cmp::max(std::cmp::max(debits.len(), credits.len()) + 3, 5);
+    let empty_row = (String::new(), String::new(), ledger.base_currency.clone());
+    debits.resize(rows, empty_row.clone());
+    credits.resize(rows, empty_row);
+
+    Ok(PostTemplate {
+        csrf_token,
+        ledger,
+        id: Some(id),
+        what: transaction.description,
+        tags,
+        when: transaction
+            .tx_time
+            .and_local_timezone(chrono::Utc)
+            .unwrap()
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+        debits,
+        credits,
+        sum_debits,
+        sum_credits,
+        rev_time: Some(rev_time),
+        rev_user,
+        recurring_tx_id: transaction.recurring_tx_id,
+        itemized,
+        attachments: attachments.into_iter().map(AttachmentEntry::from).collect(),
+        closed,
+    })
+}
+
+#[derive(Deserialize)]
+struct NewPostQuery {
+    /// The id of a template to fill the form in from
+    template: Option<i32>,
+    /// The total to scale the template to, where none leaves the amounts empty
+    total: Option<String>,
+}
+
+/// An empty post form, or one filled in from a template. Nothing is stored,
+/// and no id is allocated, until it is saved.
+async fn new_transaction(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    query: web::Query<NewPostQuery>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let ledger_id = ledger.id;
+    let NewPostQuery { template, total } = query.into_inner();
+
+    let total = match total.as_deref().map(str::trim) {
+        None | Some("") => Rational::default(),
+        Some(total) => parse_amount(total).map_err(|_| ValidationError::InvalidTotal)?,
+    };
+    let template = match template {
+        Some(id) => Some(
+            web::block(move || {
+                let mut conn = pool.get().expect("couldn't get db connection from pool");
+                load_template(&mut conn, ledger_id, id)
+            })
+            .await?
+            .map_err(map_db_error)?,
+        ),
+        None => None,
+    };
+
+    let (what, debits, credits) = match &template {
+        Some(template) => {
+            let (debits, credits) = template.postings(&total, &ledger.base_currency);
+            (template.description().to_owned(), debits, credits)
+        }
+        None => (String::new(), Postings::new(), Postings::new()),
+    };
+    let items = |postings: Postings| {
+        postings
+            .into_iter()
+            .map(|((account, currency), value)| TxItem {
+                account,
+                currency,
+                value,
+            })
+            .collect::<Vec<_>>()
+    };
+    let debits = items(debits);
+    let credits = items(credits);
+
+    let format = AmountFormat::from(&ledger);
+    let sum_debits = sum_by_currency(&format, &debits, &ledger.base_currency);
+    let sum_credits = sum_by_currency(&format, &credits, &ledger.base_currency);
+
+    let input = |item| posting_input(&format, item);
+    let mut debits = debits.into_iter().map(input).collect::<Vec<_>>();
+    let mut credits = credits.into_iter().map(input).collect::<Vec<_>>();
+
+    let rows = std::cmp::max(std::cmp::max(debits.len(), credits.len()) + 3, 5);
+    let empty_row = (String::new(), String::new(), ledger.base_currency.clone());
+    debits.resize(rows, empty_row.clone());
+    credits.resize(rows, empty_row);
+
+    Ok(PostTemplate {
+        csrf_token,
+        ledger,
+        id: None,
+        what,
+        tags: String::new(),
+        when: chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        debits,
+        credits,
+        sum_debits,
+        sum_credits,
+        rev_time: None,
+        rev_user: None,
+        recurring_tx_id: None,
+        itemized: false,
+        attachments: vec![],
+        closed: None,
+    })
+}
+
+struct TransactionItemsVisitor {
+    key_field: &'static str,
+    value_field: &'static str,
+    currency_field: &'static str,
+}
+
+impl<'de> serde::de::Visitor<'de> for TransactionItemsVisitor {
+    type Value = Postings;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter,
+            "form fields {}, {} and {} for each posting",
+            self.key_field, self.value_field, self.currency_field
+        )
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        // (account, value, currency) for each row of the form
+        let mut rows = Vec::<(String, String, String)>::new();
+
+        while let Some((key, value)) = map.next_entry::<String, String>()? {
+            if key == self.key_field {
+                rows.push((value, String::new(), String::new()));
+            } else if let Some(row) = rows.last_mut() {
+                if key == self.value_field {
+                    row.1 = value;
+                } else if key == self.currency_field {
+                    row.2 = value;
+                }
+            }
+        }
+
+        rows.into_iter()
+            // ignore empty rows
+            .filter(|(account, value, _)| !(account.is_empty() && value.is_empty()))
+            .map(|(account, value, currency)| {
+                let value = parse_amount(&value).map_err(|_| {
+                    A::Error::invalid_value(serde::de::Unexpected::Str(&value), &RationalVisitor)
+                })?;
+                Ok(((account, currency), value))
+            })
+            .collect()
+    }
+}
+
+fn deserialize_debits<'de, D>(deserializer: D) -> Result<Postings, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserializer.deserialize_map(TransactionItemsVisitor {
+        key_field: "debit_account",
+        value_field: "debit_value",
+        currency_field: "debit_currency",
+    })
+}
+
+fn deserialize_credits<'de, D>(deserializer: D) -> Result<Postings, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserializer.deserialize_map(TransactionItemsVisitor {
+        key_field: "credit_account",
+        value_field: "credit_value",
+        currency_field: "credit_currency",
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct InsertTransaction {
+    csrf_token: String,
+    when: DateTime<Utc>,
+    what: String,
+    #[serde(default)]
+    tags: String,
+
+    #[serde(flatten, deserialize_with = "deserialize_debits")]
+    debits: Postings,
+
+    #[serde(flatten, deserialize_with = "deserialize_credits")]
+    credits: Postings,
+}
+
+#[derive(Error, Debug)]
+enum ValidationError {
+    #[error(transparent)]
+    InvalidTransaction(#[from] LedgerError),
+    #[error("empty account name")]
+    EmptyAccountName,
+    #[error("invalid ledger slug, use lowercase letters, digits and dashes")]
+    InvalidSlug,
+    #[error("a ledger with that slug already exists")]
+    DuplicateSlug,
+    #[error("missing name")]
+    MissingName,
+    #[error("no user with that username")]
+    UnknownUser,
+    #[error("a ledger must have at least one admin")]
+    LastAdmin,
+    #[error(transparent)]
+    InvalidCurrency(#[from] InvalidCurrency),
+    #[error(transparent)]
+    InvalidSchedule(#[from] InvalidSchedule),
+    #[error(transparent)]
+    InvalidTag(#[from] InvalidTag),
+    #[error(transparent)]
+    InvalidAttachment(#[from] AttachmentError),
+    #[error(transparent)]
+    InvalidReceipt(#[from] ItemizeError),
+    #[error(transparent)]
+    InvalidBudget(#[from] InvalidBudget),
+    #[error(transparent)]
+    PeriodClosed(#[from] ClosingError),
+    #[error(transparent)]
+    InvalidTemplate(#[from] InvalidTemplate),
+    #[error(transparent)]
+    InvalidAmount(#[from] ParseAmountError),
+    #[error("invalid total")]
+    InvalidTotal,
+    #[error("budget limits must be positive")]
+    InvalidBudgetAmount,
+    #[error("the end date must be a date after the start date")]
+    InvalidEndDate,
+    #[error(
+        "exchange rates must be positive, and given for other currencies than the base currency"
+    )]
+    InvalidRate,
+    #[error("the number of decimals must be between 0 and 6")]
+    InvalidDecimals,
+    #[error("invalid separators, the decimal separator must be . or , and the thousands separator a different single character that is not a digit")]
+    InvalidSeparator,
+}
+
+impl ResponseError for ValidationError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::BAD_REQUEST
+    }
+}
+
+impl InsertTransaction {
+    /// The transaction of the form, with currencies defaulting to
+    /// `base_currency`
+    fn transaction(self, base_currency: &str) -> Result<Transaction, LedgerError> {
+        Transaction::new(
+            self.when.naive_utc(),
+            self.what,
+            &self.tags,
+            resolve_currencies(self.debits, base_currency)?,
+            resolve_currencies(self.credits, base_currency)?,
+        )
+    }
+}
+
+/// Postings as a JSON list, sorted by account and currency, for the audit log
+fn postings_json(postings: &Postings) -> serde_json::Value {
+    postings
+        .iter()
+        .map(|((account, currency), value)| {
+            serde_json::json!({ "account": account, "currency": currency, "value": value })
+        })
+        .collect()
+}
+
+async fn create_transaction(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(doc): web::Form<InsertTransaction>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&doc.csrf_token)?;
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let transaction = doc
+        .transaction(&ledger.base_currency)
+        .map_err(map_ledger_error)?;
+
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    let id = sharebill::ledger::Ledger::new(&mut conn, ledger.id)
+        .insert_transaction(Actor::web(user.id), &transaction)
+        .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to(format!("{id}")).see_other())
+}
+
+async fn post_transaction(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(doc): web::Form<InsertTransaction>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&doc.csrf_token)?;
+
+    let (slug, id) = path.into_inner();
+
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+    let transaction = doc
+        .transaction(&ledger.base_currency)
+        .map_err(map_ledger_error)?;
+
+    // FIXME, send blocking db-code off to a background context
+
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    sharebill::ledger::Ledger::new(&mut conn, ledger.id)
+        .replace_transaction(Actor::web(user.id), id, &transaction)
+        .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    // ON SUCCESS redirect to GET of the same URL
+    Ok(Redirect::to("").see_other())
+}
+
+async fn delete_transaction(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<CsrfForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    sharebill::ledger::Ledger::new(&mut conn, ledger.id)
+        .delete_transaction(Actor::web(user.id), id)
+        .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to("../../").see_other())
+}
+
+#[actix_web::main]
+async fn main() -> io::Result<()> {
+    let matches = Command::new("web")
+        .about("Serves the web interface and the API on port 8080")
+        .arg(
+            Arg::new("backup-dir")
+                .long("backup-dir")
+                .value_parser(value_parser!(PathBuf))
+                .help("Directory to write periodic backups to, defaults to no backups"),
+        )
+        .arg(
+            Arg::new("backup-hours")
+                .long("backup-hours")
+                .default_value("24")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Hours between backups"),
+        )
+        .arg(
+            Arg::new("backup-keep")
+                .long("backup-keep")
+                .default_value("7")
+                .value_parser(value_parser!(usize))
+                .help("How many backups to keep, deleting the oldest ones"),
+        )
+        .get_matches();
+
+    let database_url = sharebill::database_url();
+    let pool = sharebill::create_pool(&database_url).expect("Could not create DB pool");
+
+    let broadcaster = events::Broadcaster::spawn();
+
+    recurring::spawn_scheduler(pool.clone(), broadcaster.clone());
+    if let Some(dir) = matches.get_one::<PathBuf>("backup-dir") {
+        sharebill::backup::check_supported()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let hours = *matches.get_one::<u64>("backup-hours").unwrap();
+        backups::spawn_backups(
+            database_url,
+            dir.clone(),
+            Duration::from_secs(hours * 60 * 60),
+            *matches.get_one::<usize>("backup-keep").unwrap(),
+        );
+    }
+
+    HttpServer::new(move || {
+        App::new()
+            .wrap(
+                middleware::DefaultHeaders::new()
+                    .add(("X-Content-Type-Options", "nosniff"))
+                    .add(("X-Frame-Options", "DENY"))
+                    .add(("Referrer-Policy", "same-origin"))
+                    .add((
+                        "Content-Security-Policy",
+                        "default-src 'self'; frame-ancestors 'none'; form-action 'self'",
+                    )),
+            )
+            .wrap_fn(|req, srv| {
+                let new_csrf_token = csrf::ensure_token(&req);
+                let secure = req.connection_info().scheme() == "https";
+                let res = srv.call(req);
+                async move {
+                    let mut res = res.await?;
+                    if let Some(token) = new_csrf_token {
+                        res.response_mut()
+                            .add_cookie(&csrf::cookie(token, secure))?;
+                    }
+                    Ok(res)
+                }
+            })
+            .service(actix_files::Files::new("/assets", "assets"))
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(broadcaster.clone()))
+            .route("/login", web::get().to(auth::get_login))
+            .route("/login", web::post().to(auth::post_login))
+            .route("/logout", web::post().to(auth::post_logout))
+            .route("/tokens", web::get().to(tokens::get_tokens))
+            .route("/tokens", web::post().to(tokens::create_token))
+            .route("/tokens/{id}/revoke", web::post().to(tokens::revoke_token))
+            .route("/api/{ledger}/balances", web::get().to(api::get_balances))
+            .route(
+                "/api/{ledger}/transactions",
+                web::get().to(api::get_transactions),
+            )
+            .route(
+                "/api/{ledger}/transactions",
+                web::post().to(api::post_transaction),
+            )
+            .route(
+                "/api/{ledger}/transactions/{id}",
+                web::get().to(api::get_transaction),
+            )
+            .route(
+                "/api/{ledger}/feed.atom",
+                web::get().to(feeds::get_ledger_feed),
+            )
+            .route(
+                "/api/{ledger}/accounts/{account}/feed.atom",
+                web::get().to(feeds::get_account_feed),
+            )
+            .route("/", web::get().to(ledgers_overview))
+            .route("/", web::post().to(create_ledger))
+            .route("/{ledger}", web::get().to(ledger_redirect))
+            .route("/{ledger}/", web::get().to(overview))
+            .route("/{ledger}/events", web::get().to(events::get_events))
+            .route("/{ledger}/settings", web::get().to(get_settings))
+            .route("/{ledger}/settings", web::post().to(post_settings))
+            .route("/{ledger}/members", web::post().to(post_member))
+            .route("/{ledger}/close", web::post().to(post_close))
+            .route("/{ledger}/audit", web::get().to(audit_log::get_audit))
+            .route("/{ledger}/rates", web::get().to(rates::get_rates))
+            .route("/{ledger}/rates", web::post().to(rates::post_rate))
+            .route("/{ledger}/converted", web::get().to(rates::get_converted))
+            .route("/{ledger}/report", web::get().to(report::get_report))
+            .route("/{ledger}/budgets", web::get().to(budgets::get_budgets))
+            .route("/{ledger}/budgets", web::post().to(budgets::post_budget))
+            .route(
+                "/{ledger}/recurring",
+                web::get().to(recurring::list_recurring),
+            )
+            .route(
+                "/{ledger}/recurring/new",
+                web::get().to(recurring::new_recurring),
+            )
+            .route(
+                "/{ledger}/recurring/new",
+                web::post().to(recurring::create_recurring),
+            )
+            .route(
+                "/{ledger}/recurring/{id}",
+                web::get().to(recurring::get_recurring),
+            )
+            .route(
+                "/{ledger}/recurring/{id}",
+                web::post().to(recurring::post_recurring),
+            )
+            .route(
+                "/{ledger}/recurring/{id}/stop",
+                web::post().to(recurring::stop_recurring),
+            )
+            .route(
+                "/{ledger}/templates",
+                web::get().to(post_templates::list_templates),
+            )
+            .route(
+                "/{ledger}/templates/new",
+                web::get().to(post_templates::new_template),
+            )
+            .route(
+                "/{ledger}/templates/new",
+                web::post().to(post_templates::create_template),
+            )
+            .route(
+                "/{ledger}/templates/{id}",
+                web::get().to(post_templates::get_template),
+            )
+            .route(
+                "/{ledger}/templates/{id}",
+                web::post().to(post_templates::post_template),
+            )
+            .route(
+                "/{ledger}/templates/{id}/delete",
+                web::post().to(post_templates::delete_template),
+            )
+            .route("/{ledger}/post/new", web::get().to(new_transaction))
+            .route("/{ledger}/post/new", web::post().to(create_transaction))
+            .route(
+                "/{ledger}/post/new/itemized",
+                web::get().to(itemized::new_itemized),
+            )
+            .route(
+                "/{ledger}/post/new/itemized",
+                web::post().to(itemized::create_itemized),
+            )
+            .route("/{ledger}/post/{id}", web::get().to(get_transaction))
+            .route("/{ledger}/post/{id}", web::post().to(post_transaction))
+            .route(
+                "/{ledger}/post/{id}/delete",
+                web::post().to(delete_transaction),
+            )
+            .route(
+                "/{ledger}/post/{id}/itemized",
+                web::get().to(itemized::get_itemized),
+            )
+            .route(
+                "/{ledger}/post/{id}/itemized",
+                web::post().to(itemized::post_itemized),
+            )
+            .route(
+                "/{ledger}/post/{id}/attachments",
+                web::post().to(attachments::upload_attachment),
+            )
+            .route(
+                "/{ledger}/post/{id}/attachments/{attachment_id}",
+                web::get().to(attachments::get_attachment),
+            )
+            .route(
+                "/{ledger}/post/{id}/attachments/{attachment_id}/delete",
+                web::post().to(attachments::delete_attachment),
+            )
+    })
+    .bind(("127.0.0.1", 8080))?
+    .run()
+    .await
+}
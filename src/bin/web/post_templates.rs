@@ -0,0 +1,248 @@
+//! Pages for managing post templates, the quick buttons on the overview
+
+use actix_web::web::Redirect;
+use actix_web::{web, Responder};
+use askama::Template;
+use serde_derive::Deserialize;
+use sharebill::audit::Actor;
+use sharebill::auth::Role;
+use sharebill::ledger::Postings;
+use sharebill::models::{Ledger, PostTemplate, User};
+use sharebill::post_templates::{
+    self, ledger_templates, load_template, save_template, Shares, TemplateError,
+};
+
+use crate::auth::CurrentUser;
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::events::Broadcaster;
+use crate::{
+    deserialize_credits, deserialize_debits, load_ledger, map_db_error, DbPool, ValidationError,
+};
+
+#[derive(Template)]
+#[template(path = "post_templates.html")]
+struct TemplateListTemplate {
+    csrf_token: String,
+    user: User,
+    ledger: Ledger,
+    templates: Vec<PostTemplate>,
+}
+
+#[derive(Template)]
+#[template(path = "post_template.html")]
+struct TemplateFormTemplate {
+    csrf_token: String,
+    ledger: Ledger,
+    /// None for a template that has not been saved yet
+    id: Option<i32>,
+    name: String,
+    what: String,
+    /// Account names and shares for the input fields
+    debits: Vec<(String, String)>,
+    credits: Vec<(String, String)>,
+}
+
+fn map_template_error(err: TemplateError) -> actix_web::Error {
+    match err {
+        TemplateError::Invalid(err) => ValidationError::from(err).into(),
+        TemplateError::Db(err) => map_db_error(err),
+    }
+}
+
+/// The account and share inputs of a template, with some empty rows to add
+/// more accounts. Shares are shown exactly, such as 1 or 1/3.
+fn share_inputs(debits: &Shares, credits: &Shares) -> [Vec<(String, String)>; 2] {
+    let rows = std::cmp::max(std::cmp::max(debits.len(), credits.len()) + 3, 5);
+    [debits, credits].map(|shares| {
+        let mut inputs = shares
+            .iter()
+            .map(|(account, share)| (account.clone(), share.to_string()))
+            .collect::<Vec<_>>();
+        inputs.resize(rows, Default::default());
+        inputs
+    })
+}
+
+pub async fn list_templates(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+
+    let templates = web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        ledger_templates(&mut conn, ledger_id)
+    })
+    .await?
+    .map_err(map_db_error)?;
+
+    Ok(TemplateListTemplate {
+        csrf_token,
+        user,
+        ledger,
+        templates,
+    })
+}
+
+pub async fn new_template(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let [debits, credits] = share_inputs(&Shares::new(), &Shares::new());
+
+    Ok(TemplateFormTemplate {
+        csrf_token,
+        ledger,
+        id: None,
+        name: String::new(),
+        what: String::new(),
+        debits,
+        credits,
+    })
+}
+
+pub async fn get_template(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+
+    let template = web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        load_template(&mut conn, ledger_id, id)
+    })
+    .await?
+    .map_err(map_db_error)?;
+
+    let [debits, credits] = share_inputs(template.debits(), template.credits());
+
+    Ok(TemplateFormTemplate {
+        csrf_token,
+        ledger,
+        id: Some(id),
+        name: template.name().to_owned(),
+        what: template.description().to_owned(),
+        debits,
+        credits,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TemplateForm {
+    csrf_token: String,
+    name: String,
+    what: String,
+
+    #[serde(flatten, deserialize_with = "deserialize_debits")]
+    debits: Postings,
+
+    #[serde(flatten, deserialize_with = "deserialize_credits")]
+    credits: Postings,
+}
+
+impl TemplateForm {
+    fn template(self) -> Result<post_templates::Template, ValidationError> {
+        // The form has no currencies, the shares are plain ratios
+        let shares = |postings: Postings| {
+            let mut shares = Shares::new();
+            for ((account, _), share) in postings {
+                *shares.entry(account).or_default() += share;
+            }
+            shares
+        };
+        Ok(post_templates::Template::new(
+            self.name,
+            self.what,
+            shares(self.debits),
+            shares(self.credits),
+        )?)
+    }
+}
+
+fn save(
+    pool: web::Data<DbPool>,
+    ledger_id: i32,
+    id: Option<i32>,
+    template: post_templates::Template,
+    user_id: i32,
+) -> Result<i32, TemplateError> {
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    save_template(&mut conn, ledger_id, id, &template, Actor::web(user_id))
+}
+
+pub async fn create_template(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<TemplateForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let template = form.template()?;
+
+    let id = web::block(move || save(pool, ledger.id, None, template, user.id))
+        .await?
+        .map_err(map_template_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to(format!("{id}")).see_other())
+}
+
+pub async fn post_template(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<TemplateForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+    let template = form.template()?;
+
+    web::block(move || save(pool, ledger.id, Some(id), template, user.id))
+        .await?
+        .map_err(map_template_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to(format!("{id}")).see_other())
+}
+
+pub async fn delete_template(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<CsrfForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+
+    web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        post_templates::delete_template(&mut conn, ledger.id, id, Actor::web(user.id))
+    })
+    .await?
+    .map_err(map_db_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to("../../templates").see_other())
+}
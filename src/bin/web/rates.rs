@@ -0,0 +1,244 @@
+//! Exchange rates of a ledger, and its balances converted to the base currency
+
+use actix_web::web::Redirect;
+use actix_web::{web, Responder};
+use askama::Template;
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use num::{Signed, Zero};
+use serde_derive::Deserialize;
+use sharebill::audit::{self, Action, Actor};
+use sharebill::auth::Role;
+use sharebill::currency::{convert_balances, parse_currency, parse_rate};
+use sharebill::format::AmountFormat;
+use sharebill::models::{Ledger, NewExchangeRate, User};
+use sharebill::rational::Rational;
+use sharebill::schema::exchange_rates;
+
+use crate::auth::CurrentUser;
+use crate::csrf::CsrfToken;
+use crate::events::Broadcaster;
+use crate::{
+    ledger_balances, load_ledger, AccountBalance, DbPool, FormattedAmount, ValidationError,
+};
+
+struct RateEntry {
+    currency: String,
+    date: String,
+    rate: String,
+}
+
+#[derive(Template)]
+#[template(path = "rates.html")]
+struct RatesTemplate {
+    csrf_token: String,
+    user: User,
+    ledger: Ledger,
+    rates: Vec<RateEntry>,
+    today: String,
+}
+
+#[derive(Template)]
+#[template(path = "converted.html")]
+struct ConvertedTemplate {
+    csrf_token: String,
+    user: User,
+    ledger: Ledger,
+    date: String,
+    balances: Vec<AccountBalance>,
+    missing_rates: Vec<String>,
+}
+
+pub async fn get_rates(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+    let format = AmountFormat::from(&ledger);
+
+    let rates = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let rates = exchange_rates::table
+                .filter(exchange_rates::ledger_id.eq(ledger_id))
+                .order((exchange_rates::currency, exchange_rates::date.desc()))
+                .select((
+                    exchange_rates::currency,
+                    exchange_rates::date,
+                    exchange_rates::rate,
+                ))
+                .load::<(String, NaiveDate, Rational)>(&mut conn)?;
+
+            Ok(rates)
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let rates = rates
+        .into_iter()
+        .map(|(currency, date, rate)| RateEntry {
+            currency,
+            date: date.to_string(),
+            rate: format.exact(&rate.to_big_rational()),
+        })
+        .collect();
+
+    Ok(RatesTemplate {
+        csrf_token,
+        user,
+        ledger,
+        rates,
+        today: chrono::Utc::now().date_naive().to_string(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RateForm {
+    csrf_token: String,
+    currency: String,
+    date: NaiveDate,
+    /// Units of the base currency per unit of `currency`. Empty removes the
+    /// rate for the date.
+    rate: String,
+}
+
+pub async fn post_rate(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<RateForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let currency = parse_currency(&form.currency).map_err(ValidationError::from)?;
+    let rate = match form.rate.trim() {
+        "" => None,
+        rate => Some(parse_rate(rate).map_err(|_| ValidationError::InvalidRate)?),
+    };
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    if currency == ledger.base_currency {
+        return Err(ValidationError::InvalidRate.into());
+    }
+
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    conn.transaction(|conn| {
+        let payload = serde_json::json!({
+            "currency": currency,
+            "date": form.date,
+            "rate": rate,
+        });
+        let action = match rate {
+            Some(rate) => {
+                diesel::insert_into(exchange_rates::table)
+                    .values(&NewExchangeRate {
+                        ledger_id: ledger.id,
+                        currency: &currency,
+                        date: form.date,
+                        rate: rate.clone(),
+                    })
+                    .on_conflict((
+                        exchange_rates::ledger_id,
+                        exchange_rates::currency,
+                        exchange_rates::date,
+                    ))
+                    .do_update()
+                    .set(exchange_rates::rate.eq(rate))
+                    .execute(conn)?;
+                Action::Edit
+            }
+            None => {
+                diesel::delete(exchange_rates::table.find((ledger.id, &currency, form.date)))
+                    .execute(conn)?;
+                Action::Delete
+            }
+        };
+
+        audit::record(
+            conn,
+            Actor::web(user.id),
+            Some(ledger.id),
+            action,
+            "rate",
+            payload,
+        )
+    })
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to("rates").see_other())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConvertedQuery {
+    /// Defaults to today
+    date: Option<NaiveDate>,
+}
+
+pub async fn get_converted(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    query: web::Query<ConvertedQuery>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Viewer).await?;
+    let date = query
+        .date
+        .unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let format = AmountFormat::from(&ledger);
+
+    let (ledger, converted) = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let balances = ledger_balances(&mut conn, ledger.id)?;
+            let converted = convert_balances(&mut conn, &ledger, balances, date)?;
+
+            Ok((ledger, converted))
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let balances = converted
+        .balances
+        .into_iter()
+        .filter(|(_, balance)| !balance.is_zero())
+        .map(|(account, balance)| AccountBalance {
+            account,
+            debit: balance.is_negative().then(|| {
+                FormattedAmount::new(
+                    &format,
+                    &-&balance,
+                    &ledger.base_currency,
+                    &ledger.base_currency,
+                )
+            }),
+            credit: balance.is_positive().then(|| {
+                FormattedAmount::new(
+                    &format,
+                    &balance,
+                    &ledger.base_currency,
+                    &ledger.base_currency,
+                )
+            }),
+        })
+        .collect();
+
+    Ok(ConvertedTemplate {
+        csrf_token,
+        user,
+        ledger,
+        date: date.to_string(),
+        balances,
+        missing_rates: converted.missing_rates,
+    })
+}
@@ -0,0 +1,542 @@
+//! Pages for managing recurring transactions, and the scheduler that turns
+//! them into ordinary transactions
+
+use actix_web::web::Redirect;
+use actix_web::{web, Responder};
+use askama::Template;
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use serde_derive::Deserialize;
+use sharebill::audit::{self, Action, Actor};
+use sharebill::auth::Role;
+use sharebill::format::AmountFormat;
+use sharebill::ledger::{resolve_currencies, validate_postings, LedgerError, Postings};
+use sharebill::models::{
+    Ledger, NewRecurringCredit, NewRecurringDebit, NewRecurringTx, RecurringTx, TxItem, User,
+};
+use sharebill::recurring::{materialize, materialize_all};
+use sharebill::schedule::Schedule;
+use sharebill::schema::{recurring_credits, recurring_debits, recurring_txs, txs};
+use sharebill::DbConnection;
+
+use crate::auth::CurrentUser;
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::events::Broadcaster;
+use crate::{
+    deserialize_credits, deserialize_debits, load_ledger, map_db_error, map_ledger_error,
+    posting_input, postings_json, sum_by_currency, DbPool, ValidationError,
+};
+
+/// How often the web server looks for due recurring transactions
+const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+struct RecurringEntry {
+    id: i32,
+    what: String,
+    schedule: String,
+    start_date: String,
+    end_date: Option<String>,
+    next_date: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "recurring_list.html")]
+struct RecurringListTemplate {
+    csrf_token: String,
+    user: User,
+    ledger: Ledger,
+    recurring: Vec<RecurringEntry>,
+}
+
+#[derive(Template)]
+#[template(path = "recurring.html")]
+struct RecurringTemplate {
+    csrf_token: String,
+    ledger: Ledger,
+    /// None for a recurring transaction that has not been saved yet
+    id: Option<i32>,
+    what: String,
+    schedule: String,
+    start_date: String,
+    end_date: String,
+    /// Account names, amounts and currencies for the input fields
+    debits: Vec<(String, String, String)>,
+    credits: Vec<(String, String, String)>,
+    sum_debits: String,
+    sum_credits: String,
+    /// Number of transactions created from it so far
+    created: i64,
+}
+
+/// Runs `materialize_all` now and then every `SCHEDULER_INTERVAL` for as long
+/// as the server runs, telling open overviews when it has posted anything
+pub fn spawn_scheduler(pool: DbPool, broadcaster: Broadcaster) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(SCHEDULER_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let pool = pool.clone();
+            let result = web::block(move || {
+                let mut conn = pool.get().expect("couldn't get db connection from pool");
+                materialize_all(
+                    &mut conn,
+                    chrono::Utc::now().date_naive(),
+                    Actor::scheduler(),
+                )
+            })
+            .await;
+
+            match result {
+                Ok(Ok(materialized)) => {
+                    for (id, err) in &materialized.failed {
+                        eprintln!("Error creating recurring transaction {id}: {err}");
+                    }
+                    if materialized.created > 0 {
+                        broadcaster.all_changed();
+                    }
+                }
+                Ok(Err(err)) => eprintln!("Error creating recurring transactions: {err}"),
+                Err(err) => eprintln!("Error creating recurring transactions: {err}"),
+            }
+        }
+    });
+}
+
+pub async fn list_recurring(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+
+    let templates = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let templates = recurring_txs::table
+                .filter(recurring_txs::ledger_id.eq(ledger_id))
+                .order(recurring_txs::description)
+                .load::<RecurringTx>(&mut conn)?;
+
+            Ok(templates)
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let recurring = templates
+        .into_iter()
+        .map(|template| {
+            // The first occurrence after the last one that was created
+            let next_date = template
+                .schedule
+                .next_after(template.start_date, template.last_date)
+                .filter(|date| match template.end_date {
+                    Some(end_date) => *date <= end_date,
+                    None => true,
+                });
+
+            RecurringEntry {
+                id: template.id,
+                what: template.description,
+                schedule: template.schedule.to_string(),
+                start_date: template.start_date.to_string(),
+                end_date: template.end_date.map(|date| date.to_string()),
+                next_date: next_date.map(|date| date.to_string()),
+            }
+        })
+        .collect();
+
+    Ok(RecurringListTemplate {
+        csrf_token,
+        user,
+        ledger,
+        recurring,
+    })
+}
+
+pub async fn new_recurring(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let format = AmountFormat::from(&ledger);
+    let zero = sum_by_currency(&format, &[], &ledger.base_currency);
+    let empty_row = (String::new(), String::new(), ledger.base_currency.clone());
+
+    Ok(RecurringTemplate {
+        csrf_token,
+        ledger,
+        id: None,
+        what: String::new(),
+        schedule: "monthly 1".to_owned(),
+        start_date: chrono::Utc::now().date_naive().to_string(),
+        end_date: String::new(),
+        debits: vec![empty_row.clone(); 5],
+        credits: vec![empty_row; 5],
+        sum_debits: zero.clone(),
+        sum_credits: zero,
+        created: 0,
+    })
+}
+
+pub async fn get_recurring(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Viewer).await?;
+    let ledger_id = ledger.id;
+
+    let (template, debits, credits, created) =
+        web::block(move || -> Result<_, diesel::result::Error> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let template = recurring_txs::table
+                .find(id)
+                .filter(recurring_txs::ledger_id.eq(ledger_id))
+                .first::<RecurringTx>(&mut conn)?;
+            let debits = recurring_debits::table
+                .filter(recurring_debits::recurring_tx_id.eq(id))
+                .select((
+                    recurring_debits::account,
+                    recurring_debits::currency,
+                    recurring_debits::value,
+                ))
+                .load::<TxItem>(&mut conn)?;
+            let credits = recurring_credits::table
+                .filter(recurring_credits::recurring_tx_id.eq(id))
+                .select((
+                    recurring_credits::account,
+                    recurring_credits::currency,
+                    recurring_credits::value,
+                ))
+                .load::<TxItem>(&mut conn)?;
+            let created = txs::table
+                .filter(txs::recurring_tx_id.eq(id))
+                .count()
+                .get_result::<i64>(&mut conn)?;
+
+            Ok((template, debits, credits, created))
+        })
+        .await?
+        .map_err(map_db_error)?;
+
+    let format = AmountFormat::from(&ledger);
+    let sum_debits = sum_by_currency(&format, &debits, &ledger.base_currency);
+    let sum_credits = sum_by_currency(&format, &credits, &ledger.base_currency);
+
+    let input = |item| posting_input(&format, item);
+    let mut debits = debits.into_iter().map(input).collect::<Vec<_>>();
+    let mut credits = credits.into_iter().map(input).collect::<Vec<_>>();
+
+    let rows = std::cmp::max(std::cmp::max(debits.len(), credits.len()) + 3, 5);
+    let empty_row = (String::new(), String::new(), ledger.base_currency.clone());
+    debits.resize(rows, empty_row.clone());
+    credits.resize(rows, empty_row);
+
+    Ok(RecurringTemplate {
+        csrf_token,
+        ledger,
+        id: Some(id),
+        what: template.description,
+        schedule: template.schedule.to_string(),
+        start_date: template.start_date.to_string(),
+        end_date: template
+            .end_date
+            .map(|date| date.to_string())
+            .unwrap_or_default(),
+        debits,
+        credits,
+        sum_debits,
+        sum_credits,
+        created,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecurringForm {
+    csrf_token: String,
+    what: String,
+    schedule: String,
+    start_date: NaiveDate,
+    /// Empty for no end date
+    end_date: String,
+
+    #[serde(flatten, deserialize_with = "deserialize_debits")]
+    debits: Postings,
+
+    #[serde(flatten, deserialize_with = "deserialize_credits")]
+    credits: Postings,
+}
+
+/// A checked `RecurringForm`
+struct ValidRecurring {
+    schedule: Schedule,
+    end_date: Option<NaiveDate>,
+    debits: Postings,
+    credits: Postings,
+}
+
+impl RecurringForm {
+    fn validate(&mut self, base_currency: &str) -> Result<ValidRecurring, ValidationError> {
+        let schedule = self.schedule.parse::<Schedule>()?;
+        let end_date = match self.end_date.trim() {
+            "" => None,
+            end_date => Some(
+                end_date
+                    .parse::<NaiveDate>()
+                    .map_err(|_| ValidationError::InvalidEndDate)?,
+            ),
+        };
+        if end_date.is_some_and(|end_date| end_date < self.start_date) {
+            return Err(ValidationError::InvalidEndDate);
+        }
+
+        let debits = resolve_currencies(std::mem::take(&mut self.debits), base_currency)?;
+        let credits = resolve_currencies(std::mem::take(&mut self.credits), base_currency)?;
+        validate_postings(&self.what, &debits, &credits)?;
+
+        Ok(ValidRecurring {
+            schedule,
+            end_date,
+            debits,
+            credits,
+        })
+    }
+}
+
+fn insert_recurring_postings(
+    conn: &mut DbConnection,
+    recurring_tx_id: i32,
+    debits: &Postings,
+    credits: &Postings,
+) -> QueryResult<()> {
+    diesel::insert_into(recurring_credits::table)
+        .values(
+            credits
+                .iter()
+                .map(|((account, currency), value)| NewRecurringCredit {
+                    recurring_tx_id,
+                    account,
+                    currency,
+                    value: value.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)?;
+
+    diesel::insert_into(recurring_debits::table)
+        .values(
+            debits
+                .iter()
+                .map(|((account, currency), value)| NewRecurringDebit {
+                    recurring_tx_id,
+                    account,
+                    currency,
+                    value: value.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Creates the transactions that are already due, so they show up right away
+/// instead of at the next run of the scheduler
+fn materialize_now(conn: &mut DbConnection, id: i32, user_id: i32) -> Result<usize, LedgerError> {
+    let template = recurring_txs::table.find(id).first::<RecurringTx>(conn)?;
+    materialize(
+        conn,
+        &template,
+        chrono::Utc::now().date_naive(),
+        Actor::web(user_id),
+    )
+}
+
+/// A recurring transaction as it is saved, for the audit log
+fn recurring_json(id: i32, form: &RecurringForm, valid: &ValidRecurring) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "what": form.what,
+        "schedule": valid.schedule.to_string(),
+        "start_date": form.start_date,
+        "end_date": valid.end_date,
+        "debits": postings_json(&valid.debits),
+        "credits": postings_json(&valid.credits),
+    })
+}
+
+pub async fn create_recurring(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    slug: web::Path<String>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(mut form): web::Form<RecurringForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Member).await?;
+    let valid = form.validate(&ledger.base_currency)?;
+
+    let id = web::block(move || -> Result<i32, LedgerError> {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        let id = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let id = diesel::insert_into(recurring_txs::table)
+                .values(&NewRecurringTx {
+                    ledger_id: ledger.id,
+                    description: &form.what,
+                    schedule: valid.schedule,
+                    start_date: form.start_date,
+                    end_date: valid.end_date,
+                })
+                .returning(recurring_txs::id)
+                .get_result::<i32>(conn)?;
+
+            insert_recurring_postings(conn, id, &valid.debits, &valid.credits)?;
+
+            audit::record(
+                conn,
+                Actor::web(user.id),
+                Some(ledger.id),
+                Action::Create,
+                "recurring",
+                recurring_json(id, &form, &valid),
+            )?;
+
+            Ok(id)
+        })?;
+
+        materialize_now(&mut conn, id, user.id)?;
+        Ok(id)
+    })
+    .await?
+    .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to(format!("{id}")).see_other())
+}
+
+/// Changes apply to transactions that are created from now on. The ones that
+/// were already created are left as they are.
+pub async fn post_recurring(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(mut form): web::Form<RecurringForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+    let valid = form.validate(&ledger.base_currency)?;
+
+    web::block(move || -> Result<(), LedgerError> {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let updated = diesel::update(
+                recurring_txs::table
+                    .find(id)
+                    .filter(recurring_txs::ledger_id.eq(ledger.id)),
+            )
+            .set((
+                recurring_txs::description.eq(&form.what),
+                recurring_txs::schedule.eq(valid.schedule),
+                recurring_txs::start_date.eq(form.start_date),
+                recurring_txs::end_date.eq(valid.end_date),
+            ))
+            .execute(conn)?;
+            if updated == 0 {
+                return Err(diesel::result::Error::NotFound);
+            }
+
+            diesel::delete(
+                recurring_credits::table.filter(recurring_credits::recurring_tx_id.eq(id)),
+            )
+            .execute(conn)?;
+            diesel::delete(
+                recurring_debits::table.filter(recurring_debits::recurring_tx_id.eq(id)),
+            )
+            .execute(conn)?;
+            insert_recurring_postings(conn, id, &valid.debits, &valid.credits)?;
+
+            audit::record(
+                conn,
+                Actor::web(user.id),
+                Some(ledger.id),
+                Action::Edit,
+                "recurring",
+                recurring_json(id, &form, &valid),
+            )
+        })?;
+
+        materialize_now(&mut conn, id, user.id)?;
+        Ok(())
+    })
+    .await?
+    .map_err(map_ledger_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to(format!("{id}")).see_other())
+}
+
+/// Stops the recurring transaction after the last transaction that was
+/// created from it, by ending it there. It is kept, and so are the
+/// transactions created from it and their link back to it.
+pub async fn stop_recurring(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    path: web::Path<(String, i32)>,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    web::Form(form): web::Form<CsrfForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let (slug, id) = path.into_inner();
+    let ledger = load_ledger(&pool, slug, &user, Role::Member).await?;
+
+    web::block(move || {
+        let mut conn = pool.get().expect("couldn't get db connection from pool");
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let template = recurring_txs::table
+                .find(id)
+                .filter(recurring_txs::ledger_id.eq(ledger.id))
+                .first::<RecurringTx>(conn)?;
+
+            // Before the start, if nothing has been created from it yet
+            let end_date = template
+                .last_date
+                .or_else(|| template.start_date.pred_opt())
+                .unwrap_or(template.start_date);
+            diesel::update(recurring_txs::table.find(id))
+                .set(recurring_txs::end_date.eq(end_date))
+                .execute(conn)?;
+
+            audit::record(
+                conn,
+                Actor::web(user.id),
+                Some(ledger.id),
+                Action::Edit,
+                "recurring",
+                serde_json::json!({ "id": id, "end_date": end_date }),
+            )
+        })
+    })
+    .await?
+    .map_err(map_db_error)?;
+    broadcaster.changed(&ledger.slug);
+
+    Ok(Redirect::to("../../recurring").see_other())
+}
@@ -0,0 +1,98 @@
+//! Spending per tag and per account over a period
+
+use actix_web::{web, Responder};
+use askama::Template;
+use chrono::{Datelike, NaiveDate};
+use serde_derive::Deserialize;
+use sharebill::auth::Role;
+use sharebill::format::AmountFormat;
+use sharebill::models::{Ledger, User};
+use sharebill::tags::expense_report;
+
+use crate::auth::CurrentUser;
+use crate::csrf::CsrfToken;
+use crate::{load_ledger, DbPool, FormattedAmount};
+
+#[derive(Template)]
+#[template(path = "report.html")]
+struct ReportTemplate {
+    csrf_token: String,
+    user: User,
+    ledger: Ledger,
+    from: String,
+    to: String,
+    /// Spending by tag, with None for transactions without tags
+    by_tag: Vec<(Option<String>, FormattedAmount)>,
+    by_account: Vec<(String, FormattedAmount)>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportQuery {
+    /// Defaults to the start of the year
+    from: Option<NaiveDate>,
+    /// Defaults to today
+    to: Option<NaiveDate>,
+}
+
+/// Joins the amounts in different currencies of consecutive rows with the
+/// same key
+fn merge_rows<K: PartialEq>(rows: Vec<(K, FormattedAmount)>) -> Vec<(K, FormattedAmount)> {
+    let mut merged = Vec::<(K, FormattedAmount)>::new();
+    for (key, amount) in rows {
+        match merged.last_mut() {
+            Some((last, sum)) if *last == key => *sum = sum.clone().join(amount),
+            _ => merged.push((key, amount)),
+        }
+    }
+    merged
+}
+
+pub async fn get_report(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    slug: web::Path<String>,
+    query: web::Query<ReportQuery>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    let ledger = load_ledger(&pool, slug.into_inner(), &user, Role::Viewer).await?;
+    let today = chrono::Utc::now().date_naive();
+    let to = query.to.unwrap_or(today);
+    let from = query
+        .from
+        .unwrap_or_else(|| NaiveDate::from_ymd_opt(to.year(), 1, 1).unwrap());
+    let format = AmountFormat::from(&ledger);
+    let ledger_id = ledger.id;
+
+    let report = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+            Ok(expense_report(&mut conn, ledger_id, from, to)?)
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let amount = |value, currency: &str| {
+        FormattedAmount::new(&format, value, currency, &ledger.base_currency)
+    };
+    let by_tag = report
+        .by_tag
+        .iter()
+        .map(|(tag, currency, sum)| (tag.clone(), amount(sum, currency)))
+        .collect();
+    let by_account = report
+        .by_account
+        .iter()
+        .map(|(account, currency, sum)| (account.clone(), amount(sum, currency)))
+        .collect();
+
+    Ok(ReportTemplate {
+        csrf_token,
+        user,
+        from: from.to_string(),
+        to: to.to_string(),
+        by_tag: merge_rows(by_tag),
+        by_account: merge_rows(by_account),
+        ledger,
+    })
+}
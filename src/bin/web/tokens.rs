@@ -0,0 +1,263 @@
+//! Pages for managing the personal API tokens of the logged in user
+
+use actix_web::web::Redirect;
+use actix_web::{web, Responder};
+use askama::Template;
+use chrono::SecondsFormat;
+use diesel::prelude::*;
+use sharebill::audit::{self, Action, Actor};
+use sharebill::auth::{generate_token, hash_token, Access};
+use sharebill::models::{ApiToken, Ledger, NewApiToken, NewApiTokenLedger, User};
+use sharebill::schema::{api_token_ledgers, api_tokens, ledger_members, ledgers};
+
+use crate::auth::{AuthError, CurrentUser};
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::{DbPool, ValidationError};
+
+struct TokenEntry {
+    id: i32,
+    name: String,
+    created: String,
+    last_used: Option<String>,
+    ledgers: Vec<(String, Access)>,
+}
+
+#[derive(Template)]
+#[template(path = "tokens.html")]
+struct TokensTemplate {
+    csrf_token: String,
+    user: User,
+    tokens: Vec<TokenEntry>,
+    ledgers: Vec<Ledger>,
+    /// A newly created token, which can only be shown this once
+    new_token: Option<String>,
+}
+
+fn format_time(time: chrono::NaiveDateTime) -> String {
+    time.and_local_timezone(chrono::Utc)
+        .unwrap()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+async fn render_tokens(
+    pool: web::Data<DbPool>,
+    csrf_token: String,
+    user: User,
+    new_token: Option<String>,
+) -> actix_web::Result<TokensTemplate> {
+    let user_id = user.id;
+    let (tokens, ledgers) = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool.get().expect("couldn't get db connection from pool");
+
+            let tokens = api_tokens::table
+                .filter(api_tokens::user_id.eq(user_id))
+                .order(api_tokens::id)
+                .load::<ApiToken>(&mut conn)?
+                .into_iter()
+                .map(|token| {
+                    let ledgers = api_token_ledgers::table
+                        .inner_join(ledgers::table)
+                        .filter(api_token_ledgers::token_id.eq(token.id))
+                        .order(ledgers::slug)
+                        .select((ledgers::slug, api_token_ledgers::access))
+                        .load::<(String, Access)>(&mut conn)?;
+
+                    Ok(TokenEntry {
+                        id: token.id,
+                        name: token.name,
+                        created: format_time(token.created_time),
+                        last_used: token.last_used_time.map(format_time),
+                        ledgers,
+                    })
+                })
+                .collect::<QueryResult<Vec<_>>>()?;
+
+            let ledgers = ledgers::table
+                .inner_join(ledger_members::table)
+                .filter(ledger_members::user_id.eq(user_id))
+                .order(ledgers::name)
+                .select(ledgers::all_columns)
+                .load::<Ledger>(&mut conn)?;
+
+            Ok((tokens, ledgers))
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(TokensTemplate {
+        csrf_token,
+        user,
+        tokens,
+        ledgers,
+        new_token,
+    })
+}
+
+pub async fn get_tokens(
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    render_tokens(pool, csrf_token, user, None).await
+}
+
+/// The token form has one `ledger` field per ledger, each either empty or
+/// `slug:access`, so it is read as a list of pairs rather than a struct
+pub async fn create_token(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<Vec<(String, String)>>,
+) -> actix_web::Result<impl Responder> {
+    let field = |name: &str| {
+        form.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default()
+    };
+
+    csrf.verify(field("csrf_token"))?;
+
+    let name = field("name").to_owned();
+    if name.is_empty() {
+        return Err(ValidationError::MissingName.into());
+    }
+
+    let grants = form
+        .iter()
+        .filter(|(key, value)| key == "ledger" && !value.is_empty())
+        .map(|(_, value)| {
+            let (slug, access) = value
+                .split_once(':')
+                .ok_or_else(|| actix_web::error::ErrorBadRequest("invalid ledger access"))?;
+            let access = access
+                .parse::<Access>()
+                .map_err(actix_web::error::ErrorBadRequest)?;
+            Ok((slug.to_owned(), access))
+        })
+        .collect::<actix_web::Result<Vec<_>>>()?;
+
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+    let user_id = user.id;
+
+    let pool1 = pool.clone();
+    let created = web::block(
+        move || -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let mut conn = pool1.get().expect("couldn't get db connection from pool");
+
+            // Tokens can only be granted access to the user's own ledgers
+            let mut ledger_grants = vec![];
+            for (slug, access) in grants {
+                let ledger_id = ledgers::table
+                    .inner_join(ledger_members::table)
+                    .filter(ledgers::slug.eq(&slug))
+                    .filter(ledger_members::user_id.eq(user_id))
+                    .select(ledgers::id)
+                    .first::<i32>(&mut conn)
+                    .optional()?;
+                let Some(ledger_id) = ledger_id else {
+                    return Ok(false);
+                };
+                ledger_grants.push((ledger_id, access));
+            }
+
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let token_id = diesel::insert_into(api_tokens::table)
+                    .values(&NewApiToken {
+                        user_id,
+                        name: &name,
+                        token_hash,
+                        created_time: chrono::Utc::now().naive_utc(),
+                    })
+                    .returning(api_tokens::id)
+                    .get_result::<i32>(conn)?;
+
+                let ledgers = ledger_grants
+                    .iter()
+                    .map(|(ledger_id, access)| {
+                        serde_json::json!({ "ledger_id": ledger_id, "access": access.as_str() })
+                    })
+                    .collect::<Vec<_>>();
+                let payload =
+                    serde_json::json!({ "id": token_id, "name": name, "ledgers": ledgers });
+                audit::record(
+                    conn,
+                    Actor::web(user_id),
+                    None,
+                    Action::Create,
+                    "token",
+                    payload,
+                )?;
+
+                diesel::insert_into(api_token_ledgers::table)
+                    .values(
+                        ledger_grants
+                            .into_iter()
+                            .map(|(ledger_id, access)| NewApiTokenLedger {
+                                token_id,
+                                ledger_id,
+                                access,
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .execute(conn)?;
+
+                Ok(())
+            })?;
+
+            Ok(true)
+        },
+    )
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if !created {
+        return Err(AuthError::Forbidden.into());
+    }
+
+    render_tokens(pool, csrf.0, user, Some(token)).await
+}
+
+pub async fn revoke_token(
+    CurrentUser(user): CurrentUser,
+    csrf: CsrfToken,
+    id: web::Path<i32>,
+    pool: web::Data<DbPool>,
+    web::Form(form): web::Form<CsrfForm>,
+) -> actix_web::Result<impl Responder> {
+    csrf.verify(&form.csrf_token)?;
+
+    let id = id.into_inner();
+    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    let deleted = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let deleted = diesel::delete(
+                api_tokens::table
+                    .filter(api_tokens::id.eq(id))
+                    .filter(api_tokens::user_id.eq(user.id)),
+            )
+            .execute(conn)?;
+            if deleted > 0 {
+                let payload = serde_json::json!({ "id": id });
+                audit::record(
+                    conn,
+                    Actor::web(user.id),
+                    None,
+                    Action::Delete,
+                    "token",
+                    payload,
+                )?;
+            }
+            Ok(deleted)
+        })
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if deleted == 0 {
+        return Err(actix_web::error::ErrorNotFound("no such token"));
+    }
+
+    Ok(Redirect::to("/tokens").see_other())
+}
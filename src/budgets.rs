@@ -0,0 +1,242 @@
+//! Soft budgets, such as "house supplies: 2000 per month", and how much of
+//! them is spent. What is spent on an account or tag is the sum of its
+//! debits, like in the spending report.
+
+use chrono::{Datelike, Duration, NaiveDate};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel::{AsExpression, FromSqlRow};
+use num::{BigInt, BigRational, ToPrimitive, Zero};
+use thiserror::Error;
+
+use crate::models::Budget;
+use crate::rational::{sum_rat, Rational};
+use crate::schema::{budgets, debits, tx_tags, txs};
+use crate::DbConnection;
+
+/// What a budget limits the spending on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum BudgetTarget {
+    Account,
+    Tag,
+}
+
+/// How often a budget starts over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum Period {
+    Week,
+    Month,
+    Year,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid budget, it must be for an account or tag, per week, month or year")]
+pub struct InvalidBudget;
+
+text_enum!(BudgetTarget, InvalidBudget {
+    Account => "account",
+    Tag => "tag",
+});
+
+text_enum!(Period, InvalidBudget {
+    Week => "week",
+    Month => "month",
+    Year => "year",
+});
+
+impl Period {
+    /// The first and last day of the period that `date` is in. Weeks start on
+    /// Mondays.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chrono::NaiveDate;
+    /// # use sharebill::budgets::Period;
+    /// let date = |m, d| NaiveDate::from_ymd_opt(2026, m, d).unwrap();
+    /// assert_eq!(Period::Week.bounds(date(10, 18)), (date(10, 12), date(10, 18)));
+    /// assert_eq!(Period::Month.bounds(date(2, 10)), (date(2, 1), date(2, 28)));
+    /// assert_eq!(Period::Year.bounds(date(2, 10)), (date(1, 1), date(12, 31)));
+    /// ```
+    pub fn bounds(&self, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+        match self {
+            Period::Week => {
+                let first = date - Duration::days(date.weekday().num_days_from_monday().into());
+                (first, first + Duration::days(6))
+            }
+            Period::Month => {
+                let first = date.with_day(1).unwrap();
+                let next = match date.month() {
+                    12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+                    month => NaiveDate::from_ymd_opt(date.year(), month + 1, 1),
+                };
+                (first, next.unwrap().pred_opt().unwrap())
+            }
+            Period::Year => (
+                NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+                NaiveDate::from_ymd_opt(date.year(), 12, 31).unwrap(),
+            ),
+        }
+    }
+}
+
+/// A budget and how much of it is spent in the current period
+pub struct BudgetStatus {
+    pub budget: Budget,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub spent: BigRational,
+}
+
+impl BudgetStatus {
+    pub fn exceeded(&self) -> bool {
+        self.spent > self.budget.amount.to_big_rational()
+    }
+
+    /// How much of the budget is spent, in whole percent rounded down
+    pub fn percent(&self) -> u32 {
+        let amount = self.budget.amount.to_big_rational();
+        if amount.is_zero() {
+            return 0;
+        }
+        (&self.spent * BigInt::from(100) / amount)
+            .floor()
+            .to_integer()
+            .to_u32()
+            .unwrap_or(u32::MAX)
+    }
+}
+
+/// What is spent on the target of `budget` from `from` up to and including
+/// `to`
+pub fn spent(
+    conn: &mut DbConnection,
+    budget: &Budget,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> QueryResult<BigRational> {
+    let start = from.and_hms_opt(0, 0, 0).unwrap();
+    let end = to.succ_opt().unwrap_or(to).and_hms_opt(0, 0, 0).unwrap();
+
+    let spent = match budget.target {
+        BudgetTarget::Account => debits::table
+            .inner_join(txs::table)
+            .filter(txs::ledger_id.eq(budget.ledger_id))
+            .filter(txs::tx_time.ge(start))
+            .filter(txs::tx_time.lt(end))
+            .filter(txs::closing_id.is_null())
+            .filter(debits::account.eq(&budget.name))
+            .filter(debits::currency.eq(&budget.currency))
+            .select(sum_rat(debits::value))
+            .first::<Rational>(conn)?,
+        BudgetTarget::Tag => debits::table
+            .inner_join(txs::table.inner_join(tx_tags::table))
+            .filter(txs::ledger_id.eq(budget.ledger_id))
+            .filter(txs::tx_time.ge(start))
+            .filter(txs::tx_time.lt(end))
+            .filter(txs::closing_id.is_null())
+            .filter(tx_tags::tag.eq(&budget.name))
+            .filter(debits::currency.eq(&budget.currency))
+            .select(sum_rat(debits::value))
+            .first::<Rational>(conn)?,
+    };
+    Ok(spent.to_big_rational())
+}
+
+/// All budgets of a ledger with what is spent in the periods that `today` is
+/// in, sorted by target and name
+pub fn budget_statuses(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    today: NaiveDate,
+) -> QueryResult<Vec<BudgetStatus>> {
+    let budgets = budgets::table
+        .filter(budgets::ledger_id.eq(ledger_id))
+        .order((budgets::target, budgets::name, budgets::currency))
+        .load::<Budget>(conn)?;
+
+    budgets
+        .into_iter()
+        .map(|budget| {
+            let (from, to) = budget.period.bounds(today);
+            let spent = spent(conn, &budget, from, to)?;
+            Ok(BudgetStatus {
+                budget,
+                from,
+                to,
+                spent,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::Actor;
+    use crate::ledger::{Ledger, Postings, Transaction};
+    use crate::models::NewBudget;
+
+    #[test]
+    fn spending_in_the_current_period() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        let date = |m, d| NaiveDate::from_ymd_opt(2026, m, d).unwrap();
+
+        let mut add = |when: NaiveDate, account: &str, value: u32, tag: &str| {
+            let posting = |account: &str| {
+                Postings::from([((account.to_owned(), "NOK".to_owned()), value.into())])
+            };
+            let transaction = Transaction::new(
+                when.and_hms_opt(12, 0, 0).unwrap(),
+                "test",
+                tag,
+                posting(account),
+                posting("bank"),
+            )?;
+            Ledger::new(&mut conn, 1).insert_transaction(Actor::cli(), &transaction)
+        };
+        add(date(9, 30), "house", 1000, "supplies")?;
+        add(date(10, 1), "house", 1500, "supplies")?;
+        add(date(10, 18), "house", 700, "food")?;
+        add(date(10, 18), "alice", 300, "supplies")?;
+
+        for (target, name) in [
+            (BudgetTarget::Account, "house"),
+            (BudgetTarget::Tag, "supplies"),
+        ] {
+            diesel::insert_into(budgets::table)
+                .values(&NewBudget {
+                    ledger_id: 1,
+                    target,
+                    name,
+                    currency: "NOK",
+                    amount: 2000u32.into(),
+                    period: Period::Month,
+                })
+                .execute(&mut conn)?;
+        }
+
+        let statuses = budget_statuses(&mut conn, 1, date(10, 18))?;
+        let summary = statuses
+            .iter()
+            .map(|status| {
+                (
+                    status.budget.name.as_str(),
+                    status.spent.to_integer().to_u32().unwrap(),
+                    status.percent(),
+                    status.exceeded(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![("house", 2200, 110, true), ("supplies", 1800, 90, false)]
+        );
+        assert_eq!(statuses[0].from, date(10, 1));
+        assert_eq!(statuses[0].to, date(10, 31));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+//! Closing periods. Posts before the closing date are locked, and the balance
+//! of every account is carried over the closing by a closing post, which
+//! zeroes the balances, and an opening post, which brings them back. The
+//! balances can then be summed from the last closing alone.
+
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use num::{BigRational, Signed, Zero};
+use thiserror::Error;
+
+use crate::audit::{self, Action, Actor};
+use crate::models::{NewClosing, NewCredit, NewDebit, NewTx};
+use crate::rational::{sum_rat, Rational};
+use crate::schema::{closings, credits, debits, txs};
+use crate::DbConnection;
+
+#[derive(Error, Debug)]
+pub enum ClosingError {
+    #[error("posts before {0} are closed and cannot be added, changed or deleted")]
+    Closed(NaiveDate),
+    #[error("the ledger is already closed before {0}, close a later date")]
+    AlreadyClosed(NaiveDate),
+    #[error("periods cannot be closed in the future")]
+    Future,
+    #[error(transparent)]
+    Db(#[from] diesel::result::Error),
+}
+
+fn midnight(date: NaiveDate) -> NaiveDateTime {
+    date.and_hms_opt(0, 0, 0).unwrap()
+}
+
+/// The date before which posts in the ledger are closed, if any are
+pub fn closed_before(conn: &mut DbConnection, ledger_id: i32) -> QueryResult<Option<NaiveDate>> {
+    closings::table
+        .filter(closings::ledger_id.eq(ledger_id))
+        .select(diesel::dsl::max(closings::closed_before))
+        .first(conn)
+}
+
+/// Checks that a post can be added at, or moved to, `tx_time`
+pub fn check_open(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    tx_time: NaiveDateTime,
+) -> Result<(), ClosingError> {
+    match closed_before(conn, ledger_id)? {
+        Some(date) if tx_time < midnight(date) => Err(ClosingError::Closed(date)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that an existing post can be changed or deleted. The posts of a
+/// closing never can, not even the opening post after the closing date.
+pub fn check_tx_open(conn: &mut DbConnection, tx_id: i32) -> Result<(), ClosingError> {
+    let (ledger_id, tx_time, closing_id) = txs::table
+        .find(tx_id)
+        .select((txs::ledger_id, txs::tx_time, txs::closing_id))
+        .first::<(i32, NaiveDateTime, Option<i32>)>(conn)?;
+    if let Some(closing_id) = closing_id {
+        let date = closings::table
+            .find(closing_id)
+            .select(closings::closed_before)
+            .first(conn)?;
+        return Err(ClosingError::Closed(date));
+    }
+    check_open(conn, ledger_id, tx_time)
+}
+
+/// Balances of all accounts in the ledger before `before`, or of all posts
+/// if it is `None`, per account and currency. Positive balances are credit
+/// balances. Only the posts since the last closing are summed.
+pub fn balances(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    before: Option<NaiveDate>,
+) -> QueryResult<BTreeMap<(String, String), BigRational>> {
+    let mut last_closing = closings::table
+        .filter(closings::ledger_id.eq(ledger_id))
+        .select(diesel::dsl::max(closings::closed_before))
+        .into_boxed();
+    if let Some(before) = before {
+        last_closing = last_closing.filter(closings::closed_before.lt(before));
+    }
+    let last_closing = last_closing.first::<Option<NaiveDate>>(conn)?;
+
+    let mut cre = credits::table
+        .inner_join(txs::table)
+        .filter(txs::ledger_id.eq(ledger_id))
+        .group_by((credits::account, credits::currency))
+        .select((credits::account, credits::currency, sum_rat(credits::value)))
+        .into_boxed();
+    let mut deb = debits::table
+        .inner_join(txs::table)
+        .filter(txs::ledger_id.eq(ledger_id))
+        .group_by((debits::account, debits::currency))
+        .select((debits::account, debits::currency, sum_rat(debits::value)))
+        .into_boxed();
+    // Summing starts at the opening post of the last closing, and the
+    // closing posts, which only zero the balances, are left out
+    match last_closing.map(midnight) {
+        Some(from) => {
+            let carried = txs::tx_time
+                .ge(from)
+                .and(txs::closing_id.is_null().or(txs::tx_time.eq(from)));
+            cre = cre.filter(carried);
+            deb = deb.filter(carried);
+        }
+        None => {
+            cre = cre.filter(txs::closing_id.is_null());
+            deb = deb.filter(txs::closing_id.is_null());
+        }
+    }
+    if let Some(until) = before.map(midnight) {
+        cre = cre.filter(txs::tx_time.lt(until));
+        deb = deb.filter(txs::tx_time.lt(until));
+    }
+    let cre = cre.load::<(String, String, Rational)>(conn)?;
+    let deb = deb.load::<(String, String, Rational)>(conn)?;
+
+    let mut balances = cre
+        .into_iter()
+        .map(|(account, currency, value)| ((account, currency), value.to_big_rational()))
+        .collect::<BTreeMap<_, _>>();
+
+    for (account, currency, value) in deb {
+        *balances.entry((account, currency)).or_default() -= value.to_big_rational();
+    }
+
+    Ok(balances)
+}
+
+/// The absolute value of `value`
+fn magnitude(value: &BigRational) -> Rational {
+    let value = value.abs();
+    Rational::new(
+        value.numer().magnitude().clone(),
+        value.denom().magnitude().clone(),
+    )
+}
+
+/// Closes the ledger before `date`: locks the posts before it, and carries
+/// the balances over with a closing post just before midnight and an opening
+/// post at midnight. Returns the id of the closing.
+pub fn close_period(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    date: NaiveDate,
+    today: NaiveDate,
+    actor: Actor,
+) -> Result<i32, ClosingError> {
+    if date > today {
+        return Err(ClosingError::Future);
+    }
+
+    crate::transaction(conn, |conn| {
+        if let Some(closed) = closed_before(conn, ledger_id)? {
+            if date <= closed {
+                return Err(ClosingError::AlreadyClosed(closed));
+            }
+        }
+
+        let balances = balances(conn, ledger_id, Some(date))?
+            .into_iter()
+            .filter(|(_, balance)| !balance.is_zero())
+            .collect::<Vec<_>>();
+
+        let now = chrono::Utc::now().naive_utc();
+        let closing_id = diesel::insert_into(closings::table)
+            .values(&NewClosing {
+                ledger_id,
+                closed_before: date,
+                created_time: now,
+                user_id: actor.user_id,
+            })
+            .returning(closings::id)
+            .get_result::<i32>(conn)?;
+        audit::record(
+            conn,
+            actor,
+            Some(ledger_id),
+            Action::Close,
+            "period",
+            serde_json::json!({ "id": closing_id, "closed_before": date }),
+        )?;
+
+        if balances.is_empty() {
+            return Ok(closing_id);
+        }
+
+        let posts = [
+            (
+                midnight(date) - Duration::seconds(1),
+                format!("Closing balances before {date}"),
+                true,
+            ),
+            (
+                midnight(date),
+                format!("Opening balances from {date}"),
+                false,
+            ),
+        ];
+        for (tx_time, description, closing) in posts {
+            let tx_id = diesel::insert_into(txs::table)
+                .values(&NewTx {
+                    ledger_id,
+                    tx_time,
+                    rev_time: now,
+                    description: &description,
+                    rev_user_id: actor.user_id,
+                    recurring_tx_id: None,
+                    closing_id: Some(closing_id),
+                })
+                .returning(txs::id)
+                .get_result::<i32>(conn)?;
+
+            // The closing post debits credit balances and credits debit
+            // balances, and the opening post does the opposite
+            let (debit_balances, credit_balances): (Vec<_>, Vec<_>) = balances
+                .iter()
+                .partition(|(_, balance)| balance.is_positive() == closing);
+            diesel::insert_into(debits::table)
+                .values(
+                    debit_balances
+                        .iter()
+                        .map(|((account, currency), balance)| NewDebit {
+                            tx_id,
+                            account,
+                            currency,
+                            value: magnitude(balance),
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .execute(conn)?;
+            diesel::insert_into(credits::table)
+                .values(
+                    credit_balances
+                        .iter()
+                        .map(|((account, currency), balance)| NewCredit {
+                            tx_id,
+                            account,
+                            currency,
+                            value: magnitude(balance),
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .execute(conn)?;
+        }
+
+        Ok(closing_id)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Ledger, Postings, Transaction};
+
+    #[test]
+    fn closing_carries_balances_over() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+        let mut add = |when: NaiveDate, debit: &str, credit: &str, value: u32| {
+            let posting = |account: &str| {
+                Postings::from([((account.to_owned(), "NOK".to_owned()), value.into())])
+            };
+            let transaction = Transaction::new(
+                when.and_hms_opt(12, 0, 0).unwrap(),
+                "test",
+                "",
+                posting(debit),
+                posting(credit),
+            )?;
+            Ledger::new(&mut conn, 1).insert_transaction(Actor::cli(), &transaction)
+        };
+        let old = add(date(2025, 6, 1), "bob", "alice", 100)?;
+        add(date(2025, 12, 31), "alice", "bob", 30)?;
+        let new = add(date(2026, 2, 1), "bob", "alice", 5)?;
+
+        let before = balances(&mut conn, 1, None)?;
+        assert!(matches!(
+            close_period(
+                &mut conn,
+                1,
+                date(2026, 1, 1),
+                date(2025, 12, 31),
+                Actor::cli()
+            ),
+            Err(ClosingError::Future)
+        ));
+        close_period(
+            &mut conn,
+            1,
+            date(2026, 1, 1),
+            date(2026, 10, 18),
+            Actor::cli(),
+        )?;
+        assert!(matches!(
+            close_period(
+                &mut conn,
+                1,
+                date(2025, 7, 1),
+                date(2026, 10, 18),
+                Actor::cli()
+            ),
+            Err(ClosingError::AlreadyClosed(_))
+        ));
+
+        // Summing from the closing gives the same balances as summing it all
+        assert_eq!(balances(&mut conn, 1, None)?, before);
+        assert_eq!(
+            before[&("alice".to_owned(), "NOK".to_owned())],
+            BigRational::from_integer(75.into())
+        );
+        let carried = balances(&mut conn, 1, Some(date(2026, 1, 1)))?;
+        assert_eq!(
+            carried[&("alice".to_owned(), "NOK".to_owned())],
+            BigRational::from_integer(70.into())
+        );
+        assert_eq!(
+            carried[&("bob".to_owned(), "NOK".to_owned())],
+            BigRational::from_integer((-70).into())
+        );
+
+        assert!(matches!(
+            check_tx_open(&mut conn, old),
+            Err(ClosingError::Closed(_))
+        ));
+        assert!(check_tx_open(&mut conn, new).is_ok());
+        let opening = txs::table
+            .filter(txs::tx_time.eq(midnight(date(2026, 1, 1))))
+            .select(txs::id)
+            .first::<i32>(&mut conn)?;
+        assert!(matches!(
+            check_tx_open(&mut conn, opening),
+            Err(ClosingError::Closed(_))
+        ));
+        assert!(matches!(
+            check_open(
+                &mut conn,
+                1,
+                date(2025, 12, 31).and_hms_opt(23, 0, 0).unwrap()
+            ),
+            Err(ClosingError::Closed(_))
+        ));
+
+        Ok(())
+    }
+}
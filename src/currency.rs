@@ -0,0 +1,181 @@
+//! Currencies of postings and conversion to the base currency of a ledger
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use num::BigRational;
+use thiserror::Error;
+
+use crate::amount::parse_amount;
+use crate::models::Ledger;
+use crate::rational::Rational;
+use crate::schema::exchange_rates;
+use crate::DbConnection;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid currency, use a three letter code such as EUR")]
+pub struct InvalidCurrency;
+
+/// Checks and normalizes a currency code, so "eur" becomes "EUR"
+pub fn parse_currency(code: &str) -> Result<String, InvalidCurrency> {
+    let code = code.trim().to_ascii_uppercase();
+    if code.len() == 3 && code.chars().all(|c| c.is_ascii_uppercase()) {
+        Ok(code)
+    } else {
+        Err(InvalidCurrency)
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid exchange rate, use a positive amount such as 11.25")]
+pub struct InvalidRate;
+
+/// Parses an exchange rate, which like any amount may be written as a
+/// fraction or a sum, but must not be zero
+pub fn parse_rate(input: &str) -> Result<Rational, InvalidRate> {
+    match parse_amount(input) {
+        Ok(rate) if !rate.is_zero() => Ok(rate),
+        _ => Err(InvalidRate),
+    }
+}
+
+/// The latest rate for `currency` on or before `date`, as units of the
+/// ledger's base currency per unit of `currency`
+pub fn find_rate(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    currency: &str,
+    date: NaiveDate,
+) -> QueryResult<Option<Rational>> {
+    exchange_rates::table
+        .filter(exchange_rates::ledger_id.eq(ledger_id))
+        .filter(exchange_rates::currency.eq(currency))
+        .filter(exchange_rates::date.le(date))
+        .order(exchange_rates::date.desc())
+        .select(exchange_rates::rate)
+        .first::<Rational>(conn)
+        .optional()
+}
+
+/// Balances converted to the base currency of the ledger
+pub struct ConvertedBalances {
+    /// Total balance of each account
+    pub balances: Vec<(String, BigRational)>,
+    /// Currencies without a rate on the date. Amounts in these are left out.
+    pub missing_rates: Vec<String>,
+}
+
+/// Converts balances given per account and currency, as returned by the
+/// balance queries, using the rates as of `date`
+pub fn convert_balances(
+    conn: &mut DbConnection,
+    ledger: &Ledger,
+    balances: Vec<(String, String, BigRational)>,
+    date: NaiveDate,
+) -> QueryResult<ConvertedBalances> {
+    let mut rates = BTreeMap::<String, Option<BigRational>>::new();
+    let mut totals = BTreeMap::<String, BigRational>::new();
+    let mut missing_rates = BTreeSet::new();
+
+    for (account, currency, balance) in balances {
+        let rate = if currency == ledger.base_currency {
+            Some(BigRational::from_integer(1.into()))
+        } else {
+            match rates.get(&currency) {
+                Some(rate) => rate.clone(),
+                None => {
+                    let rate = find_rate(conn, ledger.id, &currency, date)?
+                        .map(|rate| rate.to_big_rational());
+                    rates.insert(currency.clone(), rate.clone());
+                    rate
+                }
+            }
+        };
+
+        match rate {
+            Some(rate) => *totals.entry(account).or_default() += balance * rate,
+            None => {
+                missing_rates.insert(currency);
+            }
+        }
+    }
+
+    Ok(ConvertedBalances {
+        balances: totals.into_iter().collect(),
+        missing_rates: missing_rates.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::NewExchangeRate;
+    use crate::schema::ledgers;
+
+    #[test]
+    fn currency_codes() {
+        assert_eq!(parse_currency("eur"), Ok("EUR".to_owned()));
+        assert_eq!(parse_currency(" NOK "), Ok("NOK".to_owned()));
+        assert_eq!(parse_currency("kr"), Err(InvalidCurrency));
+        assert_eq!(parse_currency("EU1"), Err(InvalidCurrency));
+        assert_eq!(parse_currency("ÆØÅ"), Err(InvalidCurrency));
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(parse_rate("11,25"), Ok(Rational::new(45u32, 4u32)));
+        assert_eq!(parse_rate("1/8"), Ok(Rational::new(1u32, 8u32)));
+        assert_eq!(parse_rate("0"), Err(InvalidRate));
+        assert_eq!(parse_rate("0.00"), Err(InvalidRate));
+        assert_eq!(parse_rate("-1"), Err(InvalidRate));
+        assert_eq!(parse_rate(""), Err(InvalidRate));
+    }
+
+    #[test]
+    fn converts_with_latest_rate() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        let ledger = ledgers::table.find(1).first::<Ledger>(&mut conn)?;
+
+        let date = |d| NaiveDate::from_ymd_opt(2026, 10, d).unwrap();
+        diesel::insert_into(exchange_rates::table)
+            .values(&[
+                NewExchangeRate {
+                    ledger_id: ledger.id,
+                    currency: "EUR",
+                    date: date(1),
+                    rate: Rational::new(11u32, 1u32),
+                },
+                NewExchangeRate {
+                    ledger_id: ledger.id,
+                    currency: "EUR",
+                    date: date(10),
+                    rate: Rational::new(23u32, 2u32),
+                },
+            ])
+            .execute(&mut conn)?;
+
+        let r = |n: i64| BigRational::from_integer(n.into());
+        let balances = vec![
+            ("a".to_owned(), ledger.base_currency.clone(), r(5)),
+            ("a".to_owned(), "EUR".to_owned(), r(2)),
+            ("b".to_owned(), "EUR".to_owned(), r(-2)),
+            ("b".to_owned(), "SEK".to_owned(), r(100)),
+        ];
+
+        let converted = convert_balances(&mut conn, &ledger, balances.clone(), date(5))?;
+        assert_eq!(
+            converted.balances,
+            vec![("a".to_owned(), r(27)), ("b".to_owned(), r(-22))]
+        );
+        assert_eq!(converted.missing_rates, vec!["SEK".to_owned()]);
+
+        let converted = convert_balances(&mut conn, &ledger, balances, date(10))?;
+        assert_eq!(
+            converted.balances,
+            vec![("a".to_owned(), r(28)), ("b".to_owned(), r(-23))]
+        );
+
+        Ok(())
+    }
+}
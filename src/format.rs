@@ -0,0 +1,245 @@
+//! Human readable formatting of amounts
+
+use num::{BigInt, BigRational, Integer, One, Signed, Zero};
+
+use crate::models::Ledger;
+
+/// How amounts are displayed in a ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmountFormat {
+    pub decimals: u32,
+    pub decimal_separator: String,
+    /// Inserted between groups of three digits, may be empty
+    pub thousands_separator: String,
+    /// May be empty
+    pub currency_symbol: String,
+    /// Whether the currency symbol goes before, as in "$5", or after, as in
+    /// "5 kr", the number
+    pub currency_before: bool,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        Self {
+            decimals: 2,
+            decimal_separator: ".".to_owned(),
+            thousands_separator: String::new(),
+            currency_symbol: String::new(),
+            currency_before: false,
+        }
+    }
+}
+
+impl From<&Ledger> for AmountFormat {
+    fn from(ledger: &Ledger) -> Self {
+        Self {
+            decimals: ledger.decimals.try_into().unwrap_or_default(),
+            decimal_separator: ledger.decimal_separator.clone(),
+            thousands_separator: ledger.thousands_separator.clone(),
+            currency_symbol: ledger.currency_symbol.clone(),
+            currency_before: ledger.currency_before,
+        }
+    }
+}
+
+/// Marks amounts that were rounded to fit the number of decimals
+pub const APPROXIMATE: &str = "≈";
+
+fn pow10(exp: u32) -> BigInt {
+    num::pow(BigInt::from(10), exp as usize)
+}
+
+/// Number of decimals needed to write a fraction with the given denominator
+/// exactly, or `None` if its decimal expansion never ends
+fn exact_decimals(denom: &BigInt) -> Option<u32> {
+    let mut denom = denom.clone();
+    let (mut twos, mut fives) = (0, 0);
+    while !denom.is_zero() && denom.is_even() {
+        denom /= 2;
+        twos += 1;
+    }
+    while !denom.is_zero() && (&denom % 5u32).is_zero() {
+        denom /= 5;
+        fives += 1;
+    }
+    denom.is_one().then_some(std::cmp::max(twos, fives))
+}
+
+impl AmountFormat {
+    /// Splits the integer `scaled`, the amount times 10^`decimals`, into its
+    /// sign and the digits with separators
+    fn digits(&self, scaled: &BigInt, decimals: u32, group: bool) -> (bool, String) {
+        let digits = format!(
+            "{:0>width$}",
+            scaled.abs().to_string(),
+            width = decimals as usize + 1
+        );
+        let (int, frac) = digits.split_at(digits.len() - decimals as usize);
+
+        let mut out = String::new();
+        for (i, c) in int.chars().enumerate() {
+            if group && i > 0 && (int.len() - i) % 3 == 0 {
+                out.push_str(&self.thousands_separator);
+            }
+            out.push(c);
+        }
+        if decimals > 0 {
+            out.push_str(&self.decimal_separator);
+            out.push_str(frac);
+        }
+
+        (scaled.is_negative(), out)
+    }
+
+    fn rounded(&self, value: &BigRational) -> (bool, bool, String) {
+        let scaled = value * BigRational::from_integer(pow10(self.decimals));
+        let rounded = scaled.round();
+        let approximate = rounded != scaled;
+        let (negative, digits) = self.digits(&rounded.to_integer(), self.decimals, true);
+        (approximate, negative, digits)
+    }
+
+    /// The amount rounded to the configured number of decimals, with
+    /// separators but no currency symbol, such as "1 234,50". Rounded values
+    /// are marked with `APPROXIMATE`.
+    pub fn number(&self, value: &BigRational) -> String {
+        let (approximate, negative, digits) = self.rounded(value);
+        format!(
+            "{}{}{}",
+            if approximate { APPROXIMATE } else { "" },
+            if negative { "-" } else { "" },
+            digits
+        )
+    }
+
+    /// Like `number`, but with the currency symbol
+    pub fn amount(&self, value: &BigRational) -> String {
+        if self.currency_symbol.is_empty() {
+            return self.number(value);
+        }
+
+        let (approximate, negative, digits) = self.rounded(value);
+        let approximate = if approximate { APPROXIMATE } else { "" };
+        let sign = if negative { "-" } else { "" };
+        if self.currency_before {
+            format!("{approximate}{sign}{}{digits}", self.currency_symbol)
+        } else {
+            format!("{approximate}{sign}{digits} {}", self.currency_symbol)
+        }
+    }
+
+    /// The exact amount without thousands separators or currency symbol. This
+    /// is a decimal number, with at least the configured number of decimals,
+    /// if one can represent the amount, otherwise a mixed number.
+    pub fn exact(&self, value: &BigRational) -> String {
+        let Some(decimals) = exact_decimals(value.denom()) else {
+            return mixed_number(value);
+        };
+
+        let decimals = if decimals == 0 {
+            0
+        } else {
+            std::cmp::max(decimals, self.decimals)
+        };
+        let scaled = (value * BigRational::from_integer(pow10(decimals))).to_integer();
+        let (negative, digits) = self.digits(&scaled, decimals, false);
+        format!("{}{}", if negative { "-" } else { "" }, digits)
+    }
+}
+
+/// Formats a fraction as a whole number and a proper fraction, such as
+/// "3 1/2", omitting parts that are zero.
+///
+/// # Examples
+///
+/// ```
+/// # use num::BigRational;
+/// # use sharebill::format::mixed_number;
+/// let r = |n: i32, d: i32| BigRational::new(n.into(), d.into());
+/// assert_eq!(mixed_number(&r(7, 2)), "3 1/2");
+/// assert_eq!(mixed_number(&r(-7, 2)), "-3 1/2");
+/// assert_eq!(mixed_number(&r(-1, 3)), "-1/3");
+/// assert_eq!(mixed_number(&r(6, 3)), "2");
+/// ```
+pub fn mixed_number(value: &BigRational) -> String {
+    let whole = value.trunc();
+    let fraction = (value - &whole).abs();
+
+    if fraction.is_zero() {
+        whole.to_integer().to_string()
+    } else if whole.is_zero() {
+        format!(
+            "{}{}/{}",
+            if value.is_negative() { "-" } else { "" },
+            fraction.numer(),
+            fraction.denom()
+        )
+    } else {
+        format!(
+            "{} {}/{}",
+            whole.to_integer(),
+            fraction.numer(),
+            fraction.denom()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn r(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    #[test]
+    fn rounds_to_decimals() {
+        let format = AmountFormat::default();
+        assert_eq!(format.number(&r(25, 2)), "12.50");
+        assert_eq!(format.number(&r(-25, 2)), "-12.50");
+        assert_eq!(format.number(&r(1, 3)), "≈0.33");
+        assert_eq!(format.number(&r(2, 3)), "≈0.67");
+        assert_eq!(format.number(&r(-1, 1000)), "≈0.00");
+        assert_eq!(format.number(&r(0, 1)), "0.00");
+
+        let format = AmountFormat {
+            decimals: 0,
+            ..Default::default()
+        };
+        assert_eq!(format.number(&r(25, 2)), "≈13");
+        assert_eq!(format.number(&r(12, 1)), "12");
+    }
+
+    #[test]
+    fn uses_separators_and_currency() {
+        let format = AmountFormat {
+            decimal_separator: ",".to_owned(),
+            thousands_separator: " ".to_owned(),
+            currency_symbol: "kr".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(format.number(&r(123456789, 100)), "1 234 567,89");
+        assert_eq!(format.amount(&r(-1000, 1)), "-1 000,00 kr");
+        assert_eq!(format.amount(&r(100, 1)), "100,00 kr");
+
+        let format = AmountFormat {
+            thousands_separator: ",".to_owned(),
+            currency_symbol: "$".to_owned(),
+            currency_before: true,
+            ..Default::default()
+        };
+        assert_eq!(format.amount(&r(-123456, 1)), "-$123,456.00");
+        assert_eq!(format.amount(&r(1, 3)), "≈$0.33");
+    }
+
+    #[test]
+    fn exact_values() {
+        let format = AmountFormat::default();
+        assert_eq!(format.exact(&r(12, 1)), "12");
+        assert_eq!(format.exact(&r(25, 2)), "12.50");
+        assert_eq!(format.exact(&r(1, 8)), "0.125");
+        assert_eq!(format.exact(&r(-1, 8)), "-0.125");
+        assert_eq!(format.exact(&r(37, 3)), "12 1/3");
+        assert_eq!(format.exact(&r(123456, 1)), "123456");
+    }
+}
@@ -0,0 +1,460 @@
+//! Integrity checks of a whole database. The schema and its triggers keep
+//! new changes consistent, but databases from before them, and changes made
+//! behind the application's back, may still hold inconsistent data.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Binary};
+use num::{BigRational, Zero};
+
+use crate::audit::{self, Action, Actor};
+use crate::rational::Rational;
+use crate::schema::{credits, debits, txs};
+use crate::DbConnection;
+
+/// Every column that holds a [`Rational`], by table
+const VALUE_COLUMNS: &[(&str, &str)] = &[
+    ("budgets", "amount"),
+    ("credits", "value"),
+    ("debits", "value"),
+    ("exchange_rates", "rate"),
+    ("post_template_credits", "share"),
+    ("post_template_debits", "share"),
+    ("receipt_items", "value"),
+    ("recurring_credits", "value"),
+    ("recurring_debits", "value"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Side {
+    Credit,
+    Debit,
+}
+
+/// A row in `credits` or `debits`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Posting {
+    pub side: Side,
+    pub tx_id: i32,
+    pub account: String,
+    pub currency: String,
+}
+
+/// An expression for a number that identifies a row in any table, at least
+/// until the row is changed. PostgreSQL has no rowid, so the location of the
+/// row in the table is used instead.
+#[cfg(not(feature = "postgres"))]
+const ROW_ID: &str = "rowid";
+#[cfg(feature = "postgres")]
+const ROW_ID: &str = "(((ctid::text::point)[0]::bigint << 16) | (ctid::text::point)[1]::bigint)";
+
+/// A stored [`Rational`], by table, column and rowid
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoredValue {
+    pub table: &'static str,
+    pub column: &'static str,
+    pub rowid: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Problem {
+    /// A transaction whose credits and debits differ in a currency
+    Unbalanced { tx_id: i32, currency: String },
+    /// A transaction without any credits or debits
+    Empty { tx_id: i32 },
+    /// A transaction that was last changed before it happened
+    RevisedBeforeTime { tx_id: i32 },
+    /// A credit or debit of a transaction that does not exist
+    OrphanPosting(Posting),
+    /// A credit or debit of zero
+    ZeroValue(Posting),
+    /// A credit or debit to an account without a name
+    EmptyAccountName(Posting),
+    /// A value that cannot be read
+    InvalidValue(StoredValue),
+    /// A value that reads fine, but not as it would be written, such as one
+    /// in the legacy form
+    NonCanonicalValue(StoredValue),
+}
+
+impl Problem {
+    /// Whether [`repair`] can fix the problem without losing anything
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            Problem::OrphanPosting(_) | Problem::ZeroValue(_) | Problem::NonCanonicalValue(_)
+        )
+    }
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Side::Credit => "credit",
+            Side::Debit => "debit",
+        })
+    }
+}
+
+impl std::fmt::Display for Posting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of post {} to {:?} in {}",
+            self.side, self.tx_id, self.account, self.currency
+        )
+    }
+}
+
+impl std::fmt::Display for StoredValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{} of row {}", self.table, self.column, self.rowid)
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Unbalanced { tx_id, currency } => {
+                write!(f, "post {tx_id}: credits and debits differ in {currency}")
+            }
+            Problem::Empty { tx_id } => write!(f, "post {tx_id}: no credits or debits"),
+            Problem::RevisedBeforeTime { tx_id } => {
+                write!(f, "post {tx_id}: revised before the time of the post")
+            }
+            Problem::OrphanPosting(posting) => write!(f, "{posting}: no such post"),
+            Problem::ZeroValue(posting) => write!(f, "{posting}: zero value"),
+            Problem::EmptyAccountName(posting) => write!(f, "{posting}: empty account name"),
+            Problem::InvalidValue(value) => write!(f, "{value}: invalid rational number"),
+            Problem::NonCanonicalValue(value) => write!(f, "{value}: not in canonical form"),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct RawValue {
+    #[diesel(sql_type = BigInt)]
+    rowid: i64,
+    #[diesel(sql_type = Binary)]
+    value: Vec<u8>,
+}
+
+fn raw_values(conn: &mut DbConnection, table: &str, column: &str) -> QueryResult<Vec<RawValue>> {
+    sql_query(format!(
+        "SELECT {ROW_ID} AS rowid, {column} AS value FROM {table} ORDER BY rowid"
+    ))
+    .load(conn)
+}
+
+/// Scans the whole database for problems, ordered by kind and then by post
+pub fn check(conn: &mut DbConnection) -> QueryResult<Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    for &(table, column) in VALUE_COLUMNS {
+        for raw in raw_values(conn, table, column)? {
+            let stored = StoredValue {
+                table,
+                column,
+                rowid: raw.rowid,
+            };
+            match Rational::from_bytes(&raw.value) {
+                None => problems.push(Problem::InvalidValue(stored)),
+                Some(value) if value.to_bytes() != raw.value => {
+                    problems.push(Problem::NonCanonicalValue(stored))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    let tx_times = txs::table
+        .select((txs::id, txs::tx_time, txs::rev_time))
+        .load::<(i32, chrono::NaiveDateTime, chrono::NaiveDateTime)>(conn)?;
+    problems.extend(
+        tx_times
+            .iter()
+            .filter(|(_, tx_time, rev_time)| rev_time < tx_time)
+            .map(|&(tx_id, _, _)| Problem::RevisedBeforeTime { tx_id }),
+    );
+
+    let credits = credits::table
+        .select((
+            credits::tx_id,
+            credits::account,
+            credits::currency,
+            credits::value,
+        ))
+        .load::<(i32, String, String, Vec<u8>)>(conn)?;
+    let debits = debits::table
+        .select((
+            debits::tx_id,
+            debits::account,
+            debits::currency,
+            debits::value,
+        ))
+        .load::<(i32, String, String, Vec<u8>)>(conn)?;
+    let postings = credits
+        .into_iter()
+        .map(|posting| (Side::Credit, posting))
+        .chain(debits.into_iter().map(|posting| (Side::Debit, posting)));
+
+    // Credits minus debits, by transaction and currency. Transactions with
+    // invalid values are left out, as their balance is unknown.
+    let mut balances = BTreeMap::<i32, BTreeMap<String, BigRational>>::new();
+    let mut unreadable = BTreeSet::new();
+    let tx_ids = tx_times
+        .iter()
+        .map(|&(id, _, _)| id)
+        .collect::<BTreeSet<_>>();
+    for (side, (tx_id, account, currency, value)) in postings {
+        let posting = Posting {
+            side,
+            tx_id,
+            account,
+            currency,
+        };
+        if !tx_ids.contains(&tx_id) {
+            problems.push(Problem::OrphanPosting(posting));
+            continue;
+        }
+        let Some(value) = Rational::from_bytes(&value) else {
+            unreadable.insert(tx_id);
+            continue;
+        };
+
+        if posting.account.is_empty() {
+            problems.push(Problem::EmptyAccountName(posting.clone()));
+        }
+        if value.is_zero() {
+            problems.push(Problem::ZeroValue(posting.clone()));
+        }
+
+        let balance = balances
+            .entry(tx_id)
+            .or_default()
+            .entry(posting.currency)
+            .or_default();
+        match side {
+            Side::Credit => *balance += value.to_big_rational(),
+            Side::Debit => *balance -= value.to_big_rational(),
+        }
+    }
+
+    for tx_id in tx_ids.difference(&unreadable) {
+        match balances.get(tx_id) {
+            None => problems.push(Problem::Empty { tx_id: *tx_id }),
+            Some(by_currency) => problems.extend(
+                by_currency
+                    .iter()
+                    .filter(|(_, balance)| !balance.is_zero())
+                    .map(|(currency, _)| Problem::Unbalanced {
+                        tx_id: *tx_id,
+                        currency: currency.clone(),
+                    }),
+            ),
+        }
+    }
+
+    problems.sort();
+    Ok(problems)
+}
+
+fn delete_posting(conn: &mut DbConnection, posting: &Posting) -> QueryResult<usize> {
+    match posting.side {
+        Side::Credit => diesel::delete(
+            credits::table
+                .filter(credits::tx_id.eq(posting.tx_id))
+                .filter(credits::account.eq(&posting.account))
+                .filter(credits::currency.eq(&posting.currency)),
+        )
+        .execute(conn),
+        Side::Debit => diesel::delete(
+            debits::table
+                .filter(debits::tx_id.eq(posting.tx_id))
+                .filter(debits::account.eq(&posting.account))
+                .filter(debits::currency.eq(&posting.currency)),
+        )
+        .execute(conn),
+    }
+}
+
+fn rewrite_value(conn: &mut DbConnection, stored: &StoredValue) -> QueryResult<usize> {
+    let StoredValue {
+        table,
+        column,
+        rowid,
+    } = stored;
+    let raw = sql_query(format!(
+        "SELECT {ROW_ID} AS rowid, {column} AS value FROM {table} WHERE {ROW_ID} = {rowid}"
+    ))
+    .get_result::<RawValue>(conn)?;
+    if Rational::from_bytes(&raw.value).is_none() {
+        return Ok(0);
+    }
+
+    sql_query(format!(
+        "UPDATE {table} SET {column} = rat_canonical({column}) WHERE {ROW_ID} = {rowid}"
+    ))
+    .execute(conn)
+}
+
+/// Whether every post balances, with the changes made so far in the current
+/// database transaction. Unlike the check the database makes when the
+/// transaction commits, a failed check can still be rolled back.
+#[cfg(not(feature = "postgres"))]
+fn all_balanced(conn: &mut DbConnection) -> QueryResult<bool> {
+    let unbalanced = crate::schema::unbalanced_txs::table
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(unbalanced == 0)
+}
+
+/// PostgreSQL makes the deferred checks right away when asked to. A failed
+/// check aborts the transaction, which must then be rolled back.
+#[cfg(feature = "postgres")]
+fn all_balanced(conn: &mut DbConnection) -> QueryResult<bool> {
+    use diesel::result::{DatabaseErrorKind, Error};
+
+    match sql_query("SET CONSTRAINTS ALL IMMEDIATE").execute(conn) {
+        Ok(_) => {}
+        Err(Error::DatabaseError(DatabaseErrorKind::CheckViolation, _)) => return Ok(false),
+        Err(err) => return Err(err),
+    }
+    sql_query("SET CONSTRAINTS ALL DEFERRED").execute(conn)?;
+    Ok(true)
+}
+
+/// Repairs a problem found by [`check`], if it is repairable. Each repair
+/// is its own database transaction, and is left undone if it would leave a
+/// post unbalanced. Returns whether the problem was repaired.
+pub fn repair(conn: &mut DbConnection, problem: &Problem) -> QueryResult<bool> {
+    let result = conn.transaction(|conn| {
+        let (action, object, changed) = match problem {
+            Problem::OrphanPosting(posting) | Problem::ZeroValue(posting) => {
+                (Action::Delete, "posting", delete_posting(conn, posting)?)
+            }
+            Problem::NonCanonicalValue(stored) => {
+                (Action::Edit, "value", rewrite_value(conn, stored)?)
+            }
+            _ => return Ok(false),
+        };
+
+        // The triggers only allow a commit with no unbalanced posts, so a
+        // failed check must be rolled back here rather than at the commit
+        if changed == 0 || !all_balanced(conn)? {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+
+        audit::record(
+            conn,
+            Actor::cli(),
+            None,
+            action,
+            object,
+            serde_json::json!({ "repaired": problem.to_string() }),
+        )?;
+        Ok(true)
+    });
+
+    match result {
+        Err(diesel::result::Error::RollbackTransaction) => Ok(false),
+        result => result,
+    }
+}
+
+/// The problems can only be made with the checks turned off, which is done
+/// the SQLite way
+#[cfg(all(test, not(feature = "postgres")))]
+mod test {
+    use diesel::connection::SimpleConnection;
+
+    use super::*;
+
+    #[test]
+    fn finds_and_repairs_problems() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+
+        // Data like this can only come from before the balance triggers, so
+        // write it with the foreign keys off and forget the unbalanced posts
+        conn.batch_execute(
+            "PRAGMA foreign_keys = OFF;
+            INSERT INTO txs (id, ledger_id, tx_time, rev_time, description) VALUES
+                (1, 1, '2026-01-01 12:00:00', '2026-01-01 12:00:00', 'ok'),
+                (2, 1, '2026-01-01 12:00:00', '2026-01-01 12:00:00', 'unbalanced'),
+                (3, 1, '2026-01-02 12:00:00', '2026-01-01 12:00:00', 'empty'),
+                (4, 1, '2026-01-01 12:00:00', '2026-01-01 12:00:00', 'nameless');
+            INSERT INTO debits (tx_id, account, currency, value) VALUES
+                (1, 'bob', 'NOK', X'010000000A01'),
+                (1, 'carol', 'NOK', X'F10001'),
+                (2, 'bob', 'NOK', X'F10A01'),
+                (4, '', 'NOK', X'F10501');
+            INSERT INTO credits (tx_id, account, currency, value) VALUES
+                (1, 'alice', 'NOK', X'F10A01'),
+                (2, 'alice', 'NOK', X'F10901'),
+                (4, 'alice', 'NOK', X'F10501'),
+                (99, 'alice', 'NOK', X'F10501');
+            INSERT INTO exchange_rates (ledger_id, currency, date, rate) VALUES
+                (1, 'EUR', '2026-01-01', X'00');
+            DELETE FROM unbalanced_txs;
+            PRAGMA foreign_keys = ON;",
+        )?;
+
+        let posting = |side, tx_id, account: &str| Posting {
+            side,
+            tx_id,
+            account: account.to_owned(),
+            currency: "NOK".to_owned(),
+        };
+        let bob = StoredValue {
+            table: "debits",
+            column: "value",
+            rowid: 1,
+        };
+        let problems = check(&mut conn)?;
+        assert_eq!(
+            problems,
+            vec![
+                Problem::Unbalanced {
+                    tx_id: 2,
+                    currency: "NOK".to_owned()
+                },
+                Problem::Empty { tx_id: 3 },
+                Problem::RevisedBeforeTime { tx_id: 3 },
+                Problem::OrphanPosting(posting(Side::Credit, 99, "alice")),
+                Problem::ZeroValue(posting(Side::Debit, 1, "carol")),
+                Problem::EmptyAccountName(posting(Side::Debit, 4, "")),
+                Problem::InvalidValue(StoredValue {
+                    table: "exchange_rates",
+                    column: "rate",
+                    rowid: 1,
+                }),
+                Problem::NonCanonicalValue(bob),
+            ]
+        );
+
+        let repaired = problems
+            .iter()
+            .filter(|problem| repair(&mut conn, problem).unwrap())
+            .count();
+        assert_eq!(repaired, 3);
+        assert_eq!(
+            check(&mut conn)?,
+            problems
+                .into_iter()
+                .filter(|problem| !problem.is_repairable())
+                .collect::<Vec<_>>()
+        );
+
+        // Rewriting a value of an unbalanced post is left undone
+        let alice = StoredValue {
+            table: "credits",
+            column: "value",
+            rowid: 2,
+        };
+        assert!(!repair(&mut conn, &Problem::NonCanonicalValue(alice))?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,649 @@
+//! Reading and changing the transactions of a ledger. All the binaries go
+//! through [`Ledger`], so transactions are validated, checked against closed
+//! periods and logged the same way wherever they come from.
+
+use std::collections::BTreeMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use num::BigRational;
+use thiserror::Error;
+
+use crate::attachments::delete_tx_attachments;
+use crate::audit::{self, tx_snapshot, Action, Actor, Source};
+use crate::closing::{self, check_open, check_tx_open, ClosingError};
+use crate::currency::{parse_currency, InvalidCurrency};
+use crate::models::{NewCredit, NewDebit, NewTx, Tx, TxItem};
+use crate::rational::{rat_cmp, Rational};
+use crate::receipts::delete_receipt;
+use crate::schema::{credits, debits, tx_tags, txs};
+use crate::tags::{parse_tags, set_tx_tags, tx_tags, InvalidTag};
+use crate::DbConnection;
+
+/// Amounts by account and currency
+pub type Postings = BTreeMap<(String, String), Rational>;
+
+#[derive(Error, Debug)]
+pub enum LedgerError {
+    #[error("no transaction, credits and debits are zero")]
+    ZeroValue,
+    #[error("missing description")]
+    MissingDescription,
+    #[error("unbalanced transaction, credits != debits in {0}")]
+    Unbalanced(String),
+    #[error("empty account name")]
+    EmptyAccountName,
+    #[error(transparent)]
+    InvalidCurrency(#[from] InvalidCurrency),
+    #[error(transparent)]
+    InvalidTag(#[from] InvalidTag),
+    #[error(transparent)]
+    Closed(ClosingError),
+    #[error("no such transaction")]
+    NotFound,
+    #[error(transparent)]
+    Db(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for LedgerError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::NotFound => LedgerError::NotFound,
+            err => LedgerError::Db(err),
+        }
+    }
+}
+
+impl From<ClosingError> for LedgerError {
+    fn from(err: ClosingError) -> Self {
+        match err {
+            ClosingError::Db(err) => err.into(),
+            err => LedgerError::Closed(err),
+        }
+    }
+}
+
+/// Normalizes the currency codes of postings, using `base_currency` where
+/// none is given. Amounts for the same account and currency are added up.
+pub fn resolve_currencies(
+    postings: impl IntoIterator<Item = ((String, String), Rational)>,
+    base_currency: &str,
+) -> Result<Postings, InvalidCurrency> {
+    let mut resolved = Postings::new();
+    for ((account, currency), value) in postings {
+        let currency = if currency.is_empty() {
+            base_currency.to_owned()
+        } else {
+            parse_currency(&currency)?
+        };
+        *resolved.entry((account, currency)).or_default() += value;
+    }
+    Ok(resolved)
+}
+
+/// Checks that each currency balances on its own, and that there is some
+/// value
+fn check_balanced(debits: &Postings, credits: &Postings) -> Result<(), LedgerError> {
+    let mut sums = BTreeMap::<&str, (Rational, Rational)>::new();
+    for ((_, currency), value) in debits {
+        sums.entry(currency).or_default().0 += value.clone();
+    }
+    for ((_, currency), value) in credits {
+        sums.entry(currency).or_default().1 += value.clone();
+    }
+    if let Some((currency, _)) = sums
+        .iter()
+        .find(|(_, (sum_debits, sum_credits))| sum_debits != sum_credits)
+    {
+        return Err(LedgerError::Unbalanced(currency.to_string()));
+    }
+    if sums.values().all(|(sum_debits, _)| sum_debits.is_zero()) {
+        return Err(LedgerError::ZeroValue);
+    }
+
+    Ok(())
+}
+
+/// Checks that a transaction has a description, named accounts and some
+/// value, and that each currency balances on its own
+pub fn validate_postings(
+    what: &str,
+    debits: &Postings,
+    credits: &Postings,
+) -> Result<(), LedgerError> {
+    if what.is_empty() {
+        return Err(LedgerError::MissingDescription);
+    }
+
+    check_balanced(debits, credits)?;
+
+    if credits.keys().any(|(account, _)| account.is_empty())
+        || debits.keys().any(|(account, _)| account.is_empty())
+    {
+        return Err(LedgerError::EmptyAccountName);
+    }
+
+    Ok(())
+}
+
+/// A valid transaction, which can be stored in a ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    when: NaiveDateTime,
+    what: String,
+    tags: Vec<String>,
+    debits: Postings,
+    credits: Postings,
+}
+
+impl Transaction {
+    /// Validates a transaction. The tags are comma separated, and the
+    /// currencies of the postings must already be resolved with
+    /// [`resolve_currencies`].
+    pub fn new(
+        when: NaiveDateTime,
+        what: impl Into<String>,
+        tags: &str,
+        debits: Postings,
+        credits: Postings,
+    ) -> Result<Self, LedgerError> {
+        let what = what.into();
+        validate_postings(&what, &debits, &credits)?;
+
+        Ok(Transaction {
+            when,
+            what,
+            tags: parse_tags(tags)?,
+            debits,
+            credits,
+        })
+    }
+
+    /// The transaction with other tags, which are comma separated
+    pub fn with_tags(self, tags: &str) -> Result<Self, LedgerError> {
+        Ok(Transaction {
+            tags: parse_tags(tags)?,
+            ..self
+        })
+    }
+
+    pub fn when(&self) -> NaiveDateTime {
+        self.when
+    }
+
+    pub fn what(&self) -> &str {
+        &self.what
+    }
+
+    /// The tags, sorted
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn debits(&self) -> &Postings {
+        &self.debits
+    }
+
+    pub fn credits(&self) -> &Postings {
+        &self.credits
+    }
+}
+
+/// A transaction as it is stored, with its revision
+pub struct StoredTransaction {
+    pub tx: Tx,
+    pub transaction: Transaction,
+}
+
+fn insert_postings(
+    conn: &mut DbConnection,
+    tx_id: i32,
+    debits: &Postings,
+    credits: &Postings,
+) -> QueryResult<()> {
+    diesel::insert_into(credits::table)
+        .values(
+            credits
+                .iter()
+                .map(|((account, currency), value)| NewCredit {
+                    tx_id,
+                    account,
+                    currency,
+                    value: value.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)?;
+
+    diesel::insert_into(debits::table)
+        .values(
+            debits
+                .iter()
+                .map(|((account, currency), value)| NewDebit {
+                    tx_id,
+                    account,
+                    currency,
+                    value: value.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)?;
+
+    Ok(())
+}
+
+fn postings(items: Vec<TxItem>) -> Postings {
+    let mut postings = Postings::new();
+    for item in items {
+        *postings.entry((item.account, item.currency)).or_default() += item.value;
+    }
+    postings
+}
+
+/// The stored debits and credits of a transaction
+fn load_postings(conn: &mut DbConnection, tx_id: i32) -> QueryResult<(Postings, Postings)> {
+    let debits = debits::table
+        .select((debits::account, debits::currency, debits::value))
+        .filter(debits::tx_id.eq(tx_id))
+        .load::<TxItem>(conn)?;
+    let credits = credits::table
+        .select((credits::account, credits::currency, credits::value))
+        .filter(credits::tx_id.eq(tx_id))
+        .load::<TxItem>(conn)?;
+    Ok((postings(debits), postings(credits)))
+}
+
+/// The transactions of one ledger
+pub struct Ledger<'a> {
+    conn: &'a mut DbConnection,
+    id: i32,
+}
+
+impl<'a> Ledger<'a> {
+    pub fn new(conn: &'a mut DbConnection, ledger_id: i32) -> Self {
+        Ledger {
+            conn,
+            id: ledger_id,
+        }
+    }
+
+    /// Balances of all accounts before `before`, or of all posts if it is
+    /// `None`, per account and currency. Positive balances are credit
+    /// balances.
+    pub fn balances(
+        &mut self,
+        before: Option<NaiveDate>,
+    ) -> Result<BTreeMap<(String, String), BigRational>, LedgerError> {
+        Ok(closing::balances(self.conn, self.id, before)?)
+    }
+
+    /// Stores a new transaction, returning its id. Transactions from the
+    /// importer are logged as imports.
+    pub fn insert_transaction(
+        &mut self,
+        actor: Actor,
+        transaction: &Transaction,
+    ) -> Result<i32, LedgerError> {
+        self.insert(actor, transaction, None)
+    }
+
+    /// Stores a new transaction that was created from a recurring
+    /// transaction, linked back to it, and returns its id.
+    pub fn insert_recurring_transaction(
+        &mut self,
+        actor: Actor,
+        recurring_tx_id: i32,
+        transaction: &Transaction,
+    ) -> Result<i32, LedgerError> {
+        self.insert(actor, transaction, Some(recurring_tx_id))
+    }
+
+    fn insert(
+        &mut self,
+        actor: Actor,
+        transaction: &Transaction,
+        recurring_tx_id: Option<i32>,
+    ) -> Result<i32, LedgerError> {
+        let ledger_id = self.id;
+        crate::transaction(self.conn, |conn| {
+            check_open(conn, ledger_id, transaction.when)?;
+
+            // The id is allocated by the insert, so concurrent posts never collide
+            let id = diesel::insert_into(txs::table)
+                .values(&NewTx {
+                    ledger_id,
+                    tx_time: transaction.when,
+                    rev_time: chrono::Utc::now().naive_utc(),
+                    description: &transaction.what,
+                    rev_user_id: actor.user_id,
+                    recurring_tx_id,
+                    closing_id: None,
+                })
+                .returning(txs::id)
+                .get_result::<i32>(conn)?;
+
+            insert_postings(conn, id, &transaction.debits, &transaction.credits)?;
+            set_tx_tags(conn, id, &transaction.tags)?;
+
+            let action = match actor.source {
+                Source::Importer => Action::Import,
+                _ => Action::Create,
+            };
+            let payload = tx_snapshot(conn, id)?;
+            audit::record(conn, actor, Some(ledger_id), action, "post", payload)?;
+
+            Ok(id)
+        })
+    }
+
+    /// Replaces a transaction with a new revision. Its itemization, which no
+    /// longer matches the postings, is dropped.
+    pub fn replace_transaction(
+        &mut self,
+        actor: Actor,
+        id: i32,
+        transaction: &Transaction,
+    ) -> Result<(), LedgerError> {
+        let ledger_id = self.id;
+        crate::transaction(self.conn, |conn| {
+            // Changes to closed periods are refused, both where the
+            // transaction was and where it goes
+            txs::table
+                .find(id)
+                .filter(txs::ledger_id.eq(ledger_id))
+                .select(txs::id)
+                .first::<i32>(conn)?;
+            check_tx_open(conn, id)?;
+            check_open(conn, ledger_id, transaction.when)?;
+
+            diesel::update(txs::table.find(id))
+                .set((
+                    txs::tx_time.eq(transaction.when),
+                    txs::rev_time.eq(chrono::Utc::now().naive_utc()),
+                    txs::description.eq(&transaction.what),
+                    txs::rev_user_id.eq(actor.user_id),
+                ))
+                .execute(conn)?;
+
+            diesel::delete(credits::table.filter(credits::tx_id.eq(id))).execute(conn)?;
+            diesel::delete(debits::table.filter(debits::tx_id.eq(id))).execute(conn)?;
+            insert_postings(conn, id, &transaction.debits, &transaction.credits)?;
+            set_tx_tags(conn, id, &transaction.tags)?;
+            delete_receipt(conn, id)?;
+
+            let payload = tx_snapshot(conn, id)?;
+            audit::record(conn, actor, Some(ledger_id), Action::Edit, "post", payload)?;
+
+            Ok(())
+        })
+    }
+
+    /// Deletes a transaction along with its tags, attachments and itemization
+    pub fn delete_transaction(&mut self, actor: Actor, id: i32) -> Result<(), LedgerError> {
+        let ledger_id = self.id;
+        crate::transaction(self.conn, |conn| {
+            txs::table
+                .find(id)
+                .filter(txs::ledger_id.eq(ledger_id))
+                .select(txs::id)
+                .first::<i32>(conn)?;
+            check_tx_open(conn, id)?;
+
+            let payload = tx_snapshot(conn, id)?;
+            audit::record(
+                conn,
+                actor,
+                Some(ledger_id),
+                Action::Delete,
+                "post",
+                payload,
+            )?;
+
+            diesel::delete(credits::table.filter(credits::tx_id.eq(id))).execute(conn)?;
+            diesel::delete(debits::table.filter(debits::tx_id.eq(id))).execute(conn)?;
+            diesel::delete(tx_tags::table.filter(tx_tags::tx_id.eq(id))).execute(conn)?;
+            delete_tx_attachments(conn, id)?;
+            delete_receipt(conn, id)?;
+            diesel::delete(txs::table.find(id)).execute(conn)?;
+
+            Ok(())
+        })
+    }
+
+    fn load(&mut self, tx: Tx) -> Result<StoredTransaction, LedgerError> {
+        let (debits, credits) = load_postings(self.conn, tx.id)?;
+
+        // Stored transactions were validated when they were stored, and the
+        // posts of a closing have no tags
+        let transaction = Transaction {
+            when: tx.tx_time,
+            what: tx.description.clone(),
+            tags: tx_tags(self.conn, tx.id)?,
+            debits,
+            credits,
+        };
+
+        Ok(StoredTransaction { tx, transaction })
+    }
+
+    pub fn get_transaction(&mut self, id: i32) -> Result<StoredTransaction, LedgerError> {
+        let tx = txs::table
+            .find(id)
+            .filter(txs::ledger_id.eq(self.id))
+            .first::<Tx>(self.conn)?;
+        self.load(tx)
+    }
+
+    /// The latest transactions, newest first, optionally only those with
+    /// `tag`, those with a debit or credit for `account` and those with a
+    /// debit of at least `min_amount`, in any currency
+    pub fn list_transactions(
+        &mut self,
+        tag: Option<&str>,
+        account: Option<&str>,
+        min_amount: Option<&Rational>,
+        limit: i64,
+    ) -> Result<Vec<StoredTransaction>, LedgerError> {
+        let mut query = txs::table
+            .filter(txs::ledger_id.eq(self.id))
+            .order((txs::tx_time.desc(), txs::id.desc()))
+            .limit(limit)
+            .into_boxed();
+        if let Some(tag) = tag {
+            query = query.filter(
+                txs::id.eq_any(
+                    tx_tags::table
+                        .filter(tx_tags::tag.eq(tag.to_owned()))
+                        .select(tx_tags::tx_id),
+                ),
+            );
+        }
+        if let Some(account) = account {
+            query = query.filter(
+                exists(
+                    debits::table
+                        .filter(debits::tx_id.eq(txs::id))
+                        .filter(debits::account.eq(account.to_owned())),
+                )
+                .or(exists(
+                    credits::table
+                        .filter(credits::tx_id.eq(txs::id))
+                        .filter(credits::account.eq(account.to_owned())),
+                )),
+            );
+        }
+        if let Some(min_amount) = min_amount {
+            query = query.filter(exists(
+                debits::table
+                    .filter(debits::tx_id.eq(txs::id))
+                    .filter(rat_cmp(debits::value, min_amount.clone()).ge(0)),
+            ));
+        }
+        query
+            .load::<Tx>(self.conn)?
+            .into_iter()
+            .map(|tx| self.load(tx))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn postings(items: &[(&str, u32)]) -> Postings {
+        items
+            .iter()
+            .map(|(account, value)| (((*account).to_owned(), "NOK".to_owned()), (*value).into()))
+            .collect()
+    }
+
+    #[test]
+    fn validates_transactions() {
+        let when = NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let new = |what: &str, debits, credits| Transaction::new(when, what, "", debits, credits);
+
+        assert!(new(
+            "Pizza",
+            postings(&[("bob", 10)]),
+            postings(&[("alice", 10)])
+        )
+        .is_ok());
+        assert!(matches!(
+            new("", postings(&[("bob", 10)]), postings(&[("alice", 10)])),
+            Err(LedgerError::MissingDescription)
+        ));
+        assert!(matches!(
+            new("Pizza", postings(&[("bob", 10)]), postings(&[("alice", 9)])),
+            Err(LedgerError::Unbalanced(_))
+        ));
+        assert!(matches!(
+            new("Pizza", postings(&[("bob", 0)]), postings(&[("alice", 0)])),
+            Err(LedgerError::ZeroValue)
+        ));
+        assert!(matches!(
+            new("Pizza", postings(&[("", 10)]), postings(&[("alice", 10)])),
+            Err(LedgerError::EmptyAccountName)
+        ));
+    }
+
+    #[test]
+    fn stores_transactions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        let mut ledger = Ledger::new(&mut conn, 1);
+        let when = NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+
+        let pizza = Transaction::new(
+            when,
+            "Pizza",
+            "Food",
+            postings(&[("bob", 10)]),
+            postings(&[("alice", 10)]),
+        )?;
+        let id = ledger.insert_transaction(Actor::cli(), &pizza)?;
+        assert_eq!(ledger.get_transaction(id)?.transaction, pizza);
+
+        let beer = Transaction::new(
+            when,
+            "Beer",
+            "",
+            postings(&[("bob", 3), ("carol", 3)]),
+            postings(&[("alice", 6)]),
+        )?;
+        ledger.replace_transaction(Actor::cli(), id, &beer)?;
+        let listed = ledger.list_transactions(None, None, None, 10)?;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].transaction, beer);
+        assert!(ledger
+            .list_transactions(Some("food"), None, None, 10)?
+            .is_empty());
+        assert_eq!(
+            ledger
+                .list_transactions(None, Some("carol"), None, 10)?
+                .len(),
+            1
+        );
+        assert_eq!(
+            ledger
+                .list_transactions(None, Some("alice"), None, 10)?
+                .len(),
+            1
+        );
+        assert!(ledger
+            .list_transactions(None, Some("dave"), None, 10)?
+            .is_empty());
+        let three = Rational::from(3u32);
+        assert_eq!(
+            ledger
+                .list_transactions(None, None, Some(&three), 10)?
+                .len(),
+            1
+        );
+        let four = Rational::from(4u32);
+        assert!(ledger
+            .list_transactions(None, None, Some(&four), 10)?
+            .is_empty());
+        assert_eq!(
+            ledger.balances(None)?[&("alice".to_owned(), "NOK".to_owned())],
+            BigRational::from_integer(6.into())
+        );
+
+        ledger.delete_transaction(Actor::cli(), id)?;
+        assert!(matches!(
+            ledger.get_transaction(id),
+            Err(LedgerError::NotFound)
+        ));
+        assert!(matches!(
+            ledger.delete_transaction(Actor::cli(), id),
+            Err(LedgerError::NotFound)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rolls_back_unbalanced_transactions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        let mut ledger = Ledger::new(&mut conn, 1);
+        let when = NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+
+        // Only possible here, as Transaction::new checks the balance. The
+        // database refuses it when committing.
+        let unbalanced = Transaction {
+            when,
+            what: "Pizza".to_owned(),
+            tags: Vec::new(),
+            debits: postings(&[("bob", 10)]),
+            credits: postings(&[("alice", 9)]),
+        };
+        assert!(matches!(
+            ledger.insert_transaction(Actor::cli(), &unbalanced),
+            Err(LedgerError::Db(_))
+        ));
+
+        // The same connection goes on to store and replace transactions
+        let pizza = Transaction {
+            credits: postings(&[("alice", 10)]),
+            ..unbalanced.clone()
+        };
+        let id = ledger.insert_transaction(Actor::cli(), &pizza)?;
+        assert!(matches!(
+            ledger.replace_transaction(Actor::cli(), id, &unbalanced),
+            Err(LedgerError::Db(_))
+        ));
+        assert_eq!(ledger.get_transaction(id)?.transaction, pizza);
+        assert_eq!(ledger.list_transactions(None, None, None, 10)?.len(), 1);
+
+        Ok(())
+    }
+}
@@ -1,50 +1,576 @@
-use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, R2D2Connection};
 use diesel::sql_types::*;
 use diesel::{prelude::*, SqliteConnection};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-use rational::{sum_rat, SumRat};
+use rational::{
+    rat_canonical, rat_cmp, rat_eq, rat_from_text, rat_sub, rat_to_real, rat_to_text, sum_rat,
+    Rational, SumRat,
+};
 
+/// Stores a type as text in the database, using its `Display` and `FromStr`
+/// implementations
+macro_rules! text_sql {
+    ($type:ty) => {
+        impl diesel::serialize::ToSql<diesel::sql_types::Text, diesel::sqlite::Sqlite> for $type {
+            fn to_sql<'c>(
+                &'c self,
+                out: &mut diesel::serialize::Output<'c, '_, diesel::sqlite::Sqlite>,
+            ) -> diesel::serialize::Result {
+                out.set_value(self.to_string());
+                Ok(diesel::serialize::IsNull::No)
+            }
+        }
+
+        impl diesel::deserialize::FromSql<diesel::sql_types::Text, diesel::sqlite::Sqlite>
+            for $type
+        {
+            fn from_sql(
+                bytes: diesel::sqlite::SqliteValue<'_, '_, '_>,
+            ) -> diesel::deserialize::Result<Self> {
+                let s = <String as diesel::deserialize::FromSql<
+                    diesel::sql_types::Text,
+                    diesel::sqlite::Sqlite,
+                >>::from_sql(bytes)?;
+                Ok(s.parse()?)
+            }
+        }
+
+        #[cfg(feature = "postgres")]
+        impl diesel::serialize::ToSql<diesel::sql_types::Text, diesel::pg::Pg> for $type {
+            fn to_sql<'c>(
+                &'c self,
+                out: &mut diesel::serialize::Output<'c, '_, diesel::pg::Pg>,
+            ) -> diesel::serialize::Result {
+                std::io::Write::write_all(out, self.to_string().as_bytes())?;
+                Ok(diesel::serialize::IsNull::No)
+            }
+        }
+
+        #[cfg(feature = "postgres")]
+        impl diesel::deserialize::FromSql<diesel::sql_types::Text, diesel::pg::Pg> for $type {
+            fn from_sql(value: diesel::pg::PgValue<'_>) -> diesel::deserialize::Result<Self> {
+                Ok(std::str::from_utf8(value.as_bytes())?.parse()?)
+            }
+        }
+    };
+}
+
+/// Gives a fieldless enum a name for each variant, with `as_str`, `FromStr`,
+/// `Display` and conversions to and from text in the database. Parsing any
+/// other name fails with `$error`.
+macro_rules! text_enum {
+    ($type:ident, $error:ident { $($variant:ident => $name:literal),+ $(,)? }) => {
+        impl $type {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($type::$variant => $name,)+
+                }
+            }
+        }
+
+        impl std::str::FromStr for $type {
+            type Err = $error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($name => Ok($type::$variant),)+
+                    _ => Err($error),
+                }
+            }
+        }
+
+        impl std::fmt::Display for $type {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        text_sql!($type);
+    };
+}
+
+pub mod amount;
+pub mod attachments;
+pub mod audit;
+pub mod auth;
+pub mod backup;
+pub mod budgets;
+pub mod closing;
+pub mod currency;
+pub mod format;
+pub mod fsck;
+pub mod ledger;
 pub mod models;
 pub mod parse_arg; // for doctests
+pub mod post_templates;
 pub mod rational;
+pub mod receipts;
+pub mod recurring;
+pub mod schedule;
 pub mod schema;
+pub mod tags;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
-pub fn establish_connection(database_url: &str) -> SqliteConnection {
-    let mut con = SqliteConnection::establish(&database_url)
+#[cfg(feature = "postgres")]
+pub const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations-postgres");
+
+/// A kind of database the ledgers can be stored in
+pub trait Database:
+    R2D2Connection + MigrationHarness<<Self as Connection>::Backend> + 'static
+{
+    /// The migrations that bring the schema up to date
+    const MIGRATIONS: EmbeddedMigrations;
+
+    /// Prepares a new connection for use, before any migrations are run
+    fn initialize(&mut self) -> QueryResult<()>;
+}
+
+impl Database for SqliteConnection {
+    const MIGRATIONS: EmbeddedMigrations = MIGRATIONS;
+
+    fn initialize(&mut self) -> QueryResult<()> {
+        diesel::dsl::sql::<(Integer,)>("PRAGMA foreign_keys = ON").execute(self)?;
+
+        sum_rat::register_impl::<SumRat, _>(self)?;
+        rat_eq::register_impl(self, |x: Rational, y: Rational| x == y)?;
+        rat_canonical::register_impl(self, |x: Vec<u8>| {
+            Rational::from_bytes(&x).map_or(x, |value| value.to_bytes())
+        })?;
+        rat_cmp::register_impl(self, |x: Rational, y: Rational| x.cmp(&y) as i32)?;
+        rat_sub::register_impl(self, |x: Rational, y: Rational| x.checked_sub(&y))?;
+        rat_to_text::register_impl(self, |x: Rational| x.to_string())?;
+        rat_to_real::register_impl(self, |x: Rational| x.to_f64())?;
+        rat_from_text::register_impl(self, |x: String| amount::parse_number(&x).ok())?;
+
+        Ok(())
+    }
+}
+
+/// The rational functions are created by the migrations
+#[cfg(feature = "postgres")]
+impl Database for diesel::PgConnection {
+    const MIGRATIONS: EmbeddedMigrations = POSTGRES_MIGRATIONS;
+
+    fn initialize(&mut self) -> QueryResult<()> {
+        Ok(())
+    }
+}
+
+pub fn connect<C: Database>(database_url: &str) -> C {
+    let con = C::establish(database_url)
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
 
-    SqliteInitializer.on_acquire(&mut con).unwrap();
-    con.run_pending_migrations(MIGRATIONS).unwrap();
+    prepare(con)
+}
+
+/// Prepares a new connection and brings the schema up to date
+fn prepare<C: Database>(mut con: C) -> C {
+    con.initialize().unwrap();
+    con.run_pending_migrations(C::MIGRATIONS).unwrap();
 
     con
 }
 
-#[derive(Debug)]
-struct SqliteInitializer;
+/// The database the programs store the ledgers in. Builds with the
+/// `postgres` feature use PostgreSQL, the others SQLite.
+#[cfg(not(feature = "postgres"))]
+pub type DbConnection = SqliteConnection;
+#[cfg(feature = "postgres")]
+pub type DbConnection = diesel::PgConnection;
 
-impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for SqliteInitializer {
-    fn on_acquire(&self, con: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
-        diesel::dsl::sql::<(Integer,)>("PRAGMA foreign_keys = ON")
-            .execute(con)
-            .map_err(|x| diesel::r2d2::Error::QueryError(x))?;
+/// The database given by `DATABASE_URL`. SQLite builds default to `test.db`
+/// in the current directory, PostgreSQL builds need it to be set.
+pub fn database_url() -> String {
+    match std::env::var("DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) if cfg!(feature = "postgres") => {
+            panic!("DATABASE_URL must be set to the URL of the PostgreSQL database")
+        }
+        Err(_) => "test.db".to_owned(),
+    }
+}
 
-        sum_rat::register_impl::<SumRat, _>(con).unwrap();
+pub fn establish_connection(database_url: &str) -> DbConnection {
+    connect(database_url)
+}
 
-        Ok(())
+#[derive(Debug)]
+struct Initializer;
+
+impl<C: Database> CustomizeConnection<C, diesel::r2d2::Error> for Initializer {
+    fn on_acquire(&self, con: &mut C) -> Result<(), diesel::r2d2::Error> {
+        con.initialize().map_err(diesel::r2d2::Error::QueryError)
     }
 }
 
-pub fn create_pool<S: Into<String>>(
+pub fn create_database_pool<C: Database, S: Into<String>>(
     connection_string: S,
-) -> Result<Pool<ConnectionManager<SqliteConnection>>, Box<dyn std::error::Error>> {
-    let manager = ConnectionManager::<SqliteConnection>::new(connection_string);
+) -> Result<Pool<ConnectionManager<C>>, Box<dyn std::error::Error>> {
+    let manager = ConnectionManager::<C>::new(connection_string);
     let pool = Pool::builder()
-        .connection_customizer(Box::new(SqliteInitializer {}))
+        .connection_customizer(Box::new(Initializer {}))
         .build(manager)?;
 
-    pool.get()?.run_pending_migrations(MIGRATIONS).unwrap();
+    pool.get()?.run_pending_migrations(C::MIGRATIONS).unwrap();
 
     Ok(pool)
 }
+
+pub fn create_pool<S: Into<String>>(
+    connection_string: S,
+) -> Result<Pool<ConnectionManager<DbConnection>>, Box<dyn std::error::Error>> {
+    create_database_pool(connection_string)
+}
+
+/// Runs `f` in a database transaction like `Connection::transaction`, but
+/// also rolls back when the commit fails. The balance checks are made at the
+/// commit, and SQLite keeps the transaction open when they fail, which would
+/// leave the connection stuck in it.
+pub fn transaction<C, T, E, F>(conn: &mut C, f: F) -> Result<T, E>
+where
+    C: Connection,
+    F: FnOnce(&mut C) -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    use diesel::connection::TransactionManager;
+
+    C::TransactionManager::begin_transaction(conn)?;
+    let result = f(conn).and_then(|value| {
+        C::TransactionManager::commit_transaction(conn)?;
+        Ok(value)
+    });
+    if result.is_err() {
+        match C::TransactionManager::rollback_transaction(conn) {
+            // PostgreSQL ends the transaction when the commit fails
+            Ok(()) | Err(diesel::result::Error::NotInTransaction) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    result
+}
+
+/// A connection to a new, empty database for a test. PostgreSQL builds use
+/// the database at the URL in `SHAREBILL_TEST_POSTGRES`, such as
+/// `postgres://localhost/sharebill_test`, with a schema per connection.
+#[cfg(all(test, not(feature = "postgres")))]
+pub(crate) fn test_connection() -> DbConnection {
+    establish_connection(":memory:")
+}
+
+#[cfg(all(test, feature = "postgres"))]
+pub(crate) fn test_connection() -> DbConnection {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SCHEMAS: AtomicUsize = AtomicUsize::new(0);
+    test::postgres_connection(&format!("test_{}", SCHEMAS.fetch_add(1, Ordering::Relaxed)))
+}
+
+#[cfg(test)]
+mod test {
+    /// Tests of the storage layer that every kind of database must pass. The
+    /// module they are expanded in provides the connection type `Conn`, and
+    /// `connect`, which returns a connection to a new, migrated database.
+    macro_rules! database_tests {
+        () => {
+            use chrono::NaiveDate;
+            use diesel::prelude::*;
+
+            use crate::models::{NewCredit, NewDebit, NewTx};
+            use crate::rational::{
+                rat_cmp, rat_eq, rat_from_text, rat_sub, rat_to_real, rat_to_text, sum_rat,
+                Rational,
+            };
+            use crate::schema::{credits, debits, ledgers, txs};
+
+            fn insert_tx(
+                conn: &mut Conn,
+                debit_values: &[(&str, &str, Rational)],
+                credit_values: &[(&str, &str, Rational)],
+            ) -> QueryResult<i32> {
+                let when = NaiveDate::from_ymd_opt(2026, 10, 18)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0)
+                    .unwrap();
+                crate::transaction(conn, |conn| {
+                    let tx_id = diesel::insert_into(txs::table)
+                        .values(&NewTx {
+                            ledger_id: 1,
+                            tx_time: when,
+                            rev_time: when,
+                            description: "test",
+                            rev_user_id: None,
+                            recurring_tx_id: None,
+                            closing_id: None,
+                        })
+                        .returning(txs::id)
+                        .get_result::<i32>(conn)?;
+                    for (account, currency, value) in debit_values {
+                        diesel::insert_into(debits::table)
+                            .values(&NewDebit {
+                                tx_id,
+                                account,
+                                currency,
+                                value: value.clone(),
+                            })
+                            .execute(conn)?;
+                    }
+                    for (account, currency, value) in credit_values {
+                        diesel::insert_into(credits::table)
+                            .values(&NewCredit {
+                                tx_id,
+                                account,
+                                currency,
+                                value: value.clone(),
+                            })
+                            .execute(conn)?;
+                    }
+                    Ok(tx_id)
+                })
+            }
+
+            #[test]
+            fn stores_and_sums_rationals() -> Result<(), Box<dyn std::error::Error>> {
+                let mut conn = connect("stores_and_sums_rationals");
+
+                let slug = ledgers::table
+                    .find(1)
+                    .select(ledgers::slug)
+                    .first::<String>(&mut conn)?;
+                assert_eq!(slug, "default");
+
+                let third = Rational::new(1u32, 3u32);
+                let two_thirds = Rational::new(2u32, 3u32);
+                let tx_id = insert_tx(
+                    &mut conn,
+                    &[("bob", "NOK", third.clone()), ("carol", "NOK", two_thirds)],
+                    &[("alice", "NOK", 1u32.into())],
+                )?;
+
+                let stored = debits::table
+                    .filter(debits::account.eq("bob"))
+                    .select(debits::value)
+                    .first::<Rational>(&mut conn)?;
+                assert_eq!(stored, third);
+
+                let sum = debits::table
+                    .filter(debits::tx_id.eq(tx_id))
+                    .select(sum_rat(debits::value))
+                    .first::<Rational>(&mut conn)?;
+                assert_eq!(sum, 1u32.into());
+
+                let nothing = credits::table
+                    .filter(credits::account.eq("nobody"))
+                    .select(sum_rat(credits::value))
+                    .first::<Rational>(&mut conn)?;
+                assert_eq!(nothing, 0u32.into());
+
+                let halves = rat_eq(Rational::new(2u32, 4u32), Rational::new(1u32, 2u32));
+                let reduced = diesel::select(halves).get_result::<bool>(&mut conn)?;
+                assert!(reduced);
+
+                Ok(())
+            }
+
+            #[test]
+            fn compares_and_renders_rationals() -> Result<(), Box<dyn std::error::Error>> {
+                let mut conn = connect("compares_and_renders_rationals");
+
+                let five_halves = Rational::new(5u32, 2u32);
+                insert_tx(
+                    &mut conn,
+                    &[
+                        ("bob", "NOK", 10u32.into()),
+                        ("carol", "NOK", five_halves.clone()),
+                    ],
+                    &[("alice", "NOK", Rational::new(25u32, 2u32))],
+                )?;
+
+                let over_three = debits::table
+                    .filter(rat_cmp(debits::value, Rational::from(3u32)).gt(0))
+                    .select(debits::account)
+                    .load::<String>(&mut conn)?;
+                assert_eq!(over_three, ["bob"]);
+
+                let by_value = debits::table
+                    .order(rat_to_real(debits::value))
+                    .select((debits::account, rat_to_text(debits::value)))
+                    .load::<(String, String)>(&mut conn)?;
+                assert_eq!(
+                    by_value,
+                    [
+                        ("carol".to_owned(), "5/2".to_owned()),
+                        ("bob".to_owned(), "10".to_owned())
+                    ]
+                );
+
+                let difference = rat_sub(Rational::from(10u32), five_halves.clone());
+                let difference =
+                    diesel::select(difference).get_result::<Option<Rational>>(&mut conn)?;
+                assert_eq!(difference, Some(Rational::new(15u32, 2u32)));
+                let negative = rat_sub(five_halves.clone(), Rational::from(10u32));
+                let negative =
+                    diesel::select(negative).get_result::<Option<Rational>>(&mut conn)?;
+                assert_eq!(negative, None);
+
+                // Both databases read the same plain numbers
+                for (text, value) in [
+                    ("12,50", Some(Rational::new(25u32, 2u32))),
+                    (" 12.5 ", Some(Rational::new(25u32, 2u32))),
+                    (".5", Some(Rational::new(1u32, 2u32))),
+                    ("5.", Some(Rational::from(5u32))),
+                    ("5/2", Some(five_halves.clone())),
+                    ("1/0", None),
+                    ("1.2.3", None),
+                    ("3 1/2", None),
+                    ("120/3 + 15", None),
+                    ("2*3", None),
+                    ("-5", None),
+                    ("−5", None),
+                    ("+5", None),
+                    ("twelve", None),
+                ] {
+                    let parsed = diesel::select(rat_from_text(text))
+                        .get_result::<Option<Rational>>(&mut conn)?;
+                    assert_eq!(parsed, value, "{text}");
+                }
+
+                Ok(())
+            }
+
+            #[test]
+            fn rejects_unbalanced_transactions() -> Result<(), Box<dyn std::error::Error>> {
+                let mut conn = connect("rejects_unbalanced_transactions");
+
+                let rejected = insert_tx(
+                    &mut conn,
+                    &[("bob", "NOK", 10u32.into())],
+                    &[("alice", "NOK", 9u32.into())],
+                );
+                assert!(rejected.is_err());
+                assert!(insert_tx(&mut conn, &[], &[]).is_err());
+                // Balancing in one currency does not make up for another
+                let rejected = insert_tx(
+                    &mut conn,
+                    &[("bob", "NOK", 10u32.into()), ("bob", "EUR", 1u32.into())],
+                    &[("alice", "NOK", 10u32.into())],
+                );
+                assert!(rejected.is_err());
+
+                // The rejected posts are rolled back, and the connection is
+                // still usable
+                let tx_id = insert_tx(
+                    &mut conn,
+                    &[("bob", "NOK", 10u32.into())],
+                    &[("alice", "NOK", 10u32.into())],
+                )?;
+
+                // Statements outside of a transaction are checked right away
+                assert!(
+                    diesel::update(credits::table.filter(credits::tx_id.eq(tx_id)))
+                        .set(credits::value.eq(Rational::from(9u32)))
+                        .execute(&mut conn)
+                        .is_err()
+                );
+                assert!(
+                    diesel::delete(credits::table.filter(credits::tx_id.eq(tx_id)))
+                        .execute(&mut conn)
+                        .is_err()
+                );
+                assert_eq!(txs::table.count().get_result::<i64>(&mut conn)?, 1);
+
+                Ok(())
+            }
+        };
+    }
+
+    /// A connection to the database at the URL in `SHAREBILL_TEST_POSTGRES`,
+    /// in a new schema with the given name
+    #[cfg(feature = "postgres")]
+    pub(crate) fn postgres_connection(schema: &str) -> diesel::PgConnection {
+        use diesel::connection::SimpleConnection;
+        use diesel::Connection;
+
+        let url = std::env::var("SHAREBILL_TEST_POSTGRES")
+            .expect("SHAREBILL_TEST_POSTGRES should be the URL of a database to test in");
+        let mut conn = diesel::PgConnection::establish(&url).unwrap();
+        conn.batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE; \
+             CREATE SCHEMA {schema}; \
+             SET search_path TO {schema}"
+        ))
+        .unwrap();
+        crate::prepare(conn)
+    }
+
+    mod sqlite {
+        type Conn = diesel::SqliteConnection;
+
+        fn connect(_name: &str) -> Conn {
+            crate::connect(":memory:")
+        }
+
+        database_tests!();
+
+        #[test]
+        fn canonicalizes_only_balanced_posts() -> Result<(), Box<dyn std::error::Error>> {
+            use diesel::connection::SimpleConnection;
+            use diesel::Connection;
+            use diesel_migrations::MigrationHarness;
+
+            use crate::Database;
+
+            let mut conn = Conn::establish(":memory:")?;
+            conn.initialize()?;
+            // Migrates up to the rewrite
+            loop {
+                let pending = conn.pending_migrations(crate::MIGRATIONS).unwrap();
+                let name = pending[0].name().to_string();
+                if name.starts_with("2026-10-18-230000") {
+                    break;
+                }
+                conn.run_next_migration(crate::MIGRATIONS).unwrap();
+            }
+
+            // Both posts are in the legacy form, and the second one is from
+            // before the balance triggers
+            conn.batch_execute(
+                "PRAGMA foreign_keys = OFF;
+                INSERT INTO txs (id, ledger_id, tx_time, rev_time, description) VALUES
+                    (1, 1, '2026-01-01 12:00:00', '2026-01-01 12:00:00', 'ok'),
+                    (2, 1, '2026-01-01 12:00:00', '2026-01-01 12:00:00', 'unbalanced');
+                INSERT INTO debits (tx_id, account, currency, value) VALUES
+                    (1, 'bob', 'NOK', X'010000000A01'),
+                    (2, 'bob', 'NOK', X'010000000A01');
+                INSERT INTO credits (tx_id, account, currency, value) VALUES
+                    (1, 'alice', 'NOK', X'010000000A01'),
+                    (2, 'alice', 'NOK', X'010000000901');
+                DELETE FROM unbalanced_txs;
+                PRAGMA foreign_keys = ON;",
+            )?;
+            conn.run_pending_migrations(crate::MIGRATIONS).unwrap();
+
+            let stored = debits::table
+                .select((debits::tx_id, debits::value))
+                .union_all(credits::table.select((credits::tx_id, credits::value)))
+                .load::<(i32, Vec<u8>)>(&mut conn)?;
+            for (tx_id, value) in stored {
+                let canonical = Rational::from_bytes(&value).unwrap().to_bytes();
+                assert_eq!(value == canonical, tx_id == 1);
+            }
+
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    mod postgres {
+        type Conn = diesel::PgConnection;
+
+        fn connect(name: &str) -> Conn {
+            super::postgres_connection(name)
+        }
+
+        database_tests!();
+    }
+}
@@ -1,39 +1,172 @@
 use diesel::prelude::*;
 
+#[derive(Queryable)]
+pub struct Ledger {
+    pub id: i32,
+    pub slug: String,
+    pub name: String,
+    pub decimals: i32,
+    pub decimal_separator: String,
+    pub thousands_separator: String,
+    pub currency_symbol: String,
+    pub currency_before: bool,
+    pub base_currency: String,
+}
+
 #[derive(Queryable)]
 pub struct Tx {
     pub id: i32,
+    pub ledger_id: i32,
     pub tx_time: chrono::NaiveDateTime,
     pub rev_time: chrono::NaiveDateTime,
     pub description: String,
+    pub rev_user_id: Option<i32>,
+    pub recurring_tx_id: Option<i32>,
+    pub closing_id: Option<i32>,
+}
+
+#[derive(Queryable)]
+pub struct RecurringTx {
+    pub id: i32,
+    pub ledger_id: i32,
+    pub description: String,
+    pub schedule: Schedule,
+    pub start_date: chrono::NaiveDate,
+    pub end_date: Option<chrono::NaiveDate>,
+    pub last_date: Option<chrono::NaiveDate>,
+}
+
+#[derive(Queryable)]
+pub struct PostTemplate {
+    pub id: i32,
+    pub ledger_id: i32,
+    pub name: String,
+    pub description: String,
+}
+
+/// An attachment without its contents
+#[derive(Queryable)]
+pub struct Attachment {
+    pub id: i32,
+    pub tx_id: i32,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i32,
+    pub created_time: chrono::NaiveDateTime,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Queryable)]
+pub struct Budget {
+    pub id: i32,
+    pub ledger_id: i32,
+    pub target: BudgetTarget,
+    pub name: String,
+    pub currency: String,
+    pub amount: Rational,
+    pub period: Period,
+}
+
+#[derive(Queryable)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub time: chrono::NaiveDateTime,
+    pub ledger_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub source: Source,
+    pub action: Action,
+    pub object: String,
+    pub payload: String,
+}
+
+#[derive(Queryable)]
+pub struct Closing {
+    pub id: i32,
+    pub ledger_id: i32,
+    pub closed_before: chrono::NaiveDate,
+    pub created_time: chrono::NaiveDateTime,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Queryable)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub password_hash: String,
+}
+
+#[derive(Queryable)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: Vec<u8>,
+    pub created_time: chrono::NaiveDateTime,
+    pub last_used_time: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Queryable)]
 pub struct TxItem {
     pub account: String,
+    pub currency: String,
     pub value: Rational,
 }
 
 use crate::{
+    audit::{Action, Source},
+    auth::{Access, Role},
+    budgets::{BudgetTarget, Period},
     rational::Rational,
-    schema::{credits, debits, txs},
+    schedule::Schedule,
+    schema::{
+        api_token_ledgers, api_tokens, attachments, audit_log, budgets, closings, credits, debits,
+        exchange_rates, ledger_members, ledgers, post_template_credits, post_template_debits,
+        post_templates, receipt_item_consumers, receipt_items, recurring_credits, recurring_debits,
+        recurring_txs, sessions, txs, users,
+    },
 };
 
+#[derive(Insertable)]
+#[diesel(table_name = ledgers)]
+pub struct NewLedger<'a> {
+    pub slug: &'a str,
+    pub name: &'a str,
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = txs)]
 pub struct NewTx<'a> {
+    pub ledger_id: i32,
     pub tx_time: chrono::NaiveDateTime,
     pub rev_time: chrono::NaiveDateTime,
     pub description: &'a str,
+    pub rev_user_id: Option<i32>,
+    pub recurring_tx_id: Option<i32>,
+    pub closing_id: Option<i32>,
 }
 
 #[derive(Insertable)]
-#[diesel(table_name = txs)]
-pub struct NewTxWithId<'a> {
-    pub id: i32,
-    pub tx_time: chrono::NaiveDateTime,
-    pub rev_time: chrono::NaiveDateTime,
-    pub description: &'a str,
+#[diesel(table_name = users)]
+pub struct NewUser<'a> {
+    pub username: &'a str,
+    pub password_hash: &'a str,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = sessions)]
+pub struct NewSession {
+    pub token_hash: Vec<u8>,
+    pub user_id: i32,
+    pub created_time: chrono::NaiveDateTime,
+    pub expiry_time: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ledger_members)]
+pub struct NewLedgerMember {
+    pub ledger_id: i32,
+    pub user_id: i32,
+    pub role: Role,
 }
 
 #[derive(Insertable)]
@@ -41,6 +174,7 @@ pub struct NewTxWithId<'a> {
 pub struct NewCredit<'a> {
     pub tx_id: i32,
     pub account: &'a str,
+    pub currency: &'a str,
     pub value: Rational,
 }
 
@@ -49,5 +183,145 @@ pub struct NewCredit<'a> {
 pub struct NewDebit<'a> {
     pub tx_id: i32,
     pub account: &'a str,
+    pub currency: &'a str,
     pub value: Rational,
 }
+
+#[derive(Insertable)]
+#[diesel(table_name = exchange_rates)]
+pub struct NewExchangeRate<'a> {
+    pub ledger_id: i32,
+    pub currency: &'a str,
+    pub date: chrono::NaiveDate,
+    pub rate: Rational,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = recurring_txs)]
+pub struct NewRecurringTx<'a> {
+    pub ledger_id: i32,
+    pub description: &'a str,
+    pub schedule: Schedule,
+    pub start_date: chrono::NaiveDate,
+    pub end_date: Option<chrono::NaiveDate>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = recurring_credits)]
+pub struct NewRecurringCredit<'a> {
+    pub recurring_tx_id: i32,
+    pub account: &'a str,
+    pub currency: &'a str,
+    pub value: Rational,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = recurring_debits)]
+pub struct NewRecurringDebit<'a> {
+    pub recurring_tx_id: i32,
+    pub account: &'a str,
+    pub currency: &'a str,
+    pub value: Rational,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = post_templates)]
+pub struct NewPostTemplate<'a> {
+    pub ledger_id: i32,
+    pub name: &'a str,
+    pub description: &'a str,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = post_template_credits)]
+pub struct NewPostTemplateCredit<'a> {
+    pub post_template_id: i32,
+    pub account: &'a str,
+    pub share: Rational,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = post_template_debits)]
+pub struct NewPostTemplateDebit<'a> {
+    pub post_template_id: i32,
+    pub account: &'a str,
+    pub share: Rational,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_tokens)]
+pub struct NewApiToken<'a> {
+    pub user_id: i32,
+    pub name: &'a str,
+    pub token_hash: Vec<u8>,
+    pub created_time: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_token_ledgers)]
+pub struct NewApiTokenLedger {
+    pub token_id: i32,
+    pub ledger_id: i32,
+    pub access: Access,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = attachments)]
+pub struct NewAttachment<'a> {
+    pub tx_id: i32,
+    pub filename: &'a str,
+    pub content_type: &'a str,
+    pub size: i32,
+    pub data: &'a [u8],
+    pub created_time: chrono::NaiveDateTime,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = receipt_items)]
+pub struct NewReceiptItem<'a> {
+    pub tx_id: i32,
+    pub position: i32,
+    pub description: &'a str,
+    pub value: Rational,
+    pub shared: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = receipt_item_consumers)]
+pub struct NewReceiptItemConsumer<'a> {
+    pub item_id: i32,
+    pub account: &'a str,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = budgets)]
+pub struct NewBudget<'a> {
+    pub ledger_id: i32,
+    pub target: BudgetTarget,
+    pub name: &'a str,
+    pub currency: &'a str,
+    pub amount: Rational,
+    pub period: Period,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = closings)]
+pub struct NewClosing {
+    pub ledger_id: i32,
+    pub closed_before: chrono::NaiveDate,
+    pub created_time: chrono::NaiveDateTime,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct NewAuditEntry<'a> {
+    pub time: chrono::NaiveDateTime,
+    pub ledger_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub source: Source,
+    pub action: Action,
+    pub object: &'a str,
+    pub payload: String,
+}
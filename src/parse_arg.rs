@@ -1,7 +1,6 @@
 // this belongs with bin/add-transaction.rs, but needed to be extracted to run doctests
 
-use std::str::FromStr;
-
+use crate::amount::parse_amount;
 use crate::rational::Rational;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -10,6 +9,9 @@ pub enum EntryType {
     Debit,
 }
 
+/// Splits an argument like "jh+5/2" into the entry type, account and amount.
+/// The amount may be anything `parse_amount` accepts.
+///
 /// # Examples
 ///
 /// ```
@@ -17,27 +19,31 @@ pub enum EntryType {
 /// assert_eq!(parse_arg("test"), None);
 /// assert_eq!(parse_arg("jh+2"), Some((EntryType::Credit, "jh", 2u32.into())));
 /// assert_eq!(parse_arg("xyz-5/3"), Some((EntryType::Debit, "xyz", Rational::new(5u32, 3u32))));
+/// assert_eq!(parse_arg("jh+12.50"), Some((EntryType::Credit, "jh", Rational::new(25u32, 2u32))));
+/// assert_eq!(parse_arg("jh+3 1/2"), Some((EntryType::Credit, "jh", Rational::new(7u32, 2u32))));
+/// assert_eq!(parse_arg("jh+120/3+15"), Some((EntryType::Credit, "jh", 55u32.into())));
 /// assert_eq!(parse_arg("xyz--5/3"), None);
 /// assert_eq!(parse_arg("xyz-+5/3"), None);
 /// assert_eq!(parse_arg("xyz+-5/3"), None);
 /// assert_eq!(parse_arg("xyz++5/3"), None);
 /// ```
-pub fn parse_arg<'a>(arg: &'a str) -> Option<(EntryType, &'a str, Rational)> {
-    let result = match (arg.find('+'), arg.find('-')) {
-        (Some(index), None) => Some((EntryType::Credit, index)),
-        (None, Some(index)) => Some((EntryType::Debit, index)),
-        _ => None,
+pub fn parse_arg(arg: &str) -> Option<(EntryType, &str, Rational)> {
+    // The first sign separates the account from the amount, any later ones
+    // belong to the amount
+    let index = arg.find(['+', '-'])?;
+    let (account, rest) = arg.split_at(index);
+    let (sign, amount) = rest.split_at(1);
+    let entry_type = match sign {
+        "+" => EntryType::Credit,
+        _ => EntryType::Debit,
     };
-    if let Some((entry_type, index)) = result {
-        let (account, rest) = arg.split_at(index);
-        let (sign, amount) = rest.split_at(1);
-        if amount.find(sign) != None {
-            // multiple signs
-            return None;
-        }
-        return Rational::from_str(amount)
-            .ok()
-            .and_then(|amount| Some((entry_type, account, amount)));
+
+    // A sign at the start of the amount would be ambiguous
+    if amount.starts_with(['+', '-']) {
+        return None;
     }
-    return None;
+
+    parse_amount(amount)
+        .ok()
+        .map(|amount| (entry_type, account, amount))
 }
@@ -0,0 +1,452 @@
+//! Templates for posts that are entered again and again, such as the weekly
+//! groceries split among everyone in the house. A template keeps the
+//! accounts and how large a share each of them has, and the shares are
+//! scaled to the total of each post.
+
+use std::collections::BTreeMap;
+
+use diesel::prelude::*;
+use num::rational::Ratio;
+use num::BigUint;
+use thiserror::Error;
+
+use crate::audit::{self, Action, Actor};
+use crate::ledger::Postings;
+use crate::models::{NewPostTemplate, NewPostTemplateCredit, NewPostTemplateDebit, PostTemplate};
+use crate::rational::Rational;
+use crate::schema::{post_template_credits, post_template_debits, post_templates};
+use crate::DbConnection;
+
+/// Shares by account
+pub type Shares = BTreeMap<String, Rational>;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum InvalidTemplate {
+    #[error("missing template name")]
+    MissingName,
+    #[error("missing description")]
+    MissingDescription,
+    #[error("empty account name")]
+    EmptyAccountName,
+    #[error("a template needs a share for at least one debit and one credit account")]
+    MissingShares,
+    #[error("a template with that name already exists")]
+    DuplicateName,
+}
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error(transparent)]
+    Invalid(#[from] InvalidTemplate),
+    #[error(transparent)]
+    Db(#[from] diesel::result::Error),
+}
+
+/// A valid template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    name: String,
+    description: String,
+    debits: Shares,
+    credits: Shares,
+}
+
+impl Template {
+    /// Validates a template. Accounts without a share are left out.
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        debits: Shares,
+        credits: Shares,
+    ) -> Result<Self, InvalidTemplate> {
+        let name = name.into().trim().to_owned();
+        let description = description.into();
+        let nonzero = |shares: Shares| -> Shares {
+            shares
+                .into_iter()
+                .filter(|(_, share)| !share.is_zero())
+                .collect()
+        };
+        let debits = nonzero(debits);
+        let credits = nonzero(credits);
+
+        if name.is_empty() {
+            return Err(InvalidTemplate::MissingName);
+        }
+        if description.is_empty() {
+            return Err(InvalidTemplate::MissingDescription);
+        }
+        if debits.is_empty() || credits.is_empty() {
+            return Err(InvalidTemplate::MissingShares);
+        }
+        if debits.keys().chain(credits.keys()).any(String::is_empty) {
+            return Err(InvalidTemplate::EmptyAccountName);
+        }
+
+        Ok(Template {
+            name,
+            description,
+            debits,
+            credits,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn debits(&self) -> &Shares {
+        &self.debits
+    }
+
+    pub fn credits(&self) -> &Shares {
+        &self.credits
+    }
+
+    /// The debits and credits of a post of `total` in `currency`. Both sides
+    /// add up to exactly `total`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sharebill::post_templates::{Shares, Template};
+    /// # use sharebill::rational::Rational;
+    /// let shares = |accounts: &[(&str, u32)]| -> Shares {
+    ///     accounts
+    ///         .iter()
+    ///         .map(|&(account, share)| (account.to_owned(), share.into()))
+    ///         .collect()
+    /// };
+    /// let template = Template::new(
+    ///     "groceries",
+    ///     "Weekly groceries",
+    ///     shares(&[("alice", 1), ("bob", 1), ("carol", 2)]),
+    ///     shares(&[("alice", 1)]),
+    /// )
+    /// .unwrap();
+    ///
+    /// let (debits, credits) = template.postings(&100u32.into(), "NOK");
+    /// let key = |account: &str| (account.to_owned(), "NOK".to_owned());
+    /// assert_eq!(debits[&key("bob")], 25u32.into());
+    /// assert_eq!(debits[&key("carol")], 50u32.into());
+    /// assert_eq!(credits[&key("alice")], 100u32.into());
+    ///
+    /// let (debits, _) = template.postings(&10u32.into(), "NOK");
+    /// assert_eq!(debits[&key("bob")], Rational::new(5u32, 2u32));
+    /// ```
+    pub fn postings(&self, total: &Rational, currency: &str) -> (Postings, Postings) {
+        (
+            scale(&self.debits, total, currency),
+            scale(&self.credits, total, currency),
+        )
+    }
+}
+
+/// Splits `total` in proportion to the shares, which must not all be zero
+fn scale(shares: &Shares, total: &Rational, currency: &str) -> Postings {
+    let total = total.clone().into_inner();
+    let sum = shares
+        .values()
+        .map(|share| share.clone().into_inner())
+        .sum::<Ratio<BigUint>>();
+
+    shares
+        .iter()
+        .map(|(account, share)| {
+            let value = &total * share.clone().into_inner() / &sum;
+            (
+                (account.clone(), currency.to_owned()),
+                Rational::from(value),
+            )
+        })
+        .collect()
+}
+
+/// The templates of a ledger, by name
+pub fn ledger_templates(conn: &mut DbConnection, ledger_id: i32) -> QueryResult<Vec<PostTemplate>> {
+    post_templates::table
+        .filter(post_templates::ledger_id.eq(ledger_id))
+        .order(post_templates::name)
+        .load(conn)
+}
+
+/// A template of a ledger, by id
+pub fn load_template(conn: &mut DbConnection, ledger_id: i32, id: i32) -> QueryResult<Template> {
+    let template = post_templates::table
+        .find(id)
+        .filter(post_templates::ledger_id.eq(ledger_id))
+        .first::<PostTemplate>(conn)?;
+
+    let debits = post_template_debits::table
+        .filter(post_template_debits::post_template_id.eq(id))
+        .select((post_template_debits::account, post_template_debits::share))
+        .load::<(String, Rational)>(conn)?;
+    let credits = post_template_credits::table
+        .filter(post_template_credits::post_template_id.eq(id))
+        .select((post_template_credits::account, post_template_credits::share))
+        .load::<(String, Rational)>(conn)?;
+
+    Ok(Template {
+        name: template.name,
+        description: template.description,
+        debits: debits.into_iter().collect(),
+        credits: credits.into_iter().collect(),
+    })
+}
+
+/// A template of a ledger, by name
+pub fn find_template(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    name: &str,
+) -> QueryResult<Option<Template>> {
+    let id = post_templates::table
+        .filter(post_templates::ledger_id.eq(ledger_id))
+        .filter(post_templates::name.eq(name.trim()))
+        .select(post_templates::id)
+        .first::<i32>(conn)
+        .optional()?;
+
+    id.map(|id| load_template(conn, ledger_id, id)).transpose()
+}
+
+fn insert_shares(conn: &mut DbConnection, id: i32, template: &Template) -> QueryResult<()> {
+    diesel::insert_into(post_template_credits::table)
+        .values(
+            template
+                .credits
+                .iter()
+                .map(|(account, share)| NewPostTemplateCredit {
+                    post_template_id: id,
+                    account,
+                    share: share.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)?;
+
+    diesel::insert_into(post_template_debits::table)
+        .values(
+            template
+                .debits
+                .iter()
+                .map(|(account, share)| NewPostTemplateDebit {
+                    post_template_id: id,
+                    account,
+                    share: share.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)?;
+
+    Ok(())
+}
+
+fn delete_shares(conn: &mut DbConnection, id: i32) -> QueryResult<()> {
+    diesel::delete(
+        post_template_credits::table.filter(post_template_credits::post_template_id.eq(id)),
+    )
+    .execute(conn)?;
+    diesel::delete(
+        post_template_debits::table.filter(post_template_debits::post_template_id.eq(id)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// A template as it is saved, for the audit log
+fn template_json(id: i32, template: &Template) -> serde_json::Value {
+    let shares = |shares: &Shares| -> serde_json::Value {
+        shares
+            .iter()
+            .map(|(account, share)| serde_json::json!({ "account": account, "share": share }))
+            .collect()
+    };
+    serde_json::json!({
+        "id": id,
+        "name": template.name,
+        "what": template.description,
+        "debits": shares(&template.debits),
+        "credits": shares(&template.credits),
+    })
+}
+
+/// Saves a template, as a new one if `id` is `None`, and returns its id
+pub fn save_template(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    id: Option<i32>,
+    template: &Template,
+    actor: Actor,
+) -> Result<i32, TemplateError> {
+    conn.transaction(|conn| {
+        let same_name = post_templates::table
+            .filter(post_templates::ledger_id.eq(ledger_id))
+            .filter(post_templates::name.eq(&template.name))
+            .select(post_templates::id)
+            .first::<i32>(conn)
+            .optional()?;
+        if same_name.is_some() && same_name != id {
+            return Err(InvalidTemplate::DuplicateName.into());
+        }
+
+        let (id, action) = match id {
+            Some(id) => {
+                let updated = diesel::update(
+                    post_templates::table
+                        .find(id)
+                        .filter(post_templates::ledger_id.eq(ledger_id)),
+                )
+                .set((
+                    post_templates::name.eq(&template.name),
+                    post_templates::description.eq(&template.description),
+                ))
+                .execute(conn)?;
+                if updated == 0 {
+                    return Err(diesel::result::Error::NotFound.into());
+                }
+                delete_shares(conn, id)?;
+                (id, Action::Edit)
+            }
+            None => {
+                let id = diesel::insert_into(post_templates::table)
+                    .values(&NewPostTemplate {
+                        ledger_id,
+                        name: &template.name,
+                        description: &template.description,
+                    })
+                    .returning(post_templates::id)
+                    .get_result::<i32>(conn)?;
+                (id, Action::Create)
+            }
+        };
+        insert_shares(conn, id, template)?;
+
+        audit::record(
+            conn,
+            actor,
+            Some(ledger_id),
+            action,
+            "template",
+            template_json(id, template),
+        )?;
+
+        Ok(id)
+    })
+}
+
+pub fn delete_template(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    id: i32,
+    actor: Actor,
+) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        post_templates::table
+            .find(id)
+            .filter(post_templates::ledger_id.eq(ledger_id))
+            .select(post_templates::id)
+            .first::<i32>(conn)?;
+
+        delete_shares(conn, id)?;
+        diesel::delete(post_templates::table.find(id)).execute(conn)?;
+
+        audit::record(
+            conn,
+            actor,
+            Some(ledger_id),
+            Action::Delete,
+            "template",
+            serde_json::json!({ "id": id }),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn shares(accounts: &[(&str, u32)]) -> Shares {
+        accounts
+            .iter()
+            .map(|&(account, share)| (account.to_owned(), share.into()))
+            .collect()
+    }
+
+    #[test]
+    fn validates_templates() {
+        let valid = |name: &str, debits: &[(&str, u32)], credits: &[(&str, u32)]| {
+            Template::new(name, "Groceries", shares(debits), shares(credits))
+        };
+
+        assert_eq!(
+            valid(" ", &[("bob", 1)], &[("alice", 1)]),
+            Err(InvalidTemplate::MissingName)
+        );
+        assert_eq!(
+            valid("groceries", &[("bob", 0)], &[("alice", 1)]),
+            Err(InvalidTemplate::MissingShares)
+        );
+        assert_eq!(
+            valid("groceries", &[("", 1)], &[("alice", 1)]),
+            Err(InvalidTemplate::EmptyAccountName)
+        );
+
+        let template = valid("groceries", &[("bob", 1), ("carol", 0)], &[("alice", 1)]).unwrap();
+        assert_eq!(template.debits(), &shares(&[("bob", 1)]));
+    }
+
+    #[test]
+    fn saves_finds_and_deletes_templates() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        let template = Template::new(
+            "groceries",
+            "Weekly groceries",
+            shares(&[("alice", 1), ("bob", 1), ("carol", 1)]),
+            shares(&[("alice", 1)]),
+        )?;
+
+        let id = save_template(&mut conn, 1, None, &template, Actor::cli())?;
+        assert_eq!(
+            find_template(&mut conn, 1, "groceries")?,
+            Some(template.clone())
+        );
+        assert!(matches!(
+            save_template(&mut conn, 1, None, &template, Actor::cli()),
+            Err(TemplateError::Invalid(InvalidTemplate::DuplicateName))
+        ));
+
+        // A third each, which adds up to the total exactly
+        let (debits, credits) = template.postings(&100u32.into(), "NOK");
+        assert_eq!(
+            debits.values().sum::<Rational>(),
+            credits.values().sum::<Rational>()
+        );
+        assert_eq!(
+            debits[&("bob".to_owned(), "NOK".to_owned())],
+            Rational::new(100u32, 3u32)
+        );
+
+        let renamed = Template::new(
+            "food",
+            "Weekly groceries",
+            shares(&[("bob", 1)]),
+            shares(&[("alice", 1)]),
+        )?;
+        assert_eq!(
+            save_template(&mut conn, 1, Some(id), &renamed, Actor::cli())?,
+            id
+        );
+        assert_eq!(find_template(&mut conn, 1, "groceries")?, None);
+        assert_eq!(load_template(&mut conn, 1, id)?, renamed);
+
+        delete_template(&mut conn, 1, id, Actor::cli())?;
+        assert!(ledger_templates(&mut conn, 1)?.is_empty());
+
+        Ok(())
+    }
+}
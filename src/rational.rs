@@ -3,13 +3,13 @@ use std::iter::Sum;
 use diesel::deserialize::{self, FromSql};
 use diesel::prelude::*;
 use diesel::serialize::{self, Output, ToSql};
-use diesel::sql_types::Binary;
+use diesel::sql_types::{Binary, Text};
 use diesel::sqlite::{Sqlite, SqliteAggregateFunction, SqliteValue};
 use diesel::{AsExpression, FromSqlRow};
 use num::rational::Ratio;
-use num::{BigUint, Zero};
+use num::{BigInt, BigRational, BigUint, Integer as _, One, ToPrimitive, Zero};
 
-#[derive(Default, PartialEq, Eq, Debug, AsExpression, FromSqlRow, Clone)]
+#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Debug, AsExpression, FromSqlRow, Clone)]
 #[diesel(sql_type = Binary)]
 pub struct Rational(Ratio<BigUint>);
 
@@ -18,20 +18,89 @@ sql_function! {
     fn sum_rat(x: Binary) -> Binary;
 }
 
-#[derive(Default)]
+sql_function! {
+    /// Whether two rationals are equal, regardless of how they are encoded
+    fn rat_eq(x: Binary, y: Binary) -> Bool;
+}
+
+sql_function! {
+    /// A stored rational rewritten in the current stored form. Values that
+    /// cannot be read are returned as they are.
+    fn rat_canonical(x: Binary) -> Binary;
+}
+
+sql_function! {
+    /// -1, 0 or 1 as x is less than, equal to or greater than y
+    fn rat_cmp(x: Binary, y: Binary) -> Integer;
+}
+
+sql_function! {
+    /// x - y, or NULL when y is larger, as rationals are never negative
+    fn rat_sub(x: Binary, y: Binary) -> Nullable<Binary>;
+}
+
+sql_function! {
+    /// The exact value as text, such as 3/14 or 5
+    fn rat_to_text(x: Binary) -> Text;
+}
+
+sql_function! {
+    /// The nearest floating point number, for sorting and display
+    fn rat_to_real(x: Binary) -> Double;
+}
+
+sql_function! {
+    /// A plain number such as 12.50 or 3/14, as read by
+    /// [`crate::amount::parse_number`], or NULL if it cannot be read
+    fn rat_from_text(x: Text) -> Nullable<Binary>;
+}
+
+/// Sums in machine integers for as long as the sum fits, and only then in
+/// big integers
 pub struct SumRat {
-    sum: Rational,
+    // Boxed, as SQLite only aligns the state of an aggregate to 8 bytes and
+    // u128 needs 16
+    small: Box<(u128, u128)>,
+    big: Rational,
+}
+
+impl Default for SumRat {
+    fn default() -> Self {
+        Self {
+            small: Box::new((0, 1)),
+            big: Rational::default(),
+        }
+    }
+}
+
+/// The sum of two reduced fractions, unless it overflows
+fn checked_add_small((a, b): (u128, u128), (c, d): (u128, u128)) -> Option<(u128, u128)> {
+    let g = b.gcd(&d);
+    let denom = (b / g).checked_mul(d)?;
+    let numer = a.checked_mul(d / g)?.checked_add(c.checked_mul(b / g)?)?;
+    let g = numer.gcd(&denom);
+    Some((numer / g, denom / g))
 }
 
 impl SqliteAggregateFunction<Rational> for SumRat {
     type Output = Rational;
 
     fn step(&mut self, expr: Rational) {
-        self.sum += expr;
+        let small = expr.0.numer().to_u64().zip(expr.0.denom().to_u64());
+        let sum = small.and_then(|(numer, denom)| {
+            checked_add_small(*self.small, (numer.into(), denom.into()))
+        });
+
+        match sum {
+            Some(sum) => *self.small = sum,
+            None => self.big += expr,
+        }
     }
 
     fn finalize(aggregator: Option<Self>) -> Self::Output {
-        aggregator.map(|a| a.sum).unwrap_or_default()
+        aggregator
+            .map(|a| a.big + Rational::new(a.small.0, a.small.1))
+            .unwrap_or_default()
     }
 }
 
@@ -47,13 +116,132 @@ impl Rational {
     pub fn is_zero(&self) -> bool {
         self.0.is_zero()
     }
+
+    pub fn checked_sub(&self, rhs: &Rational) -> Option<Rational> {
+        (self >= rhs).then(|| Rational(&self.0 - &rhs.0))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64().unwrap_or(f64::NAN)
+    }
+
+    pub fn to_big_rational(&self) -> BigRational {
+        BigRational::new(
+            BigInt::from(self.0.numer().clone()),
+            BigInt::from(self.0.denom().clone()),
+        )
+    }
+
+    /// The stored form: [`FORMAT_V1`], followed by the numerator and the
+    /// denominator as unsigned LEB128, in lowest terms
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![FORMAT_V1];
+        write_varint(&mut bytes, self.0.numer());
+        write_varint(&mut bytes, self.0.denom());
+        bytes
+    }
+
+    /// Reads the stored form. Only what [`Rational::to_bytes`] writes is
+    /// accepted, apart from values in the legacy form.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match *bytes.first()? {
+            FORMAT_V1 => Self::from_v1_bytes(&bytes[1..]),
+            FORMAT_RESERVED.. => None,
+            _ => Self::from_legacy_bytes(bytes),
+        }
+    }
+
+    fn from_v1_bytes(bytes: &[u8]) -> Option<Self> {
+        let (numer, rest) = read_varint(bytes)?;
+        let (denom, rest) = read_varint(rest)?;
+
+        if !rest.is_empty() || denom.is_zero() || !numer.gcd(&denom).is_one() {
+            return None;
+        }
+
+        Some(Rational(Ratio::new_raw(numer, denom)))
+    }
+
+    /// The legacy form: the length of the numerator as four little-endian
+    /// bytes, followed by the numerator and the denominator, both
+    /// little-endian. Bytes it would not have been written as, such as
+    /// trailing zeros or an unreduced fraction, are accepted too.
+    fn from_legacy_bytes(bytes: &[u8]) -> Option<Self> {
+        let (header, values) = get_split_at(bytes, 4)?;
+        let numer_len = u32::from_le_bytes(header.try_into().unwrap()) as usize;
+
+        let (numer, denom) = get_split_at(values, numer_len)?;
+
+        let numer = BigUint::from_bytes_le(numer);
+        let denom = BigUint::from_bytes_le(denom);
+
+        if denom.is_zero() {
+            return None;
+        }
+
+        Some(Rational(Ratio::new(numer, denom)))
+    }
+}
+
+/// The first byte of the stored form. The legacy form starts with the lowest
+/// byte of the numerator length instead, which only reaches
+/// [`FORMAT_RESERVED`] for numerators of 240 bytes or more.
+pub const FORMAT_V1: u8 = 0xf1;
+
+/// First bytes from here up are kept for versions of the stored form
+const FORMAT_RESERVED: u8 = 0xf0;
+
+/// Writes an unsigned LEB128 number: seven bits per byte, least significant
+/// first, with the high bit set on every byte but the last
+fn write_varint(bytes: &mut Vec<u8>, n: &BigUint) {
+    match n.to_u64() {
+        Some(mut n) => {
+            while n >= 0x80 {
+                bytes.push(n as u8 | 0x80);
+                n >>= 7;
+            }
+            bytes.push(n as u8);
+        }
+        None => {
+            let digits = n.to_radix_le(128);
+            let (last, rest) = digits.split_last().unwrap();
+            bytes.extend(rest.iter().map(|digit| digit | 0x80));
+            bytes.push(*last);
+        }
+    }
+}
+
+/// Reads an unsigned LEB128 number from the start of the bytes, and returns
+/// it with the bytes after it. Only the shortest encoding is accepted.
+fn read_varint(bytes: &[u8]) -> Option<(BigUint, &[u8])> {
+    let len = bytes.iter().position(|byte| byte & 0x80 == 0)? + 1;
+    let (digits, rest) = bytes.split_at(len);
+
+    if len > 1 && digits[len - 1] == 0 {
+        return None;
+    }
+
+    // Nine digits are at most 63 bits, which covers every everyday amount
+    let n = if len <= 9 {
+        BigUint::from(
+            digits
+                .iter()
+                .rev()
+                .fold(0u64, |n, digit| n << 7 | u64::from(digit & 0x7f)),
+        )
+    } else {
+        let digits = digits.iter().map(|digit| digit & 0x7f).collect::<Vec<_>>();
+        BigUint::from_radix_le(&digits, 128)?
+    };
+
+    Some((n, rest))
 }
 
 impl std::str::FromStr for Rational {
     type Err = <Ratio<BigUint> as std::str::FromStr>::Err;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ratio::<BigUint>::from_str(s).map(|r| Rational(r))
+        Ratio::<BigUint>::from_str(s).map(Rational)
     }
 }
 
@@ -85,6 +273,12 @@ impl From<u32> for Rational {
     }
 }
 
+impl From<Ratio<BigUint>> for Rational {
+    fn from(value: Ratio<BigUint>) -> Self {
+        Self(value)
+    }
+}
+
 impl std::fmt::Display for Rational {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
@@ -100,15 +294,7 @@ impl<'a> Sum<&'a Rational> for Rational {
 
 impl ToSql<Binary, Sqlite> for Rational {
     fn to_sql<'c>(&'c self, out: &mut Output<'c, '_, Sqlite>) -> serialize::Result {
-        let numer = self.0.numer().to_bytes_le();
-        let denom = self.0.denom().to_bytes_le();
-
-        let mut bytes: Vec<u8> = Vec::new();
-        bytes.extend_from_slice(&(numer.len() as u32).to_le_bytes());
-        bytes.extend_from_slice(&numer);
-        bytes.extend_from_slice(&denom);
-
-        out.set_value(bytes);
+        out.set_value(self.to_bytes());
         Ok(serialize::IsNull::No)
     }
 }
@@ -140,19 +326,22 @@ impl FromSql<Binary, Sqlite> for Rational {
         let bytes = <*const [u8] as FromSql<Binary, Sqlite>>::from_sql(bytes)?;
         let bytes: &[u8] = unsafe { &*bytes };
 
-        let (header, values) = get_split_at(bytes, 4).ok_or(ParseError)?;
-        let numer_len = u32::from_le_bytes(header.try_into().unwrap()) as usize;
-
-        let (numer, denom) = get_split_at(values, numer_len).ok_or(ParseError)?;
-
-        let numer = BigUint::from_bytes_le(numer);
-        let denom = BigUint::from_bytes_le(denom);
+        Ok(Rational::from_bytes(bytes).ok_or(ParseError)?)
+    }
+}
 
-        if denom.is_zero() {
-            return Err(ParseError.into());
-        }
+#[cfg(feature = "postgres")]
+impl ToSql<Binary, diesel::pg::Pg> for Rational {
+    fn to_sql<'c>(&'c self, out: &mut Output<'c, '_, diesel::pg::Pg>) -> serialize::Result {
+        std::io::Write::write_all(out, &self.to_bytes())?;
+        Ok(serialize::IsNull::No)
+    }
+}
 
-        Ok(Rational(Ratio::new(numer, denom)))
+#[cfg(feature = "postgres")]
+impl FromSql<Binary, diesel::pg::Pg> for Rational {
+    fn from_sql(value: diesel::pg::PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(Rational::from_bytes(value.as_bytes()).ok_or(ParseError)?)
     }
 }
 
@@ -162,14 +351,14 @@ impl<'de> serde::de::Visitor<'de> for RationalVisitor {
     type Value = Rational;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(formatter, "rational number")
+        write!(formatter, "amount, such as 12.50, 3 1/2 or 120/3 + 15")
     }
 
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        v.parse()
+        crate::amount::parse_amount(v)
             .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(v), &self))
     }
 }
@@ -183,6 +372,15 @@ impl<'de> serde::Deserialize<'de> for Rational {
     }
 }
 
+impl serde::Serialize for Rational {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::error::Error;
@@ -254,9 +452,36 @@ mod test {
         let res = sql_query("SELECT X'020000000100010000' as value").load::<Row>(&mut conn);
         assert!(res.is_ok());
 
+        // 1/1
+        let res = sql_query("SELECT X'F10101' as value").load::<Row>(&mut conn);
+        assert!(res.is_ok());
+
+        // Trailing bytes, an overlong numerator, an unreduced fraction, a
+        // zero denominator, a missing denominator and an unknown version
+        for value in ["F1010100", "F1810001", "F10204", "F10100", "F101", "F20101"] {
+            let res = sql_query(format!("SELECT X'{value}' as value")).load::<Row>(&mut conn);
+            assert!(res.is_err(), "{value}");
+        }
+
         Ok(())
     }
 
+    #[test]
+    fn canonical_bytes() {
+        assert_eq!(Rational::from(0u32).to_bytes(), [0xf1, 0x00, 0x01]);
+        assert_eq!(Rational::new(3u32, 14u32).to_bytes(), [0xf1, 0x03, 0x0e]);
+        assert_eq!(Rational::from(300u32).to_bytes(), [0xf1, 0xac, 0x02, 0x01]);
+
+        let big = Rational::new(BigUint::from(u64::MAX) * 1000u32, 7u32);
+        assert_eq!(Rational::from_bytes(&big.to_bytes()), Some(big));
+
+        // The legacy form of 10/4 reads as 5/2, and is written anew
+        let legacy = [0x01, 0x00, 0x00, 0x00, 0x0a, 0x04];
+        let value = Rational::from_bytes(&legacy).unwrap();
+        assert_eq!(value, Rational::new(5u32, 2u32));
+        assert_eq!(value.to_bytes(), [0xf1, 0x05, 0x02]);
+    }
+
     #[test]
     fn sum_rat() -> Result<(), Box<dyn Error>> {
         let mut conn = SqliteConnection::establish(":memory:")?;
@@ -281,6 +506,21 @@ mod test {
             res.as_slice()
         );
 
+        // Sums that outgrow machine integers carry on in big integers
+        let values = [
+            Rational::new(u64::MAX, 1u32),
+            Rational::new(1u32, u64::MAX),
+            Rational::new(1u32, u64::MAX - 2),
+        ];
+        let res = sql_query("WITH t(x) AS (VALUES (?),(?),(?)) SELECT sum_rat(x) as value FROM t")
+            .bind::<Binary, _>(values[0].clone())
+            .bind::<Binary, _>(values[1].clone())
+            .bind::<Binary, _>(values[2].clone())
+            .load::<Row>(&mut conn)
+            .unwrap();
+
+        assert_eq!(res[0].value, values.iter().sum::<Rational>());
+
         Ok(())
     }
 }
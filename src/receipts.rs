@@ -0,0 +1,315 @@
+//! Itemized receipts, where each line item is split among the people who had
+//! it, and tax and tip are split in proportion to what each person had
+
+use std::collections::BTreeMap;
+
+use diesel::prelude::*;
+use num::rational::Ratio;
+use num::{BigUint, Zero};
+use thiserror::Error;
+
+use crate::models::{NewReceiptItem, NewReceiptItemConsumer};
+use crate::rational::Rational;
+use crate::schema::{receipt_item_consumers, receipt_items};
+use crate::DbConnection;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineItem {
+    pub description: String,
+    pub amount: Rational,
+    /// The accounts that share this item equally
+    pub consumers: Vec<String>,
+}
+
+/// Tax, tip or another amount shared by everyone on the receipt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedLine {
+    pub description: String,
+    pub amount: Rational,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Receipt {
+    pub items: Vec<LineItem>,
+    pub shared: Vec<SharedLine>,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ItemizeError {
+    #[error("an itemized receipt needs at least one line item")]
+    NoItems,
+    #[error("every line item must be had by at least one person")]
+    NoConsumers,
+    #[error("shared lines cannot be split when the line items add up to zero")]
+    ZeroSubtotal,
+}
+
+impl Receipt {
+    /// The sum of all line items and shared lines
+    pub fn total(&self) -> Rational {
+        self.items
+            .iter()
+            .map(|item| &item.amount)
+            .chain(self.shared.iter().map(|line| &line.amount))
+            .sum()
+    }
+
+    /// The exact share of each person, which add up to the total. People
+    /// whose share is zero are left out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sharebill::receipts::{LineItem, Receipt, SharedLine};
+    /// let receipt = Receipt {
+    ///     items: vec![
+    ///         LineItem {
+    ///             description: "Pizza".to_owned(),
+    ///             amount: 20u32.into(),
+    ///             consumers: vec!["alice".to_owned()],
+    ///         },
+    ///         LineItem {
+    ///             description: "Wine".to_owned(),
+    ///             amount: 20u32.into(),
+    ///             consumers: vec!["alice".to_owned(), "bob".to_owned()],
+    ///         },
+    ///     ],
+    ///     shared: vec![SharedLine {
+    ///         description: "Tip".to_owned(),
+    ///         amount: 4u32.into(),
+    ///     }],
+    /// };
+    /// let shares = receipt.split().unwrap();
+    /// assert_eq!(shares["alice"], 33u32.into());
+    /// assert_eq!(shares["bob"], 11u32.into());
+    /// ```
+    pub fn split(&self) -> Result<BTreeMap<String, Rational>, ItemizeError> {
+        if self.items.is_empty() {
+            return Err(ItemizeError::NoItems);
+        }
+
+        let mut shares = BTreeMap::<String, Ratio<BigUint>>::new();
+        for item in &self.items {
+            if item.consumers.is_empty() {
+                return Err(ItemizeError::NoConsumers);
+            }
+            let share = item.amount.clone().into_inner() / BigUint::from(item.consumers.len());
+            for consumer in &item.consumers {
+                *shares.entry(consumer.clone()).or_default() += &share;
+            }
+        }
+
+        let shared = self
+            .shared
+            .iter()
+            .map(|line| line.amount.clone().into_inner())
+            .sum::<Ratio<BigUint>>();
+        if !shared.is_zero() {
+            let subtotal = shares.values().sum::<Ratio<BigUint>>();
+            if subtotal.is_zero() {
+                return Err(ItemizeError::ZeroSubtotal);
+            }
+            for share in shares.values_mut() {
+                let extra = &shared * &*share / &subtotal;
+                *share += extra;
+            }
+        }
+
+        Ok(shares
+            .into_iter()
+            .filter(|(_, share)| !share.is_zero())
+            .map(|(account, share)| (account, Rational::from(share)))
+            .collect())
+    }
+}
+
+/// Stores the itemization of a transaction, replacing any earlier one
+pub fn save_receipt(conn: &mut DbConnection, tx_id: i32, receipt: &Receipt) -> QueryResult<()> {
+    delete_receipt(conn, tx_id)?;
+
+    let lines = receipt
+        .items
+        .iter()
+        .map(|item| (&item.description, &item.amount, &item.consumers[..], false))
+        .chain(
+            receipt
+                .shared
+                .iter()
+                .map(|line| (&line.description, &line.amount, &[][..], true)),
+        );
+    for (position, (description, amount, consumers, shared)) in lines.enumerate() {
+        let item_id = diesel::insert_into(receipt_items::table)
+            .values(&NewReceiptItem {
+                tx_id,
+                position: position as i32,
+                description,
+                value: amount.clone(),
+                shared,
+            })
+            .returning(receipt_items::id)
+            .get_result::<i32>(conn)?;
+        diesel::insert_into(receipt_item_consumers::table)
+            .values(
+                consumers
+                    .iter()
+                    .map(|account| NewReceiptItemConsumer { item_id, account })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// The itemization of a transaction, if it has one
+pub fn load_receipt(conn: &mut DbConnection, tx_id: i32) -> QueryResult<Option<Receipt>> {
+    let lines = receipt_items::table
+        .filter(receipt_items::tx_id.eq(tx_id))
+        .order(receipt_items::position)
+        .select((
+            receipt_items::id,
+            receipt_items::description,
+            receipt_items::value,
+            receipt_items::shared,
+        ))
+        .load::<(i32, String, Rational, bool)>(conn)?;
+    if lines.is_empty() {
+        return Ok(None);
+    }
+
+    let mut receipt = Receipt::default();
+    for (id, description, amount, shared) in lines {
+        if shared {
+            receipt.shared.push(SharedLine {
+                description,
+                amount,
+            });
+        } else {
+            let consumers = receipt_item_consumers::table
+                .filter(receipt_item_consumers::item_id.eq(id))
+                .order(receipt_item_consumers::account)
+                .select(receipt_item_consumers::account)
+                .load(conn)?;
+            receipt.items.push(LineItem {
+                description,
+                amount,
+                consumers,
+            });
+        }
+    }
+    Ok(Some(receipt))
+}
+
+/// Whether a transaction has an itemization
+pub fn has_receipt(conn: &mut DbConnection, tx_id: i32) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(
+        receipt_items::table.filter(receipt_items::tx_id.eq(tx_id)),
+    ))
+    .get_result(conn)
+}
+
+/// Deletes the itemization of a transaction, which must be done before the
+/// transaction itself is deleted
+pub fn delete_receipt(conn: &mut DbConnection, tx_id: i32) -> QueryResult<()> {
+    let items = receipt_items::table
+        .filter(receipt_items::tx_id.eq(tx_id))
+        .select(receipt_items::id);
+    diesel::delete(
+        receipt_item_consumers::table.filter(receipt_item_consumers::item_id.eq_any(items)),
+    )
+    .execute(conn)?;
+    diesel::delete(receipt_items::table.filter(receipt_items::tx_id.eq(tx_id))).execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::Actor;
+    use crate::ledger::{Ledger, Postings, Transaction};
+
+    fn item(amount: Rational, consumers: &[&str]) -> LineItem {
+        LineItem {
+            description: String::new(),
+            amount,
+            consumers: consumers.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn splits_exactly() {
+        let receipt = Receipt {
+            items: vec![
+                item(Rational::new(1000u32, 100u32), &["a", "b", "c"]),
+                item(Rational::new(550u32, 100u32), &["a"]),
+                item(0u32.into(), &["d"]),
+            ],
+            shared: vec![SharedLine {
+                description: "Tip".to_owned(),
+                amount: 2u32.into(),
+            }],
+        };
+        let shares = receipt.split().unwrap();
+
+        // Thirds of the shared item cannot be written as decimals, so the
+        // shares must be exact for them to add up
+        assert_eq!(shares.keys().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert_eq!(shares["b"], Rational::new(350u32, 93u32));
+        assert_eq!(shares.values().sum::<Rational>(), receipt.total());
+        assert_eq!(receipt.total(), Rational::new(35u32, 2u32));
+    }
+
+    #[test]
+    fn invalid_receipts() {
+        assert_eq!(Receipt::default().split(), Err(ItemizeError::NoItems));
+        assert_eq!(
+            Receipt {
+                items: vec![item(1u32.into(), &[])],
+                shared: vec![],
+            }
+            .split(),
+            Err(ItemizeError::NoConsumers)
+        );
+        assert_eq!(
+            Receipt {
+                items: vec![item(0u32.into(), &["a"])],
+                shared: vec![SharedLine {
+                    description: "Tip".to_owned(),
+                    amount: 1u32.into(),
+                }],
+            }
+            .split(),
+            Err(ItemizeError::ZeroSubtotal)
+        );
+    }
+
+    #[test]
+    fn save_and_load() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+
+        let now = chrono::Utc::now().naive_utc();
+        let posting = |account: &str| {
+            Postings::from([((account.to_owned(), "NOK".to_owned()), 10u32.into())])
+        };
+        let dinner = Transaction::new(now, "Dinner", "", posting("bob"), posting("alice"))?;
+        let tx_id = Ledger::new(&mut conn, 1).insert_transaction(Actor::cli(), &dinner)?;
+
+        assert_eq!(load_receipt(&mut conn, tx_id)?, None);
+
+        let receipt = Receipt {
+            items: vec![item(3u32.into(), &["a", "b"]), item(2u32.into(), &["b"])],
+            shared: vec![SharedLine {
+                description: "Tax".to_owned(),
+                amount: 1u32.into(),
+            }],
+        };
+        save_receipt(&mut conn, tx_id, &receipt)?;
+        save_receipt(&mut conn, tx_id, &receipt)?;
+        assert_eq!(load_receipt(&mut conn, tx_id)?, Some(receipt));
+
+        delete_receipt(&mut conn, tx_id)?;
+        Ledger::new(&mut conn, 1).delete_transaction(Actor::cli(), tx_id)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Turning recurring transaction templates into ordinary transactions
+
+use chrono::NaiveDate;
+use diesel::prelude::*;
+
+use crate::audit::Actor;
+use crate::closing::closed_before;
+use crate::ledger::{Ledger, LedgerError, Postings, Transaction};
+use crate::models::{RecurringTx, TxItem};
+use crate::schema::{recurring_credits, recurring_debits, recurring_txs};
+use crate::DbConnection;
+
+/// Occurrences of `template` up to and including `today` that have not been
+/// turned into transactions yet
+pub fn due_dates(template: &RecurringTx, today: NaiveDate) -> Vec<NaiveDate> {
+    let until = match template.end_date {
+        Some(end_date) => std::cmp::min(end_date, today),
+        None => today,
+    };
+    template
+        .schedule
+        .occurrences(template.start_date, until)
+        .into_iter()
+        .filter(|date| Some(*date) > template.last_date)
+        .collect()
+}
+
+/// Creates a transaction for each due occurrence of `template`, returning how
+/// many were created.
+///
+/// Running this again, or concurrently from another process, creates nothing
+/// new: the template's `last_date` is advanced first, and only if no one else
+/// has advanced it since the template was loaded. Transactions that are later
+/// deleted by hand are not recreated, and occurrences in closed periods are
+/// skipped.
+pub fn materialize(
+    conn: &mut DbConnection,
+    template: &RecurringTx,
+    today: NaiveDate,
+    actor: Actor,
+) -> Result<usize, LedgerError> {
+    let dates = due_dates(template, today);
+    let Some(&last_date) = dates.last() else {
+        return Ok(0);
+    };
+
+    crate::transaction(conn, |conn| {
+        let claim = recurring_txs::last_date.eq(last_date);
+        let claimed = match template.last_date {
+            Some(loaded) => diesel::update(
+                recurring_txs::table
+                    .find(template.id)
+                    .filter(recurring_txs::last_date.eq(loaded)),
+            )
+            .set(claim)
+            .execute(conn)?,
+            None => diesel::update(
+                recurring_txs::table
+                    .find(template.id)
+                    .filter(recurring_txs::last_date.is_null()),
+            )
+            .set(claim)
+            .execute(conn)?,
+        };
+        if claimed == 0 {
+            return Ok(0);
+        }
+
+        let dates = match closed_before(conn, template.ledger_id)? {
+            Some(closed) => dates.into_iter().filter(|date| *date >= closed).collect(),
+            None => dates,
+        };
+
+        let template_credits = recurring_credits::table
+            .filter(recurring_credits::recurring_tx_id.eq(template.id))
+            .select((
+                recurring_credits::account,
+                recurring_credits::currency,
+                recurring_credits::value,
+            ))
+            .load::<TxItem>(conn)?;
+        let template_debits = recurring_debits::table
+            .filter(recurring_debits::recurring_tx_id.eq(template.id))
+            .select((
+                recurring_debits::account,
+                recurring_debits::currency,
+                recurring_debits::value,
+            ))
+            .load::<TxItem>(conn)?;
+        let postings = |items: &[TxItem]| {
+            items
+                .iter()
+                .map(|item| {
+                    (
+                        (item.account.clone(), item.currency.clone()),
+                        item.value.clone(),
+                    )
+                })
+                .collect::<Postings>()
+        };
+
+        let mut ledger = Ledger::new(conn, template.ledger_id);
+        for date in &dates {
+            let transaction = Transaction::new(
+                date.and_hms_opt(0, 0, 0).unwrap(),
+                &template.description,
+                "",
+                postings(&template_debits),
+                postings(&template_credits),
+            )?;
+            ledger.insert_recurring_transaction(actor, template.id, &transaction)?;
+        }
+
+        Ok(dates.len())
+    })
+}
+
+/// The outcome of `materialize_all`
+#[derive(Debug, Default)]
+pub struct Materialized {
+    /// Number of transactions created
+    pub created: usize,
+    /// The recurring transactions that failed, by id, with their errors
+    pub failed: Vec<(i32, LedgerError)>,
+}
+
+/// Materializes the due occurrences of all recurring transactions in all
+/// ledgers. One that fails is left for the next run, and does not keep the
+/// others from being materialized.
+pub fn materialize_all(
+    conn: &mut DbConnection,
+    today: NaiveDate,
+    actor: Actor,
+) -> QueryResult<Materialized> {
+    let templates = recurring_txs::table
+        .order(recurring_txs::id)
+        .load::<RecurringTx>(conn)?;
+
+    let mut materialized = Materialized::default();
+    for template in &templates {
+        match materialize(conn, template, today, actor) {
+            Ok(created) => materialized.created += created,
+            Err(err) => materialized.failed.push((template.id, err)),
+        }
+    }
+    Ok(materialized)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{NewRecurringCredit, NewRecurringDebit, NewRecurringTx};
+    use crate::rational::Rational;
+    use crate::schedule::Schedule;
+    use crate::schema::{credits, txs};
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+    }
+
+    /// Adds a monthly recurring transaction from January until `end_date`,
+    /// which debits `us` and credits `landlord`
+    fn add_template(
+        conn: &mut DbConnection,
+        end_date: NaiveDate,
+        debit: u32,
+        credit: u32,
+    ) -> QueryResult<i32> {
+        let id = diesel::insert_into(recurring_txs::table)
+            .values(&NewRecurringTx {
+                ledger_id: 1,
+                description: "Rent",
+                schedule: Schedule::Monthly { day: 1 },
+                start_date: date(1, 1),
+                end_date: Some(end_date),
+            })
+            .returning(recurring_txs::id)
+            .get_result::<i32>(conn)?;
+        diesel::insert_into(recurring_credits::table)
+            .values(&NewRecurringCredit {
+                recurring_tx_id: id,
+                account: "landlord",
+                currency: "NOK",
+                value: Rational::new(credit, 1u32),
+            })
+            .execute(conn)?;
+        diesel::insert_into(recurring_debits::table)
+            .values(&NewRecurringDebit {
+                recurring_tx_id: id,
+                account: "us",
+                currency: "NOK",
+                value: Rational::new(debit, 1u32),
+            })
+            .execute(conn)?;
+        Ok(id)
+    }
+
+    #[test]
+    fn materializes_each_occurrence_once() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        let id = add_template(&mut conn, date(3, 31), 1000, 1000)?;
+
+        let count = |conn: &mut DbConnection| {
+            txs::table
+                .filter(txs::recurring_tx_id.eq(id))
+                .count()
+                .get_result::<i64>(conn)
+        };
+
+        assert_eq!(
+            materialize_all(&mut conn, date(2, 15), Actor::scheduler())?.created,
+            2
+        );
+        assert_eq!(
+            materialize_all(&mut conn, date(2, 15), Actor::scheduler())?.created,
+            0
+        );
+        assert_eq!(count(&mut conn)?, 2);
+
+        // A template loaded before the run above has nothing left to claim
+        let stale = RecurringTx {
+            last_date: None,
+            ..recurring_txs::table
+                .find(id)
+                .first::<RecurringTx>(&mut conn)?
+        };
+        assert_eq!(
+            materialize(&mut conn, &stale, date(2, 15), Actor::scheduler())?,
+            0
+        );
+
+        // Nothing after the end date
+        assert_eq!(
+            materialize_all(&mut conn, date(6, 1), Actor::scheduler())?.created,
+            1
+        );
+        assert_eq!(count(&mut conn)?, 3);
+
+        let postings = credits::table
+            .inner_join(txs::table)
+            .filter(txs::recurring_tx_id.eq(id))
+            .count()
+            .get_result::<i64>(&mut conn)?;
+        assert_eq!(postings, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn failing_templates_do_not_hold_back_the_others() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        // Changed in the database behind the back of the application
+        let unbalanced = add_template(&mut conn, date(3, 31), 1000, 900)?;
+        let rent = add_template(&mut conn, date(3, 31), 1000, 1000)?;
+
+        let materialized = materialize_all(&mut conn, date(2, 15), Actor::scheduler())?;
+        assert_eq!(materialized.created, 2);
+        assert_eq!(
+            materialized
+                .failed
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            vec![unbalanced]
+        );
+
+        // The failed one is tried again on the next run
+        let last_date = |conn: &mut DbConnection, id| {
+            recurring_txs::table
+                .find(id)
+                .select(recurring_txs::last_date)
+                .first::<Option<NaiveDate>>(conn)
+        };
+        assert_eq!(last_date(&mut conn, unbalanced)?, None);
+        assert_eq!(last_date(&mut conn, rent)?, Some(date(2, 1)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+//! Schedules of recurring transactions, written like "monthly 15",
+//! "weekly fri" or "every 2 weeks"
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use diesel::sql_types::Text;
+use diesel::{AsExpression, FromSqlRow};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Days,
+    Weeks,
+    Months,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum Schedule {
+    /// On the given day of every month, or the last day of months that are
+    /// shorter
+    Monthly { day: u32 },
+    /// On the given day of every week
+    Weekly { weekday: Weekday },
+    /// Every `n` days, weeks or months, counted from the start date
+    Every { n: u32, unit: Unit },
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error(
+    "invalid schedule, use \"monthly DAY\", \"weekly WEEKDAY\" or \"every N days|weeks|months\""
+)]
+pub struct InvalidSchedule;
+
+impl std::str::FromStr for Schedule {
+    type Err = InvalidSchedule;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["monthly", day] => match day.parse() {
+                Ok(day @ 1..=31) => Ok(Schedule::Monthly { day }),
+                _ => Err(InvalidSchedule),
+            },
+            ["weekly", weekday] => Ok(Schedule::Weekly {
+                weekday: weekday.parse().map_err(|_| InvalidSchedule)?,
+            }),
+            ["every", n, unit] => {
+                let n = match n.parse() {
+                    Ok(n @ 1..) => n,
+                    _ => return Err(InvalidSchedule),
+                };
+                let unit = match unit {
+                    "day" | "days" => Unit::Days,
+                    "week" | "weeks" => Unit::Weeks,
+                    "month" | "months" => Unit::Months,
+                    _ => return Err(InvalidSchedule),
+                };
+                Ok(Schedule::Every { n, unit })
+            }
+            _ => Err(InvalidSchedule),
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Monthly { day } => write!(f, "monthly {day}"),
+            Schedule::Weekly { weekday } => {
+                write!(f, "weekly {}", weekday.to_string().to_lowercase())
+            }
+            Schedule::Every { n, unit } => {
+                let unit = match unit {
+                    Unit::Days => "days",
+                    Unit::Weeks => "weeks",
+                    Unit::Months => "months",
+                };
+                write!(f, "every {n} {unit}")
+            }
+        }
+    }
+}
+
+text_sql!(Schedule);
+
+/// The given day of the month `months` after the month of `date`, or the last
+/// day of that month if it is shorter
+fn add_months(date: NaiveDate, months: u32, day: u32) -> Option<NaiveDate> {
+    let month0 = date.month0() + months;
+    let year = date.year() + i32::try_from(month0 / 12).ok()?;
+    let month = month0 % 12 + 1;
+    (day.min(28)..=day)
+        .rev()
+        .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+}
+
+impl Schedule {
+    /// The first occurrence on or after `start`
+    fn first(&self, start: NaiveDate) -> Option<NaiveDate> {
+        match *self {
+            Schedule::Monthly { day } => {
+                let first = add_months(start, 0, day)?;
+                if first < start {
+                    add_months(start, 1, day)
+                } else {
+                    Some(first)
+                }
+            }
+            Schedule::Weekly { weekday } => {
+                let days = (7 + weekday.num_days_from_monday()
+                    - start.weekday().num_days_from_monday())
+                    % 7;
+                start.checked_add_signed(Duration::days(days.into()))
+            }
+            Schedule::Every { .. } => Some(start),
+        }
+    }
+
+    /// The `k`th occurrence, counting from 0, of a schedule whose first
+    /// occurrence is `first`. Each one is computed from the first, so short
+    /// months do not move later occurrences.
+    fn nth(&self, first: NaiveDate, k: u32) -> Option<NaiveDate> {
+        match *self {
+            Schedule::Monthly { day } => add_months(first, k, day),
+            Schedule::Weekly { .. } => first.checked_add_signed(Duration::weeks(k.into())),
+            Schedule::Every { n, unit } => {
+                let steps = n.checked_mul(k)?;
+                match unit {
+                    Unit::Days => first.checked_add_signed(Duration::days(steps.into())),
+                    Unit::Weeks => first.checked_add_signed(Duration::weeks(steps.into())),
+                    Unit::Months => add_months(first, steps, first.day()),
+                }
+            }
+        }
+    }
+
+    /// All occurrences from `start` up to and including `until`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chrono::NaiveDate;
+    /// # use sharebill::schedule::Schedule;
+    /// let date = |m, d| NaiveDate::from_ymd_opt(2026, m, d).unwrap();
+    /// let schedule: Schedule = "monthly 31".parse().unwrap();
+    /// assert_eq!(
+    ///     schedule.occurrences(date(1, 15), date(4, 30)),
+    ///     vec![date(1, 31), date(2, 28), date(3, 31), date(4, 30)]
+    /// );
+    /// ```
+    pub fn occurrences(&self, start: NaiveDate, until: NaiveDate) -> Vec<NaiveDate> {
+        self.iter(start).take_while(|date| *date <= until).collect()
+    }
+
+    /// The first occurrence from `start` that comes after `after`, or the very
+    /// first one if `after` is None
+    pub fn next_after(&self, start: NaiveDate, after: Option<NaiveDate>) -> Option<NaiveDate> {
+        self.iter(start).find(|date| Some(*date) > after)
+    }
+
+    fn iter(&self, start: NaiveDate) -> impl Iterator<Item = NaiveDate> + '_ {
+        let first = self.first(start);
+        (0..).map_while(move |k| self.nth(first?, k))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in [
+            "monthly 1",
+            "weekly fri",
+            "every 2 weeks",
+            "every 10 days",
+            "every 3 months",
+        ] {
+            assert_eq!(s.parse::<Schedule>().unwrap().to_string(), s);
+        }
+        assert_eq!(
+            "every 1 month".parse(),
+            Ok(Schedule::Every {
+                n: 1,
+                unit: Unit::Months
+            })
+        );
+        assert_eq!("monthly 0".parse::<Schedule>(), Err(InvalidSchedule));
+        assert_eq!("monthly 32".parse::<Schedule>(), Err(InvalidSchedule));
+        assert_eq!("weekly someday".parse::<Schedule>(), Err(InvalidSchedule));
+        assert_eq!("every 0 days".parse::<Schedule>(), Err(InvalidSchedule));
+        assert_eq!("daily".parse::<Schedule>(), Err(InvalidSchedule));
+    }
+
+    #[test]
+    fn monthly() {
+        let schedule = Schedule::Monthly { day: 5 };
+        assert_eq!(
+            schedule.occurrences(date(2026, 11, 5), date(2027, 1, 31)),
+            vec![date(2026, 11, 5), date(2026, 12, 5), date(2027, 1, 5)]
+        );
+        assert_eq!(
+            schedule.occurrences(date(2026, 11, 6), date(2027, 1, 4)),
+            vec![date(2026, 12, 5)]
+        );
+        assert_eq!(
+            schedule.occurrences(date(2026, 11, 6), date(2026, 11, 30)),
+            vec![]
+        );
+
+        assert_eq!(
+            schedule.next_after(date(2026, 11, 6), None),
+            Some(date(2026, 12, 5))
+        );
+        assert_eq!(
+            schedule.next_after(date(2026, 11, 6), Some(date(2026, 12, 5))),
+            Some(date(2027, 1, 5))
+        );
+    }
+
+    #[test]
+    fn weekly_and_every() {
+        // 2026-10-18 is a Sunday
+        let schedule = Schedule::Weekly {
+            weekday: Weekday::Mon,
+        };
+        assert_eq!(
+            schedule.occurrences(date(2026, 10, 18), date(2026, 11, 2)),
+            vec![date(2026, 10, 19), date(2026, 10, 26), date(2026, 11, 2)]
+        );
+
+        let schedule = Schedule::Every {
+            n: 2,
+            unit: Unit::Weeks,
+        };
+        assert_eq!(
+            schedule.occurrences(date(2026, 10, 18), date(2026, 11, 14)),
+            vec![date(2026, 10, 18), date(2026, 11, 1)]
+        );
+
+        let schedule = Schedule::Every {
+            n: 1,
+            unit: Unit::Months,
+        };
+        assert_eq!(
+            schedule.occurrences(date(2027, 1, 30), date(2027, 3, 30)),
+            vec![date(2027, 1, 30), date(2027, 2, 28), date(2027, 3, 30)]
+        );
+    }
+}
@@ -1,29 +1,297 @@
 diesel::table! {
-    credits (tx_id, account) {
+    api_token_ledgers (token_id, ledger_id) {
+        token_id -> Integer,
+        ledger_id -> Integer,
+        access -> Text,
+    }
+}
+
+diesel::table! {
+    api_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        token_hash -> Binary,
+        created_time -> Timestamp,
+        last_used_time -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    attachments (id) {
+        id -> Integer,
+        tx_id -> Integer,
+        filename -> Text,
+        content_type -> Text,
+        size -> Integer,
+        data -> Binary,
+        created_time -> Timestamp,
+        user_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Integer,
+        time -> Timestamp,
+        ledger_id -> Nullable<Integer>,
+        user_id -> Nullable<Integer>,
+        source -> Text,
+        action -> Text,
+        object -> Text,
+        payload -> Text,
+    }
+}
+
+diesel::table! {
+    budgets (id) {
+        id -> Integer,
+        ledger_id -> Integer,
+        target -> Text,
+        name -> Text,
+        currency -> Text,
+        amount -> Binary,
+        period -> Text,
+    }
+}
+
+diesel::table! {
+    closings (id) {
+        id -> Integer,
+        ledger_id -> Integer,
+        closed_before -> Date,
+        created_time -> Timestamp,
+        user_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    credits (tx_id, account, currency) {
         tx_id -> Integer,
         account -> Text,
+        currency -> Text,
         value -> Binary,
     }
 }
 
 diesel::table! {
-    debits (tx_id, account) {
+    debits (tx_id, account, currency) {
         tx_id -> Integer,
         account -> Text,
+        currency -> Text,
         value -> Binary,
     }
 }
 
+diesel::table! {
+    exchange_rates (ledger_id, currency, date) {
+        ledger_id -> Integer,
+        currency -> Text,
+        date -> Date,
+        rate -> Binary,
+    }
+}
+
+diesel::table! {
+    ledger_members (ledger_id, user_id) {
+        ledger_id -> Integer,
+        user_id -> Integer,
+        role -> Text,
+    }
+}
+
+diesel::table! {
+    ledgers (id) {
+        id -> Integer,
+        slug -> Text,
+        name -> Text,
+        decimals -> Integer,
+        decimal_separator -> Text,
+        thousands_separator -> Text,
+        currency_symbol -> Text,
+        currency_before -> Bool,
+        base_currency -> Text,
+    }
+}
+
+diesel::table! {
+    no_unbalanced_txs (id) {
+        id -> Integer,
+    }
+}
+
+diesel::table! {
+    post_template_credits (post_template_id, account) {
+        post_template_id -> Integer,
+        account -> Text,
+        share -> Binary,
+    }
+}
+
+diesel::table! {
+    post_template_debits (post_template_id, account) {
+        post_template_id -> Integer,
+        account -> Text,
+        share -> Binary,
+    }
+}
+
+diesel::table! {
+    post_templates (id) {
+        id -> Integer,
+        ledger_id -> Integer,
+        name -> Text,
+        description -> Text,
+    }
+}
+
+diesel::table! {
+    receipt_item_consumers (item_id, account) {
+        item_id -> Integer,
+        account -> Text,
+    }
+}
+
+diesel::table! {
+    receipt_items (id) {
+        id -> Integer,
+        tx_id -> Integer,
+        position -> Integer,
+        description -> Text,
+        value -> Binary,
+        shared -> Bool,
+    }
+}
+
+diesel::table! {
+    recurring_credits (recurring_tx_id, account, currency) {
+        recurring_tx_id -> Integer,
+        account -> Text,
+        currency -> Text,
+        value -> Binary,
+    }
+}
+
+diesel::table! {
+    recurring_debits (recurring_tx_id, account, currency) {
+        recurring_tx_id -> Integer,
+        account -> Text,
+        currency -> Text,
+        value -> Binary,
+    }
+}
+
+diesel::table! {
+    recurring_txs (id) {
+        id -> Integer,
+        ledger_id -> Integer,
+        description -> Text,
+        schedule -> Text,
+        start_date -> Date,
+        end_date -> Nullable<Date>,
+        last_date -> Nullable<Date>,
+    }
+}
+
+diesel::table! {
+    tx_tags (tx_id, tag) {
+        tx_id -> Integer,
+        tag -> Text,
+    }
+}
+
 diesel::table! {
     txs (id) {
         id -> Integer,
+        ledger_id -> Integer,
         tx_time -> Timestamp,
         rev_time -> Timestamp,
         description -> Text,
+        rev_user_id -> Nullable<Integer>,
+        recurring_tx_id -> Nullable<Integer>,
+        closing_id -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    sessions (token_hash) {
+        token_hash -> Binary,
+        user_id -> Integer,
+        created_time -> Timestamp,
+        expiry_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    unbalanced_txs (tx_id) {
+        tx_id -> Integer,
     }
 }
 
+diesel::table! {
+    users (id) {
+        id -> Integer,
+        username -> Text,
+        password_hash -> Text,
+    }
+}
+
+diesel::joinable!(api_token_ledgers -> api_tokens (token_id));
+diesel::joinable!(api_token_ledgers -> ledgers (ledger_id));
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(attachments -> txs (tx_id));
+diesel::joinable!(attachments -> users (user_id));
+diesel::joinable!(audit_log -> ledgers (ledger_id));
+diesel::joinable!(audit_log -> users (user_id));
+diesel::joinable!(budgets -> ledgers (ledger_id));
+diesel::joinable!(closings -> ledgers (ledger_id));
+diesel::joinable!(closings -> users (user_id));
 diesel::joinable!(credits -> txs (tx_id));
 diesel::joinable!(debits -> txs (tx_id));
+diesel::joinable!(exchange_rates -> ledgers (ledger_id));
+diesel::joinable!(ledger_members -> ledgers (ledger_id));
+diesel::joinable!(ledger_members -> users (user_id));
+diesel::joinable!(post_template_credits -> post_templates (post_template_id));
+diesel::joinable!(post_template_debits -> post_templates (post_template_id));
+diesel::joinable!(post_templates -> ledgers (ledger_id));
+diesel::joinable!(receipt_item_consumers -> receipt_items (item_id));
+diesel::joinable!(receipt_items -> txs (tx_id));
+diesel::joinable!(recurring_credits -> recurring_txs (recurring_tx_id));
+diesel::joinable!(recurring_debits -> recurring_txs (recurring_tx_id));
+diesel::joinable!(recurring_txs -> ledgers (ledger_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(tx_tags -> txs (tx_id));
+diesel::joinable!(txs -> closings (closing_id));
+diesel::joinable!(txs -> ledgers (ledger_id));
+diesel::joinable!(txs -> recurring_txs (recurring_tx_id));
+diesel::joinable!(txs -> users (rev_user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_token_ledgers,
+    api_tokens,
+    attachments,
+    audit_log,
+    budgets,
+    closings,
+    credits,
+    debits,
+    exchange_rates,
+    ledger_members,
+    ledgers,
+    no_unbalanced_txs,
+    post_template_credits,
+    post_template_debits,
+    post_templates,
+    receipt_item_consumers,
+    receipt_items,
+    recurring_credits,
+    recurring_debits,
+    recurring_txs,
+    sessions,
+    tx_tags,
+    txs,
+    unbalanced_txs,
+    users,
+);
 
-diesel::allow_tables_to_appear_in_same_query!(credits, debits, txs,);
+diesel::allow_columns_to_appear_in_same_group_by_clause!(tx_tags::tag, debits::currency);
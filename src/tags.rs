@@ -0,0 +1,203 @@
+//! Tags, or categories, of transactions and reports of spending per tag
+
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use num::BigRational;
+use thiserror::Error;
+
+use crate::rational::{sum_rat, Rational};
+use crate::schema::{debits, tx_tags, txs};
+use crate::DbConnection;
+
+pub const MAX_TAG_LENGTH: usize = 40;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("tags cannot be longer than 40 characters or contain control characters")]
+pub struct InvalidTag;
+
+/// Parses a comma separated list of tags. Tags are lowercased, and the result
+/// is sorted with duplicates removed.
+///
+/// # Examples
+///
+/// ```
+/// # use sharebill::tags::parse_tags;
+/// assert_eq!(parse_tags("Groceries, party,,groceries "), Ok(vec!["groceries".to_owned(), "party".to_owned()]));
+/// assert_eq!(parse_tags(""), Ok(vec![]));
+/// ```
+pub fn parse_tags(input: &str) -> Result<Vec<String>, InvalidTag> {
+    let mut tags = input
+        .split(',')
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| {
+            if tag.chars().count() > MAX_TAG_LENGTH || tag.chars().any(char::is_control) {
+                Err(InvalidTag)
+            } else {
+                Ok(tag)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    tags.sort_unstable();
+    tags.dedup();
+    Ok(tags)
+}
+
+/// The tags of a transaction, sorted
+pub fn tx_tags(conn: &mut DbConnection, tx_id: i32) -> QueryResult<Vec<String>> {
+    tx_tags::table
+        .filter(tx_tags::tx_id.eq(tx_id))
+        .order(tx_tags::tag)
+        .select(tx_tags::tag)
+        .load(conn)
+}
+
+/// Replaces the tags of a transaction
+pub fn set_tx_tags(conn: &mut DbConnection, tx_id: i32, tags: &[String]) -> QueryResult<()> {
+    diesel::delete(tx_tags::table.filter(tx_tags::tx_id.eq(tx_id))).execute(conn)?;
+    diesel::insert_into(tx_tags::table)
+        .values(
+            tags.iter()
+                .map(|tag| (tx_tags::tx_id.eq(tx_id), tx_tags::tag.eq(tag)))
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)?;
+    Ok(())
+}
+
+/// All tags in use in a ledger, sorted
+pub fn ledger_tags(conn: &mut DbConnection, ledger_id: i32) -> QueryResult<Vec<String>> {
+    tx_tags::table
+        .inner_join(txs::table)
+        .filter(txs::ledger_id.eq(ledger_id))
+        .select(tx_tags::tag)
+        .distinct()
+        .order(tx_tags::tag)
+        .load(conn)
+}
+
+/// Spending in a period, which is the sum of the debits
+pub struct Report {
+    /// Sums by tag and currency. A transaction with several tags counts
+    /// towards each of them, and ones without tags are under `None`.
+    pub by_tag: Vec<(Option<String>, String, BigRational)>,
+    /// Sums by account and currency
+    pub by_account: Vec<(String, String, BigRational)>,
+}
+
+/// Spending in the transactions of a ledger from `from` up to and including
+/// `to`. The posts that carry balances over a closing are not spending.
+pub fn expense_report(
+    conn: &mut DbConnection,
+    ledger_id: i32,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> QueryResult<Report> {
+    let start = from.and_hms_opt(0, 0, 0).unwrap();
+    let end = to.succ_opt().unwrap_or(to).and_hms_opt(0, 0, 0).unwrap();
+
+    let by_tag = debits::table
+        .inner_join(txs::table.left_join(tx_tags::table))
+        .filter(txs::ledger_id.eq(ledger_id))
+        .filter(txs::tx_time.ge(start))
+        .filter(txs::tx_time.lt(end))
+        .filter(txs::closing_id.is_null())
+        .group_by((tx_tags::tag, debits::currency))
+        .select((
+            tx_tags::tag.nullable(),
+            debits::currency,
+            sum_rat(debits::value),
+        ))
+        // Untagged first, as databases differ in where they sort nulls
+        .order((tx_tags::tag.is_not_null(), tx_tags::tag, debits::currency))
+        .load::<(Option<String>, String, Rational)>(conn)?;
+
+    let by_account = debits::table
+        .inner_join(txs::table)
+        .filter(txs::ledger_id.eq(ledger_id))
+        .filter(txs::tx_time.ge(start))
+        .filter(txs::tx_time.lt(end))
+        .filter(txs::closing_id.is_null())
+        .group_by((debits::account, debits::currency))
+        .select((debits::account, debits::currency, sum_rat(debits::value)))
+        .order((debits::account, debits::currency))
+        .load::<(String, String, Rational)>(conn)?;
+
+    Ok(Report {
+        by_tag: by_tag
+            .into_iter()
+            .map(|(tag, currency, sum)| (tag, currency, sum.to_big_rational()))
+            .collect(),
+        by_account: by_account
+            .into_iter()
+            .map(|(account, currency, sum)| (account, currency, sum.to_big_rational()))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audit::Actor;
+    use crate::ledger::{Ledger, Postings, Transaction};
+
+    #[test]
+    fn invalid_tags() {
+        assert_eq!(
+            parse_tags(&"x".repeat(MAX_TAG_LENGTH)).map(|t| t.len()),
+            Ok(1)
+        );
+        assert_eq!(parse_tags(&"x".repeat(MAX_TAG_LENGTH + 1)), Err(InvalidTag));
+        assert_eq!(parse_tags("a\tb"), Err(InvalidTag));
+    }
+
+    #[test]
+    fn sums_debits_per_tag_and_account() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = crate::test_connection();
+        let date = |m, d| NaiveDate::from_ymd_opt(2026, m, d).unwrap();
+
+        let mut add = |when: NaiveDate, debits: &[(&str, u32)], tags: &str| {
+            let total = debits.iter().map(|(_, value)| *value).sum::<u32>();
+            let transaction = Transaction::new(
+                when.and_hms_opt(12, 0, 0).unwrap(),
+                "test",
+                tags,
+                debits
+                    .iter()
+                    .map(|(account, value)| {
+                        ((account.to_string(), "NOK".to_owned()), (*value).into())
+                    })
+                    .collect(),
+                Postings::from([(("bank".to_owned(), "NOK".to_owned()), total.into())]),
+            )?;
+            Ledger::new(&mut conn, 1).insert_transaction(Actor::cli(), &transaction)
+        };
+
+        add(date(1, 10), &[("a", 10), ("b", 20)], "groceries")?;
+        add(date(2, 10), &[("a", 5)], "groceries, party")?;
+        add(date(3, 31), &[("b", 7)], "")?;
+        add(date(4, 1), &[("b", 100)], "groceries")?;
+
+        let r = |n: i64| BigRational::from_integer(n.into());
+        let report = expense_report(&mut conn, 1, date(1, 1), date(3, 31))?;
+        assert_eq!(
+            report.by_tag,
+            vec![
+                (None, "NOK".to_owned(), r(7)),
+                (Some("groceries".to_owned()), "NOK".to_owned(), r(35)),
+                (Some("party".to_owned()), "NOK".to_owned(), r(5)),
+            ]
+        );
+        assert_eq!(
+            report.by_account,
+            vec![
+                ("a".to_owned(), "NOK".to_owned(), r(15)),
+                ("b".to_owned(), "NOK".to_owned(), r(27)),
+            ]
+        );
+
+        assert_eq!(ledger_tags(&mut conn, 1)?, vec!["groceries", "party"]);
+
+        Ok(())
+    }
+}